@@ -1,25 +1,103 @@
+// pyo3 0.19's `#[new]` expansion defines impls inside a function body.
+#![allow(non_local_definitions)]
+
 use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
     mem::transmute,
-    sync::{Arc, RwLock},
+    ops::Bound,
+    os::raw::{c_int, c_long, c_void},
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak},
 };
 
 use ::automerge::{
     self as am, transaction::Transactable, ChangeHash, ObjType, Prop, ReadDoc, ScalarValue,
 };
 use am::{
-    marks::{ExpandMark, Mark},
+    marks::{ExpandMark, Mark, MarkSet, UpdateSpansConfig},
     sync::SyncDoc,
-    ActorId,
+    transaction::CommitOptions,
+    ActorId, LoadOptions, PatchLog, TextEncoding,
 };
 use pyo3::{
-    exceptions::PyException,
+    basic::CompareOp,
+    create_exception,
+    exceptions::{
+        PyDeprecationWarning, PyException, PyIndexError, PyKeyError, PyTypeError, PyValueError,
+    },
+    ffi,
     prelude::*,
-    types::{PyBytes, PyDateTime},
+    types::{
+        timezone_utc, PyBool, PyByteArray, PyBytes, PyDate, PyDateTime, PyDict, PyFloat, PyList,
+        PyLong, PySlice, PySliceIndices, PyString, PyTuple,
+    },
+    AsPyPointer,
 };
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use unicode_segmentation::UnicodeSegmentation;
 
 struct Inner {
     doc: am::Automerge,
     tx: Option<am::transaction::Transaction<'static>>,
+    /// The heads as of the last save, used by `save_incremental`.
+    saved_heads: Vec<ChangeHash>,
+    /// Called with the patches made by changes from other documents.
+    on_patch: Option<PyObject>,
+    /// The heads as of the last call to `diff_incremental`.
+    diff_heads: Vec<ChangeHash>,
+    /// Local changes which `undo` can revert, most recent last.
+    undo_stack: Vec<ChangeHash>,
+    /// Changes made by `undo` which `redo` can revert, most recent last.
+    redo_stack: Vec<ChangeHash>,
+    /// The most entries kept on `undo_stack`.
+    undo_depth: usize,
+    /// See `revert_change`. Each entry also records the `max_op` of the revert which made it.
+    replaced: HashMap<Vec<u8>, (am::Cursor, u64)>,
+    /// How timestamps read from the document are returned.
+    timestamps: PyTimestampMode,
+    /// Incremented whenever a transaction is started, so that a `Transaction` (or a proxy obtained
+    /// from one) can tell whether the open transaction is still the one it started.
+    tx_generation: u64,
+    /// Buffers handed out by `get_bytes(..., zero_copy=True)`, by the ID of the op which set the
+    /// value, so that reading the same value again shares the buffer rather than copying it.
+    bytes_cache: Mutex<HashMap<am::ObjId, Weak<[u8]>>>,
+}
+
+const DEFAULT_UNDO_DEPTH: usize = 100;
+
+/// How many rounds `Document.sync_with` runs before giving up on two documents that won't converge.
+const MAX_SYNC_ROUNDS: usize = 100;
+
+/// An `on_patch` callback and the patches to call it with, once the document lock is released.
+type Observed = Option<(PyObject, Vec<PyPatch>)>;
+
+fn notify(py: Python<'_>, observed: Observed) -> PyResult<()> {
+    if let Some((callback, patches)) = observed {
+        callback.call1(py, (patches,))?;
+    }
+    Ok(())
+}
+
+/// Write-lock two distinct documents. The locks are always taken in address order so that two
+/// threads working on the same pair from opposite ends can't deadlock; the guards are returned in
+/// argument order.
+fn write_both<'a>(
+    a: &'a Arc<RwLock<Inner>>,
+    b: &'a Arc<RwLock<Inner>>,
+) -> PyResult<(RwLockWriteGuard<'a, Inner>, RwLockWriteGuard<'a, Inner>)> {
+    let lock = |inner: &'a Arc<RwLock<Inner>>| {
+        inner
+            .write()
+            .map_err(|e| PyException::new_err(e.to_string()))
+    };
+    if Arc::as_ptr(a) < Arc::as_ptr(b) {
+        let a = lock(a)?;
+        Ok((a, lock(b)?))
+    } else {
+        let b = lock(b)?;
+        Ok((lock(a)?, b))
+    }
 }
 
 fn get_heads(heads: Option<Vec<PyChangeHash>>) -> Option<Vec<ChangeHash>> {
@@ -28,7 +106,141 @@ fn get_heads(heads: Option<Vec<PyChangeHash>>) -> Option<Vec<ChangeHash>> {
 
 impl Inner {
     fn new(doc: am::Automerge) -> Self {
-        Self { doc, tx: None }
+        Self {
+            doc,
+            tx: None,
+            saved_heads: Vec::new(),
+            on_patch: None,
+            diff_heads: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            undo_depth: DEFAULT_UNDO_DEPTH,
+            replaced: HashMap::new(),
+            timestamps: PyTimestampMode::Naive,
+            tx_generation: 0,
+            bytes_cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The open transaction, if it's the one started as `generation`.
+    fn active_tx(
+        &mut self,
+        generation: u64,
+    ) -> PyResult<&mut am::transaction::Transaction<'static>> {
+        match self.tx.as_mut() {
+            Some(tx) if self.tx_generation == generation => Ok(tx),
+            _ => Err(PyException::new_err("transaction no longer active")),
+        }
+    }
+
+    /// Fail if any of `heads` is not a change in this document.
+    fn check_known(&self, heads: &[ChangeHash]) -> PyResult<()> {
+        match heads
+            .iter()
+            .find(|h| self.doc.get_change_by_hash(h).is_none())
+        {
+            Some(missing) => Err(PyException::new_err(format!("unknown heads: {}", missing))),
+            None => Ok(()),
+        }
+    }
+
+    /// Close the open transaction, if it's the one started as `generation`.
+    fn take_tx(&mut self, generation: u64) -> PyResult<am::transaction::Transaction<'static>> {
+        if self.tx_generation != generation {
+            return Err(PyException::new_err("transaction no longer active"));
+        }
+        self.tx
+            .take()
+            .ok_or_else(|| PyException::new_err("transaction no longer active"))
+    }
+
+    /// Remember a change committed by a local transaction so that it can be undone. A new local
+    /// change means there is nothing left to redo.
+    fn record_local(&mut self, hash: Option<ChangeHash>) {
+        if let Some(hash) = hash {
+            self.redo_stack.clear();
+            self.push_undo(hash);
+        }
+    }
+
+    fn push_undo(&mut self, hash: ChangeHash) {
+        self.undo_stack.push(hash);
+        self.trim_history();
+    }
+
+    /// Drop the oldest undo and redo entries beyond `undo_depth`, and any `replaced` entries which
+    /// no remaining entry can need. A replacement is only followed from elements of a change made
+    /// before the revert that recorded it, and changes made one after another on this document
+    /// have increasing `max_op`s.
+    fn trim_history(&mut self) {
+        for stack in [&mut self.undo_stack, &mut self.redo_stack] {
+            let excess = stack.len().saturating_sub(self.undo_depth);
+            stack.drain(..excess);
+        }
+        let oldest = self
+            .undo_stack
+            .iter()
+            .chain(&self.redo_stack)
+            .filter_map(|hash| self.doc.get_change_by_hash(hash))
+            .map(|change| change.max_op())
+            .min();
+        match oldest {
+            Some(oldest) => self.replaced.retain(|_, (_, made)| *made > oldest),
+            None => self.replaced.clear(),
+        }
+    }
+
+    /// A patch log which is only active if there is an `on_patch` callback to report to.
+    fn patch_log(&self) -> PatchLog {
+        PatchLog::new(self.on_patch.is_some())
+    }
+
+    /// Turn the patches in `log` into the arguments for the `on_patch` callback, if there is one.
+    /// The callback is run by `notify` once the document is unlocked so that it can read it. This
+    /// doesn't need the GIL, so it can be called from inside `allow_threads`.
+    fn observed(&self, log: &mut PatchLog) -> Observed {
+        let callback = self.on_patch.clone()?;
+        let patches = self.doc.make_patches(log);
+        if patches.is_empty() {
+            return None;
+        }
+        Some((callback, self.py_patches(patches)))
+    }
+
+    /// A patch log which is active if there is an `on_patch` callback or if the caller wants the
+    /// patches back.
+    fn patch_log_returning(&self, return_patches: bool) -> PatchLog {
+        if return_patches {
+            PatchLog::active()
+        } else {
+            self.patch_log()
+        }
+    }
+
+    /// Like `observed`, but for a log from `patch_log_returning`: also returns the patches if the
+    /// caller asked for them.
+    fn observed_returning(
+        &self,
+        log: &mut PatchLog,
+        return_patches: bool,
+    ) -> (Option<Vec<PyPatch>>, Observed) {
+        if !return_patches {
+            return (None, self.observed(log));
+        }
+        let patches = self.py_patches(self.doc.make_patches(log));
+        let observed = match &self.on_patch {
+            Some(callback) if !patches.is_empty() => Some((callback.clone(), patches.clone())),
+            _ => None,
+        };
+        (Some(patches), observed)
+    }
+
+    /// Wrap patches for Python, reading their values according to the document's settings.
+    fn py_patches(&self, patches: Vec<am::Patch>) -> Vec<PyPatch> {
+        patches
+            .into_iter()
+            .map(|patch| PyPatch(patch, self.timestamps))
+            .collect()
     }
 
     // Read methods go on Inner as they're callable from either Transaction or Document.
@@ -60,42 +272,283 @@ impl Inner {
             }
         }
         .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(res.map(|(v, id)| (PyValue(v.into_owned()), PyObjId(id))))
+        Ok(res.map(|(v, id)| (PyValue(v.into_owned(), self.timestamps), PyObjId(id))))
+    }
+
+    /// The prop `key` refers to in `obj_id`, or None for a negative index reaching past the start.
+    fn resolve_key(
+        &self,
+        obj_id: &PyObjId,
+        key: PyKey,
+        heads: &Option<Vec<PyChangeHash>>,
+    ) -> Option<PyProp> {
+        let index = match key {
+            PyKey::Key(key) => return Some(PyProp(Prop::Map(key))),
+            PyKey::Index(index) if index >= 0 => index as usize,
+            PyKey::Index(index) => self
+                .length(obj_id.clone(), heads.clone())
+                .checked_sub(index.unsigned_abs())?,
+        };
+        Some(PyProp(Prop::Seq(index)))
+    }
+
+    /// The object holding the last segment of `path` from `obj_id`, and that segment as a prop, or
+    /// None if the path leads through a missing value or a scalar.
+    fn follow(
+        &self,
+        obj_id: PyObjId,
+        path: PyPropPath,
+        heads: &Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Option<(PyObjId, PyProp)>> {
+        let mut obj_id = obj_id;
+        let mut segments = path.0.into_iter().peekable();
+        while let Some(key) = segments.next() {
+            let Some(prop) = self.resolve_key(&obj_id, key, heads) else {
+                return Ok(None);
+            };
+            if segments.peek().is_none() {
+                return Ok(Some((obj_id, prop)));
+            }
+            match self.get(obj_id, prop, heads.clone())? {
+                Some((PyValue(am::Value::Object(_), _), id)) => obj_id = id,
+                _ => return Ok(None),
+            }
+        }
+        Err(PyException::new_err("path must not be empty"))
+    }
+
+    /// The value of `prop` in `obj_id` as converted by `convert`, raising TypeMismatchError
+    /// naming the `expected` type if `convert` rejects it.
+    fn get_typed<T>(
+        &self,
+        obj_id: PyObjId,
+        prop: PyKey,
+        heads: Option<Vec<PyChangeHash>>,
+        expected: &str,
+        convert: fn(&am::Value<'_>, &am::ObjId) -> Option<T>,
+    ) -> PyResult<Option<T>> {
+        let Some(prop) = self.resolve_key(&obj_id, prop, &heads) else {
+            return Ok(None);
+        };
+        let Some((value, id)) = self.get(obj_id, prop.clone(), heads)? else {
+            return Ok(None);
+        };
+        convert(&value.0, &id.0).map(Some).ok_or_else(|| {
+            TypeMismatchError::new_err(format!(
+                "expected {} at {}, found {}",
+                expected,
+                prop.0,
+                value_type_name(&value.0)
+            ))
+        })
     }
 
-    fn keys(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<String>> {
+    /// The bytes value of `prop` in `obj_id` in a buffer shared with any earlier reads of the same
+    /// value which are still alive, so the bytes are only copied out of the document once. Values
+    /// read through an open transaction aren't shared as it may yet be rolled back.
+    fn get_shared_bytes(
+        &self,
+        obj_id: PyObjId,
+        prop: PyKey,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Option<Arc<[u8]>>> {
+        let Some(prop) = self.resolve_key(&obj_id, prop, &heads) else {
+            return Ok(None);
+        };
+        let heads = get_heads(heads);
         let res = if let Some(tx) = self.tx.as_ref() {
-            match get_heads(heads) {
-                Some(heads) => tx.keys_at(obj_id.0, &heads),
-                None => tx.keys(obj_id.0),
+            match &heads {
+                Some(heads) => tx.get_at(obj_id.0, prop.0.clone(), heads),
+                None => tx.get(obj_id.0, prop.0.clone()),
             }
         } else {
-            match get_heads(heads) {
-                Some(heads) => self.doc.keys_at(obj_id.0, &heads),
-                None => self.doc.keys(obj_id.0),
+            match &heads {
+                Some(heads) => self.doc.get_at(obj_id.0, prop.0.clone(), heads),
+                None => self.doc.get(obj_id.0, prop.0.clone()),
             }
+        }
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some((value, id)) = res else {
+            return Ok(None);
+        };
+        let Some(ScalarValue::Bytes(bytes)) = value.to_scalar() else {
+            return Err(TypeMismatchError::new_err(format!(
+                "expected bytes at {}, found {}",
+                prop.0,
+                value_type_name(&value)
+            )));
+        };
+        if self.tx.is_some() {
+            return Ok(Some(Arc::from(bytes.as_slice())));
+        }
+        let mut cache = self
+            .bytes_cache
+            .lock()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if let Some(shared) = cache.get(&id).and_then(Weak::upgrade) {
+            return Ok(Some(shared));
+        }
+        let shared: Arc<[u8]> = Arc::from(bytes.as_slice());
+        cache.retain(|_, buffer| buffer.strong_count() > 0);
+        cache.insert(id, Arc::downgrade(&shared));
+        Ok(Some(shared))
+    }
+
+    /// The value of `prop` in `obj_id`, with scalars as plain Python values and objects as their
+    /// ID, or `default` if there is none.
+    fn get_or(
+        &self,
+        py: Python<'_>,
+        obj_id: PyObjId,
+        prop: PyKey,
+        default: PyObject,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyObject> {
+        let Some(prop) = self.resolve_key(&obj_id, prop, &heads) else {
+            return Ok(default);
         };
-        Ok(res.collect())
+        Ok(match self.get(obj_id, prop, heads)? {
+            Some((PyValue(am::Value::Scalar(s), _), _)) => {
+                export_scalar(py, s.into_owned(), self.timestamps)?.1
+            }
+            Some((PyValue(am::Value::Object(_), _), id)) => id.into_py(py),
+            None => default,
+        })
+    }
+
+    fn lookup<'py>(
+        &self,
+        path: Vec<PathSegment>,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Option<(PyValue<'py>, PyObjId)>> {
+        let heads_arg = heads.clone();
+        let heads = get_heads(heads);
+        let (mut value, mut id) = (am::Value::Object(ObjType::Map), am::ROOT);
+        for segment in path {
+            let am::Value::Object(objtype) = value else {
+                return Ok(None);
+            };
+            let prop = match segment {
+                PathSegment::Prop(key) => {
+                    match self.resolve_key(&PyObjId(id.clone()), key, &heads_arg) {
+                        Some(prop) => prop.0,
+                        None => return Ok(None),
+                    }
+                }
+                PathSegment::Key(key) => match (objtype, key.parse::<usize>()) {
+                    (ObjType::List | ObjType::Text, Ok(index)) => Prop::Seq(index),
+                    _ => Prop::Map(key),
+                },
+            };
+            let res = if let Some(tx) = self.tx.as_ref() {
+                match &heads {
+                    Some(heads) => tx.get_at(&id, prop, heads),
+                    None => tx.get(&id, prop),
+                }
+            } else {
+                match &heads {
+                    Some(heads) => self.doc.get_at(&id, prop, heads),
+                    None => self.doc.get(&id, prop),
+                }
+            }
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+            let Some((v, child)) = res else {
+                return Ok(None);
+            };
+            (value, id) = (v.into_owned(), child);
+        }
+        Ok(Some((PyValue(value, self.timestamps), PyObjId(id))))
+    }
+
+    fn query<'py>(
+        &self,
+        steps: &[QueryStep],
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<(Vec<PyProp>, PyValue<'py>, PyObjId)>> {
+        let heads = get_heads(heads);
+        let start = (Vec::new(), am::Value::Object(ObjType::Map), am::ROOT);
+        let matches = if let Some(tx) = self.tx.as_ref() {
+            run_query(tx, start, steps, heads.as_deref())
+        } else {
+            run_query(&self.doc, start, steps, heads.as_deref())
+        }
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(matches
+            .into_iter()
+            .map(|(path, value, id)| {
+                let path = path.into_iter().map(PyProp).collect();
+                (path, PyValue(value, self.timestamps), PyObjId(id))
+            })
+            .collect())
     }
 
-    fn values<'py>(
+    fn get_all<'py>(
         &self,
         obj_id: PyObjId,
+        prop: PyProp,
         heads: Option<Vec<PyChangeHash>>,
     ) -> PyResult<Vec<(PyValue<'py>, PyObjId)>> {
         let res = if let Some(tx) = self.tx.as_ref() {
             match get_heads(heads) {
-                Some(heads) => tx.values_at(obj_id.0, &heads),
-                None => tx.values(obj_id.0),
+                Some(heads) => tx.get_all_at(obj_id.0, prop.0, &heads),
+                None => tx.get_all(obj_id.0, prop.0),
+            }
+        } else {
+            match get_heads(heads) {
+                Some(heads) => self.doc.get_all_at(obj_id.0, prop.0, &heads),
+                None => self.doc.get_all(obj_id.0, prop.0),
+            }
+        }
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(res
+            .into_iter()
+            .map(|(v, id)| (PyValue(v.into_owned(), self.timestamps), PyObjId(id)))
+            .collect())
+    }
+
+    fn range_chunk(
+        &self,
+        py: Python<'_>,
+        obj_id: &am::ObjId,
+        cursor: &mut RangeCursor,
+        yields: RangeYield,
+        heads: Option<&[ChangeHash]>,
+    ) -> Vec<PyObject> {
+        if let Some(tx) = self.tx.as_ref() {
+            read_range_chunk(py, tx, obj_id, cursor, yields, heads, self.timestamps)
+        } else {
+            read_range_chunk(
+                py,
+                &self.doc,
+                obj_id,
+                cursor,
+                yields,
+                heads,
+                self.timestamps,
+            )
+        }
+    }
+
+    fn parents(
+        &self,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<(PyObjId, PyProp)>> {
+        let res = if let Some(tx) = self.tx.as_ref() {
+            match get_heads(heads) {
+                Some(heads) => tx.parents_at(obj_id.0, &heads),
+                None => tx.parents(obj_id.0),
             }
         } else {
             match get_heads(heads) {
-                Some(heads) => self.doc.values_at(obj_id.0, &heads),
-                None => self.doc.values(obj_id.0),
+                Some(heads) => self.doc.parents_at(obj_id.0, &heads),
+                None => self.doc.parents(obj_id.0),
             }
         }
-        .map(|(v, id)| (PyValue(v.into_owned()), PyObjId(id)));
-        Ok(res.collect())
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(res
+            .map(|parent| (PyObjId(parent.obj), PyProp(parent.prop)))
+            .collect())
     }
 
     fn get_heads(&self) -> Vec<PyChangeHash> {
@@ -105,7 +558,7 @@ impl Inner {
             self.doc.get_heads()
         }
         .into_iter()
-        .map(|c| PyChangeHash(c))
+        .map(PyChangeHash)
         .collect()
     }
 
@@ -138,6 +591,20 @@ impl Inner {
         .map_err(|e| PyException::new_err(e.to_string()))
     }
 
+    fn text_range(
+        &self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> String {
+        let heads = get_heads(heads);
+        match self.tx.as_ref() {
+            Some(tx) => text_range(tx, &obj_id.0, start, end, heads.as_deref()),
+            None => text_range(&self.doc, &obj_id.0, start, end, heads.as_deref()),
+        }
+    }
+
     fn marks(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyMark>> {
         let res = if let Some(tx) = self.tx.as_ref() {
             match get_heads(heads) {
@@ -151,663 +618,5737 @@ impl Inner {
             }
         }
         .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(res
-            .into_iter()
-            .map(|m| PyMark {
-                start: m.start,
-                end: m.end,
-                name: m.name().to_owned(),
-                value: PyScalarValue(m.value().clone()),
-            })
-            .collect())
+        Ok(res.iter().map(PyMark::from).collect())
     }
-}
-
-#[pyclass]
-struct Document {
-    inner: Arc<RwLock<Inner>>,
-}
 
-#[pymethods]
-impl Document {
-    #[new]
-    fn new(actor_id: Option<&[u8]>) -> Self {
-        let mut doc = am::Automerge::new();
-        if let Some(id) = actor_id {
-            doc.set_actor(ActorId::from(id));
-        }
-        Document {
-            inner: Arc::new(RwLock::new(Inner::new(doc))),
+    fn marks_at(
+        &self,
+        obj_id: PyObjId,
+        index: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<HashMap<String, PyScalarValue>> {
+        let heads = get_heads(heads);
+        let res = if let Some(tx) = self.tx.as_ref() {
+            tx.get_marks(obj_id.0, index, heads.as_deref())
+        } else {
+            self.doc.get_marks(obj_id.0, index, heads.as_deref())
         }
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(mark_set(&res))
     }
 
-    fn get_actor<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot get actor id with an active transaction",
-            ));
+    fn get_cursor(
+        &self,
+        obj_id: PyObjId,
+        position: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyCursor> {
+        let heads = get_heads(heads);
+        if let Some(tx) = self.tx.as_ref() {
+            tx.get_cursor(obj_id.0, position, heads.as_deref())
+        } else {
+            self.doc.get_cursor(obj_id.0, position, heads.as_deref())
         }
-
-        Ok(PyBytes::new(py, inner.doc.get_actor().to_bytes()))
+        .map_err(|e| PyException::new_err(e.to_string()))
+        .map(PyCursor)
     }
 
-    fn set_actor(&mut self, actor_id: &[u8]) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot set actor with an active transaction",
-            ));
+    /// The positions of the non-overlapping occurrences of `needle` in the text `obj_id`, with
+    /// their cursors if `cursors` is set.
+    fn find_text(
+        &self,
+        obj_id: PyObjId,
+        needle: &str,
+        heads: Option<Vec<PyChangeHash>>,
+        cursors: bool,
+    ) -> PyResult<Vec<(usize, Option<PyCursor>)>> {
+        if needle.is_empty() {
+            return Err(PyException::new_err("cannot search for an empty string"));
         }
-
-        inner.doc.set_actor(ActorId::from(actor_id));
-        Ok(())
+        let heads = get_heads(heads);
+        let (text, encoding) = match self.tx.as_ref() {
+            Some(tx) => (
+                text_range(tx, &obj_id.0, 0, usize::MAX, heads.as_deref()),
+                tx.text_encoding(),
+            ),
+            None => (
+                text_range(&self.doc, &obj_id.0, 0, usize::MAX, heads.as_deref()),
+                self.doc.text_encoding(),
+            ),
+        };
+        let (mut pos, mut scanned) = (0, 0);
+        let mut found = Vec::new();
+        for (offset, _) in text.match_indices(needle) {
+            pos += text_width(encoding, &text[scanned..offset]);
+            scanned = offset;
+            let cursor = if cursors {
+                let cursor = match self.tx.as_ref() {
+                    Some(tx) => tx.get_cursor(&obj_id.0, pos, heads.as_deref()),
+                    None => self.doc.get_cursor(&obj_id.0, pos, heads.as_deref()),
+                }
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+                Some(PyCursor(cursor))
+            } else {
+                None
+            };
+            found.push((pos, cursor));
+        }
+        Ok(found)
     }
 
-    fn transaction(&self) -> PyResult<Transaction> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err("transaction already active"));
+    fn get_cursor_position(
+        &self,
+        obj_id: PyObjId,
+        cursor: PyCursor,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<usize> {
+        let heads = get_heads(heads);
+        if let Some(tx) = self.tx.as_ref() {
+            tx.get_cursor_position(obj_id.0, &cursor.0, heads.as_deref())
+        } else {
+            self.doc
+                .get_cursor_position(obj_id.0, &cursor.0, heads.as_deref())
         }
-
-        // Here we're transmuting the lifetime of the transaction to `static`, which is okay
-        // because we are then storing the transaction in `Inner` which means the document will
-        // live as long as the transaction.
-        let tx = unsafe { transmute(inner.doc.transaction()) };
-        inner.tx = Some(tx);
-        Ok(Transaction {
-            inner: Arc::clone(&self.inner),
-        })
+        .map_err(|e| PyException::new_err(e.to_string()))
     }
 
-    fn save<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot save with an active transaction",
-            ));
+    fn spans(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PySpan>> {
+        let res = if let Some(tx) = self.tx.as_ref() {
+            match get_heads(heads) {
+                Some(heads) => tx.spans_at(obj_id.0, &heads),
+                None => tx.spans(obj_id.0),
+            }
+        } else {
+            match get_heads(heads) {
+                Some(heads) => self.doc.spans_at(obj_id.0, &heads),
+                None => self.doc.spans(obj_id.0),
+            }
         }
-
-        Ok(PyBytes::new(py, &inner.doc.save()))
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(res.map(PySpan).collect())
     }
 
-    #[staticmethod]
-    fn load(bytes: &[u8]) -> PyResult<Self> {
-        let doc = am::Automerge::load(bytes).map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(Self {
-            inner: Arc::new(RwLock::new(Inner::new(doc))),
-        })
+    fn to_py(
+        &self,
+        py: Python<'_>,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyObject> {
+        let heads = get_heads(heads);
+        if let Some(tx) = self.tx.as_ref() {
+            export_object(py, tx, obj_id.0, heads.as_deref(), self.timestamps)
+        } else {
+            export_object(py, &self.doc, obj_id.0, heads.as_deref(), self.timestamps)
+        }
     }
 
-    fn fork(&self, heads: Option<Vec<PyChangeHash>>) -> PyResult<Document> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot fork with an active transaction",
-            ));
+    fn to_json(
+        &self,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+        options: &JsonOptions,
+    ) -> PyResult<serde_json::Value> {
+        let heads = get_heads(heads);
+        let objtype = self.object_type(PyObjId(obj_id.0.clone()))?;
+        let value = am::Value::Object((&objtype).into());
+        if let Some(tx) = self.tx.as_ref() {
+            export_json(tx, value, obj_id.0, heads.as_deref(), options)
+        } else {
+            export_json(&self.doc, value, obj_id.0, heads.as_deref(), options)
+        }
+        .map_err(|e| PyException::new_err(e.to_string()))
+    }
+}
+
+/// Make a change which reverts the change `hash`, returning its hash (or None if there was nothing
+/// to revert).
+///
+/// The inverse patches from `hash` back to its dependencies are applied to the current state.
+/// Their sequence positions refer to the document as of `hash`, so each is translated through a
+/// cursor on the element that was there. Elements which an earlier undo or redo reinserted are
+/// new elements, so `replaced` maps the cursor of each element that was reinserted to the cursor
+/// of its replacement, letting a later revert find it.
+fn revert_change(
+    doc: &mut am::Automerge,
+    hash: ChangeHash,
+    replaced: &mut HashMap<Vec<u8>, (am::Cursor, u64)>,
+) -> Result<Option<ChangeHash>, am::AutomergeError> {
+    let deps = doc
+        .get_change_by_hash(&hash)
+        .map(|change| change.deps().to_vec())
+        .unwrap_or_default();
+    let patches = doc.diff(&[hash], &deps);
+    // Positions are resolved before the transaction starts, and then moved past the transaction's
+    // own edits as they are made.
+    let mut shifts: HashMap<am::ObjId, isize> = HashMap::new();
+    let resolved = patches
+        .iter()
+        .map(|patch| {
+            let shift = shifts.entry(patch.obj.clone()).or_default();
+            resolve(doc, patch, shift, hash, &deps, replaced)
+        })
+        .collect::<Vec<_>>();
+
+    let mut tx = doc.transaction();
+    // Objects which reappear are recreated as copies, so patches to the originals are skipped.
+    let mut copied = HashSet::new();
+    let mut edits: HashMap<am::ObjId, Vec<(usize, isize)>> = HashMap::new();
+    let mut reinserted = Vec::new();
+    for (patch, resolved) in patches.into_iter().zip(resolved) {
+        if copied.contains(&patch.obj) {
+            continue;
         }
-        let new_doc = match get_heads(heads) {
-            Some(heads) => inner.doc.fork_at(&heads),
-            None => Ok(inner.doc.fork()),
+        let Resolved { positions, old } = resolved?;
+        let obj = &patch.obj;
+        let edits = edits.entry(obj.clone()).or_default();
+        match patch.action {
+            am::PatchAction::PutMap {
+                key,
+                value: (value, id),
+                ..
+            } => restore(
+                &mut tx,
+                obj,
+                Prop::Map(key),
+                false,
+                value,
+                &id,
+                &deps,
+                &mut copied,
+            )?,
+            am::PatchAction::DeleteMap { key } => tx.delete(obj, key.as_str())?,
+            am::PatchAction::Increment {
+                prop: Prop::Map(key),
+                value,
+            } => tx.increment(obj, key, value)?,
+            am::PatchAction::Increment { value, .. } => {
+                if let Some(pos) = positions[0] {
+                    tx.increment(obj, after_edits(edits, pos), value)?;
+                }
+            }
+            am::PatchAction::PutSeq {
+                value: (value, id), ..
+            } => {
+                if let Some(pos) = positions[0] {
+                    let prop = Prop::Seq(after_edits(edits, pos));
+                    restore(&mut tx, obj, prop, false, value, &id, &deps, &mut copied)?;
+                }
+            }
+            am::PatchAction::Insert { values, .. } => {
+                let pos = after_edits(edits, positions[0].unwrap_or_default());
+                for (i, (value, id, _)) in values.iter().enumerate() {
+                    let prop = Prop::Seq(pos + i);
+                    restore(
+                        &mut tx,
+                        obj,
+                        prop,
+                        true,
+                        value.clone(),
+                        id,
+                        &deps,
+                        &mut copied,
+                    )?;
+                }
+                record_replaced(&tx, obj, pos, old, &mut reinserted)?;
+                edits.push((pos, values.len() as isize));
+            }
+            am::PatchAction::SpliceText { value, .. } => {
+                let pos = after_edits(edits, positions[0].unwrap_or_default());
+                tx.splice_text(obj, pos, 0, &value.make_string())?;
+                edits.push((pos, old.len() as isize));
+                record_replaced(&tx, obj, pos, old, &mut reinserted)?;
+            }
+            am::PatchAction::DeleteSeq { .. } => {
+                let text = tx.object_type(obj)? == ObjType::Text;
+                for pos in positions.into_iter().flatten().rev() {
+                    let pos = after_edits(edits, pos);
+                    if text {
+                        tx.splice_text(obj, pos, 1, "")?;
+                    } else {
+                        tx.delete(obj, pos)?;
+                    }
+                    edits.push((pos, -1));
+                }
+            }
+            am::PatchAction::Mark { marks } => {
+                for (mark, range) in marks.into_iter().zip(positions.chunks(2)) {
+                    let start = after_edits(edits, range[0].unwrap_or_default());
+                    let end = after_edits(edits, range[1].unwrap_or_default());
+                    let mark = Mark::new(mark.name().to_owned(), mark.value().clone(), start, end);
+                    tx.mark(obj, mark, ExpandMark::None)?;
+                }
+            }
+            am::PatchAction::Conflict { .. } => {}
         }
-        .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(Document {
-            inner: Arc::new(RwLock::new(Inner::new(new_doc))),
+    }
+    let committed = tx.commit().0;
+    if let Some(change) = committed.and_then(|hash| doc.get_change_by_hash(&hash)) {
+        let made = change.max_op();
+        replaced.extend(reinserted.into_iter().map(|(old, new)| (old, (new, made))));
+    }
+    Ok(committed)
+}
+
+/// The position after `edits` of what was at `pos` before them, where `edits` are the
+/// `(position, count)` of each insertion (or deletion, with a count of -1) made to a sequence.
+fn after_edits(edits: &[(usize, isize)], mut pos: usize) -> usize {
+    for &(at, count) in edits {
+        if count > 0 && pos >= at {
+            pos += count as usize;
+        } else if count < 0 && pos > at {
+            pos -= 1;
+        }
+    }
+    pos
+}
+
+/// The positions in the current document which a patch reverting a change refers to, along with
+/// cursors on the elements which an insertion reinserts.
+#[derive(Default)]
+struct Resolved {
+    positions: Vec<Option<usize>>,
+    old: Vec<am::Cursor>,
+}
+
+/// Resolve the positions of `patch`, one of the patches from `hash` back to `deps`. `shift` is the
+/// number of elements which earlier patches to the same object inserted minus those they deleted,
+/// which turns a patch index back into an index as of `hash`.
+fn resolve(
+    doc: &am::Automerge,
+    patch: &am::Patch,
+    shift: &mut isize,
+    hash: ChangeHash,
+    deps: &[ChangeHash],
+    replaced: &HashMap<Vec<u8>, (am::Cursor, u64)>,
+) -> Result<Resolved, am::AutomergeError> {
+    let obj = &patch.obj;
+    let at = [hash];
+    let original = |index: usize| (index as isize - *shift) as usize;
+    let mut resolved = Resolved::default();
+    match &patch.action {
+        am::PatchAction::Increment {
+            prop: Prop::Seq(index),
+            ..
+        }
+        | am::PatchAction::PutSeq { index, .. } => {
+            resolved.positions = vec![locate(doc, obj, original(*index), &at, replaced)?];
+        }
+        am::PatchAction::Insert { index, .. } | am::PatchAction::SpliceText { index, .. } => {
+            let len = match &patch.action {
+                am::PatchAction::SpliceText { value, .. } => {
+                    text_width(doc.text_encoding(), &value.make_string())
+                }
+                am::PatchAction::Insert { values, .. } => values.len(),
+                _ => unreachable!(),
+            };
+            let pos = anchor(doc, obj, original(*index), &at, replaced)?;
+            resolved.positions = vec![Some(pos)];
+            for i in 0..len {
+                resolved
+                    .old
+                    .push(doc.get_cursor(obj, index + i, Some(deps))?);
+            }
+            *shift += len as isize;
+        }
+        am::PatchAction::DeleteSeq { index, length } => {
+            for i in *index..index + length {
+                resolved
+                    .positions
+                    .push(locate(doc, obj, original(i), &at, replaced)?);
+            }
+            *shift -= *length as isize;
+        }
+        am::PatchAction::Mark { marks } => {
+            for mark in marks.iter() {
+                for index in [mark.start, mark.end] {
+                    let pos = anchor(doc, obj, original(index), &at, replaced)?;
+                    resolved.positions.push(Some(pos));
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(resolved)
+}
+
+/// The current position of the element which was at `index` of `obj` as of `at`, following
+/// `replaced` if it has since been deleted. None if the element is no longer visible.
+fn locate(
+    doc: &am::Automerge,
+    obj: &am::ObjId,
+    index: usize,
+    at: &[ChangeHash],
+    replaced: &HashMap<Vec<u8>, (am::Cursor, u64)>,
+) -> Result<Option<usize>, am::AutomergeError> {
+    let mut cursor = doc.get_cursor(obj, index, Some(at))?;
+    loop {
+        if let Some(pos) = visible_position(doc, obj, &cursor) {
+            return Ok(Some(pos));
+        }
+        match replaced.get(&cursor.to_bytes()) {
+            Some((next, _)) => cursor = next.clone(),
+            None => return Ok(None),
+        }
+    }
+}
+
+/// The current position corresponding to position `index` of `obj` as of `at`, for inserting
+/// before whatever was at `index` then: the position of the first element from there on which is
+/// still visible, or the end of the sequence.
+fn anchor(
+    doc: &am::Automerge,
+    obj: &am::ObjId,
+    index: usize,
+    at: &[ChangeHash],
+    replaced: &HashMap<Vec<u8>, (am::Cursor, u64)>,
+) -> Result<usize, am::AutomergeError> {
+    for i in index..doc.length_at(obj, at) {
+        if let Some(pos) = locate(doc, obj, i, at, replaced)? {
+            return Ok(pos);
+        }
+    }
+    Ok(doc.length(obj))
+}
+
+/// The current position of the element `cursor` points at, or None if it has been deleted.
+/// `get_cursor_position` alone moves past deleted elements (or fails to place some of them), so
+/// the element found there is checked against `cursor`.
+///
+/// The lookup is made at the current heads: without them automerge checks its fast lookup
+/// against a linear scan, and the two disagree (a debug-build panic) for a deleted element with
+/// nothing visible after it. Resolving at heads takes the scan alone, which is what the lookups
+/// of the original positions at `at` cost already.
+fn visible_position(doc: &am::Automerge, obj: &am::ObjId, cursor: &am::Cursor) -> Option<usize> {
+    let pos = doc
+        .get_cursor_position(obj, cursor, Some(&doc.get_heads()))
+        .ok()?;
+    let found = doc.get_cursor(obj, pos, None).ok()?;
+    (found == *cursor).then_some(pos)
+}
+
+/// The length of `text` in the units of `encoding`.
+fn text_width(encoding: TextEncoding, text: &str) -> usize {
+    match encoding {
+        TextEncoding::UnicodeCodePoint => text.chars().count(),
+        TextEncoding::Utf8CodeUnit => text.len(),
+        TextEncoding::Utf16CodeUnit => text.encode_utf16().count(),
+        TextEncoding::GraphemeCluster => text.graphemes(true).count(),
+    }
+}
+
+/// The text between positions `start` (inclusive) and `end` (exclusive) of `obj`, measured in the
+/// document's text encoding. Only the elements up to `end` are visited.
+fn text_range<R: ReadDoc>(
+    doc: &R,
+    obj: &am::ObjId,
+    start: usize,
+    end: usize,
+    heads: Option<&[ChangeHash]>,
+) -> String {
+    let encoding = doc.text_encoding();
+    // Text elements are single code points, so only the other encodings need to be measured.
+    let range = match encoding {
+        TextEncoding::UnicodeCodePoint => start..end,
+        _ => 0..usize::MAX,
+    };
+    let mut pos = range.start;
+    let items = match heads {
+        Some(heads) => doc.list_range_at(obj, range, heads),
+        None => doc.list_range(obj, range),
+    };
+    let mut text = String::new();
+    for item in items {
+        if pos >= end {
+            break;
+        }
+        let elem = match item.value.into_value() {
+            am::Value::Scalar(s) => match s.as_ref() {
+                am::ScalarValue::Str(s) => s.to_string(),
+                _ => "\u{fffc}".to_string(),
+            },
+            am::Value::Object(_) => "\u{fffc}".to_string(),
+        };
+        if pos >= start {
+            text.push_str(&elem);
+        }
+        pos += text_width(encoding, &elem);
+    }
+    text
+}
+
+/// Record that the elements just inserted at `pos` replace the elements `old`.
+fn record_replaced(
+    tx: &am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    pos: usize,
+    old: Vec<am::Cursor>,
+    reinserted: &mut Vec<(Vec<u8>, am::Cursor)>,
+) -> Result<(), am::AutomergeError> {
+    for (i, old) in old.into_iter().enumerate() {
+        reinserted.push((old.to_bytes(), tx.get_cursor(obj, pos + i, None)?));
+    }
+    Ok(())
+}
+
+/// Put or insert `value` at `prop` of `obj`. An object is recreated with the contents that the
+/// object `id` had at `heads`.
+#[allow(clippy::too_many_arguments)]
+fn restore(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    prop: Prop,
+    insert: bool,
+    value: am::Value<'_>,
+    id: &am::ObjId,
+    heads: &[ChangeHash],
+    copied: &mut HashSet<am::ObjId>,
+) -> Result<(), am::AutomergeError> {
+    match (value, prop) {
+        (am::Value::Scalar(s), Prop::Seq(index)) if insert => tx.insert(obj, index, s.into_owned()),
+        (am::Value::Scalar(s), prop) => tx.put(obj, prop, s.into_owned()),
+        (am::Value::Object(objtype), prop) => {
+            let new = match prop {
+                Prop::Seq(index) if insert => tx.insert_object(obj, index, objtype)?,
+                prop => tx.put_object(obj, prop, objtype)?,
+            };
+            copy_object(tx, id, objtype, &new, heads, copied)
+        }
+    }
+}
+
+/// Fill the new object `dst` with the contents `src` had at `heads`.
+fn copy_object(
+    tx: &mut am::transaction::Transaction<'_>,
+    src: &am::ObjId,
+    objtype: ObjType,
+    dst: &am::ObjId,
+    heads: &[ChangeHash],
+    copied: &mut HashSet<am::ObjId>,
+) -> Result<(), am::AutomergeError> {
+    copied.insert(src.clone());
+    match objtype {
+        ObjType::Map | ObjType::Table => {
+            let items = tx
+                .map_range_at(src, .., heads)
+                .map(|item| {
+                    let id = item.id();
+                    (
+                        item.key.into_owned(),
+                        item.value.into_value().into_owned(),
+                        id,
+                    )
+                })
+                .collect::<Vec<_>>();
+            for (key, value, id) in items {
+                restore(tx, dst, Prop::Map(key), false, value, &id, heads, copied)?;
+            }
+        }
+        ObjType::List => {
+            let items = tx
+                .list_range_at(src, .., heads)
+                .map(|item| {
+                    let id = item.id();
+                    (item.index, item.value.into_value().into_owned(), id)
+                })
+                .collect::<Vec<_>>();
+            for (index, value, id) in items {
+                restore(tx, dst, Prop::Seq(index), true, value, &id, heads, copied)?;
+            }
+        }
+        ObjType::Text => {
+            let text = tx.text_at(src, heads)?;
+            tx.splice_text(dst, 0, 0, &text)?;
+            for mark in tx.marks_at(src, heads)? {
+                tx.mark(dst, mark, ExpandMark::None)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cut `message` down to at most `max` bytes once encoded, by leaving out the changes which don't
+/// fit. Those changes are taken back out of the hashes `state` has sent, so the sync protocol sends
+/// them in a later message. `sent` is what `state` had sent before `message` was generated.
+fn limit_message_size(
+    doc: &am::Automerge,
+    state: &mut am::sync::State,
+    sent: &BTreeSet<ChangeHash>,
+    mut message: am::sync::Message,
+    max: usize,
+) -> am::sync::Message {
+    let size = message.clone().encode().len();
+    if size <= max {
+        return message;
+    }
+    // The message may hold the whole document as one chunk, so send its changes one by one,
+    // leaving out those the peer already has.
+    let their_heads: Vec<ChangeHash> = state
+        .their_heads
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|hash| doc.get_change_by_hash(hash).is_some())
+        .collect();
+    let changes: Vec<am::Change> = doc
+        .get_changes(&their_heads)
+        .into_iter()
+        .filter(|change| {
+            let hash = change.hash();
+            state.sent_hashes.contains(&hash) && !sent.contains(&hash)
         })
+        .collect();
+    let changes_size: usize = message.changes.iter().map(|chunk| chunk.len()).sum();
+    // Allow for the length prefixes of each chunk.
+    let mut budget = max.saturating_sub(size - changes_size + 10);
+    let mut included = Vec::new();
+    for change in changes {
+        let len = change.raw_bytes().len() + 5;
+        if included.is_empty() || len <= budget {
+            budget = budget.saturating_sub(len);
+            included.push(change);
+        } else {
+            state.sent_hashes.remove(&change.hash());
+        }
     }
+    let chunks = included.iter().map(|change| change.raw_bytes().to_vec());
+    message.changes = match message.version {
+        am::sync::MessageVersion::V1 => chunks.collect::<Vec<_>>().into(),
+        am::sync::MessageVersion::V2 => chunks.flatten().collect::<Vec<u8>>().into(),
+    };
+    message
+}
 
-    fn merge(&mut self, other: &Document) -> PyResult<Vec<PyChangeHash>> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot merge with an active transaction",
-            ));
+/// Each element of the text `obj` as of `heads`, as its cursor, position and character.
+fn text_elements(
+    doc: &am::Automerge,
+    obj: &am::ObjId,
+    heads: &[ChangeHash],
+) -> Result<Vec<(Vec<u8>, usize, String)>, am::AutomergeError> {
+    let mut elements: Vec<(Vec<u8>, usize, String)> = Vec::new();
+    for pos in 0..doc.length_at(obj, heads) {
+        let cursor = doc.get_cursor(obj, pos, Some(heads))?.to_bytes();
+        // A character can span several positions, depending on the text encoding.
+        if elements.last().is_some_and(|(last, _, _)| *last == cursor) {
+            continue;
+        }
+        let text = match doc.get_at(obj, pos, heads)? {
+            Some((am::Value::Scalar(s), _)) => s.to_str().unwrap_or_default().to_owned(),
+            _ => String::new(),
+        };
+        elements.push((cursor, pos, text));
+    }
+    Ok(elements)
+}
+
+/// Apply `new_value` to the text `obj` as an edit of the text at `base`. The edit is diffed
+/// against the text at `base`, and each difference is applied where the characters it touches
+/// are now, so changes made since `base` are kept.
+fn update_text_from(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    base: &[ChangeHash],
+    new_value: &str,
+) -> Result<(), am::AutomergeError> {
+    let encoding = tx.text_encoding();
+    let old = text_chars(tx, obj, Some(base));
+    let mut current = HashMap::new();
+    let mut pos = 0;
+    for (id, c) in text_chars(tx, obj, None) {
+        let width = text_width(encoding, c.encode_utf8(&mut [0; 4]));
+        current.insert(id, (pos, width));
+        pos += width;
+    }
+    let old_chars: Vec<char> = old.iter().map(|(_, c)| *c).collect();
+    let new_chars: Vec<char> = new_value.chars().collect();
+    // Applied from the end, so the current positions of earlier characters stay valid.
+    for (start, deleted, inserted) in diff_chars(&old_chars, &new_chars).into_iter().rev() {
+        // Characters which are still next to each other are deleted with a single splice.
+        let mut run: Option<(usize, usize)> = None;
+        for (id, _) in old[start..start + deleted].iter().rev() {
+            let Some(&(pos, width)) = current.get(id) else {
+                continue;
+            };
+            run = match run {
+                Some((run_pos, len)) if pos + width == run_pos => Some((pos, len + width)),
+                Some((run_pos, len)) => {
+                    tx.splice_text(obj, run_pos, len as isize, "")?;
+                    Some((pos, width))
+                }
+                None => Some((pos, width)),
+            };
+        }
+        if let Some((pos, len)) = run {
+            tx.splice_text(obj, pos, len as isize, "")?;
+        }
+        if !inserted.is_empty() {
+            // Insert after the closest character before the edit that still exists.
+            let at = old[..start]
+                .iter()
+                .rev()
+                .find_map(|(id, _)| current.get(id))
+                .map_or(0, |&(pos, width)| pos + width);
+            tx.splice_text(obj, at, 0, &inserted)?;
+        }
+    }
+    Ok(())
+}
+
+/// The elements of the text `obj` as their IDs and characters, with objects such as block
+/// markers as U+FFFC.
+fn text_chars<R: ReadDoc>(
+    doc: &R,
+    obj: &am::ObjId,
+    heads: Option<&[ChangeHash]>,
+) -> Vec<(am::ObjId, char)> {
+    match heads {
+        Some(heads) => doc.list_range_at(obj, .., heads),
+        None => doc.list_range(obj, ..),
+    }
+    .map(|item| {
+        let id = item.id();
+        let c = match item.value.into_value() {
+            am::Value::Scalar(s) => s.to_str().and_then(|s| s.chars().next()),
+            am::Value::Object(_) => None,
+        };
+        (id, c.unwrap_or('\u{fffc}'))
+    })
+    .collect()
+}
+
+/// How many characters `diff_chars` deletes and inserts at most before giving up on a minimal
+/// diff. Myers' algorithm keeps a trace growing with the square of this.
+const MAX_DIFF_COST: isize = 1000;
+
+/// The differences between `old` and `new` as `(start, deleted, inserted)` edits of `old`, in
+/// order, found with Myers' algorithm after trimming the common prefix and suffix. Texts needing
+/// more than `MAX_DIFF_COST` deletions and insertions get a single edit replacing everything
+/// between the common prefix and suffix instead.
+fn diff_chars(old: &[char], new: &[char]) -> Vec<(usize, usize, String)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old_rest, new_rest) = (&old[prefix..], &new[prefix..]);
+    let suffix = old_rest
+        .iter()
+        .rev()
+        .zip(new_rest.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old_rest[..old_rest.len() - suffix];
+    let b = &new_rest[..new_rest.len() - suffix];
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // trace[d] holds the furthest x reached on each diagonal -d, -d + 2, ..., d after d steps.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut steps = None;
+    'search: for d in 0..=(n + m).min(MAX_DIFF_COST) {
+        for k in (-d..=d).step_by(2) {
+            let down =
+                k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]);
+            let mut x = if down {
+                v[(offset + k + 1) as usize]
+            } else {
+                v[(offset + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                steps = Some(d);
+                break 'search;
+            }
+        }
+        trace.push(
+            (-d..=d)
+                .step_by(2)
+                .map(|k| v[(offset + k) as usize])
+                .collect(),
+        );
+    }
+    let Some(steps) = steps else {
+        return vec![(prefix, a.len(), b.iter().collect())];
+    };
+
+    // Walk back from the end, collecting `(x, None)` for a deletion of a[x] and `(x, Some(c))`
+    // for an insertion of c before a[x].
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..=steps).rev() {
+        let prev = &trace[d as usize - 1];
+        let at = |k: isize| prev[((k + d - 1) / 2) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && at(k - 1) < at(k + 1));
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        let inserted = down.then(|| b[prev_y as usize]);
+        ops.push((prev_x as usize, inserted));
+        (x, y) = (prev_x, prev_y);
+    }
+    ops.reverse();
+
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    for (x, inserted) in ops {
+        let start = prefix + x;
+        let edit = match edits.last_mut() {
+            Some(edit) if edit.0 + edit.1 == start => edit,
+            _ => {
+                edits.push((start, 0, String::new()));
+                edits.last_mut().unwrap()
+            }
+        };
+        match inserted {
+            Some(c) => edit.2.push(c),
+            None => edit.1 += 1,
         }
-        let mut other_inner = other
+    }
+    edits
+}
+
+/// The number of objects reachable from `obj`, including `obj` itself.
+fn count_objects<R: ReadDoc>(doc: &R, obj: &am::ObjId) -> usize {
+    1 + doc
+        .values(obj)
+        .filter(|(v, _)| v.is_object())
+        .map(|(_, id)| count_objects(doc, &id))
+        .sum::<usize>()
+}
+
+/// Recursively convert the object `obj` and all of its descendants into JSON.
+fn export_json<R: ReadDoc>(
+    doc: &R,
+    value: am::Value<'_>,
+    id: am::ObjId,
+    heads: Option<&[ChangeHash]>,
+    options: &JsonOptions,
+) -> Result<serde_json::Value, am::AutomergeError> {
+    Ok(match value {
+        am::Value::Object(ObjType::Map | ObjType::Table) => {
+            let items = match heads {
+                Some(heads) => doc.map_range_at(&id, .., heads),
+                None => doc.map_range(&id, ..),
+            };
+            let mut map = serde_json::Map::new();
+            for item in items {
+                let id = item.id();
+                let v = export_json(doc, item.value.into_value(), id, heads, options)?;
+                map.insert(item.key.into_owned(), v);
+            }
+            serde_json::Value::Object(map)
+        }
+        am::Value::Object(ObjType::List) => {
+            let items = match heads {
+                Some(heads) => doc.list_range_at(&id, .., heads),
+                None => doc.list_range(&id, ..),
+            };
+            serde_json::Value::Array(
+                items
+                    .map(|item| {
+                        let id = item.id();
+                        export_json(doc, item.value.into_value(), id, heads, options)
+                    })
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        am::Value::Object(ObjType::Text) => serde_json::Value::String(match heads {
+            Some(heads) => doc.text_at(&id, heads)?,
+            None => doc.text(&id)?,
+        }),
+        am::Value::Scalar(s) => options.scalar(&s),
+    })
+}
+
+fn json_to_string(value: &serde_json::Value, indent: Option<usize>) -> PyResult<String> {
+    let res = match indent {
+        Some(indent) => {
+            let indent = vec![b' '; indent];
+            let mut out = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(&indent);
+            let mut ser = serde_json::Serializer::with_formatter(&mut out, formatter);
+            value.serialize(&mut ser).map(|_| out)
+        }
+        None => serde_json::to_vec(value),
+    };
+    res.map(|out| String::from_utf8(out).expect("serde_json produces valid UTF-8"))
+        .map_err(|e| PyException::new_err(e.to_string()))
+}
+
+/// How `to_json` represents the scalar types which have no JSON equivalent.
+struct JsonOptions {
+    /// "number" or "object", the latter giving `{"counter": n}`.
+    counters: String,
+    /// "millis" since the epoch or an "iso" 8601 string in UTC.
+    timestamps: String,
+    /// A "hex" string or an "array" of integers.
+    bytes: String,
+}
+
+impl JsonOptions {
+    fn new(counters: &str, timestamps: &str, bytes: &str) -> PyResult<Self> {
+        for (name, value, allowed) in [
+            ("counters", counters, ["number", "object"]),
+            ("timestamps", timestamps, ["millis", "iso"]),
+            ("bytes", bytes, ["hex", "array"]),
+        ] {
+            if !allowed.contains(&value) {
+                return Err(PyException::new_err(format!(
+                    "invalid value for {}: {:?}, expected one of {:?}",
+                    name, value, allowed
+                )));
+            }
+        }
+        Ok(JsonOptions {
+            counters: counters.to_owned(),
+            timestamps: timestamps.to_owned(),
+            bytes: bytes.to_owned(),
+        })
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> serde_json::Value {
+        match self.bytes.as_str() {
+            "array" => bytes.iter().copied().collect(),
+            _ => serde_json::Value::String(hex::encode(bytes)),
+        }
+    }
+
+    fn scalar(&self, value: &ScalarValue) -> serde_json::Value {
+        match value {
+            ScalarValue::Bytes(b) => self.bytes(b),
+            ScalarValue::Str(s) => serde_json::Value::String(s.to_string()),
+            ScalarValue::Int(i) => (*i).into(),
+            ScalarValue::Uint(u) => (*u).into(),
+            // NaN and infinities have no JSON representation and become null.
+            ScalarValue::F64(f) => (*f).into(),
+            ScalarValue::Counter(c) => {
+                let n = i64::from(c);
+                match self.counters.as_str() {
+                    "object" => serde_json::json!({ "counter": n }),
+                    _ => n.into(),
+                }
+            }
+            ScalarValue::Timestamp(ms) => match self.timestamps.as_str() {
+                "iso" => match chrono::DateTime::from_timestamp_millis(*ms) {
+                    Some(dt) => serde_json::Value::String(
+                        dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    ),
+                    None => (*ms).into(),
+                },
+                _ => (*ms).into(),
+            },
+            ScalarValue::Boolean(b) => (*b).into(),
+            ScalarValue::Unknown { bytes, .. } => self.bytes(bytes),
+            ScalarValue::Null => serde_json::Value::Null,
+        }
+    }
+}
+
+/// Recursively convert the object `obj` and all of its descendants into plain Python values.
+fn export_object<R: ReadDoc>(
+    py: Python<'_>,
+    doc: &R,
+    obj: am::ObjId,
+    heads: Option<&[ChangeHash]>,
+    timestamps: PyTimestampMode,
+) -> PyResult<PyObject> {
+    let objtype = doc
+        .object_type(&obj)
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+    export_value(py, doc, am::Value::Object(objtype), obj, heads, timestamps)
+}
+
+fn export_value<R: ReadDoc>(
+    py: Python<'_>,
+    doc: &R,
+    value: am::Value<'_>,
+    id: am::ObjId,
+    heads: Option<&[ChangeHash]>,
+    timestamps: PyTimestampMode,
+) -> PyResult<PyObject> {
+    Ok(match value {
+        am::Value::Object(ObjType::Map | ObjType::Table) => {
+            let items = match heads {
+                Some(heads) => doc.map_range_at(&id, .., heads),
+                None => doc.map_range(&id, ..),
+            };
+            let dict = PyDict::new(py);
+            for item in items {
+                let id = item.id();
+                let v = export_value(py, doc, item.value.into_value(), id, heads, timestamps)?;
+                dict.set_item(item.key.as_ref(), v)?;
+            }
+            dict.into()
+        }
+        am::Value::Object(ObjType::List) => {
+            let items = match heads {
+                Some(heads) => doc.list_range_at(&id, .., heads),
+                None => doc.list_range(&id, ..),
+            };
+            let values = items
+                .map(|item| {
+                    let id = item.id();
+                    export_value(py, doc, item.value.into_value(), id, heads, timestamps)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, values).into()
+        }
+        am::Value::Object(ObjType::Text) => match heads {
+            Some(heads) => doc.text_at(&id, heads),
+            None => doc.text(&id),
+        }
+        .map_err(|e| PyException::new_err(e.to_string()))?
+        .into_py(py),
+        am::Value::Scalar(s) => export_scalar(py, s.into_owned(), timestamps)?.1,
+    })
+}
+
+#[pyclass]
+struct Document {
+    inner: Arc<RwLock<Inner>>,
+}
+
+#[pymethods]
+impl Document {
+    /// Create an empty document. `text_encoding` sets the unit in which text positions and
+    /// lengths are counted, and defaults to `TextEncoding.UnicodeCodePoint`, matching Python's
+    /// `str` indexing. `timestamps` sets how timestamps are read, see the `timestamps` property.
+    #[new]
+    #[pyo3(signature = (actor_id = None, text_encoding = None, timestamps = None))]
+    fn new(
+        actor_id: Option<&[u8]>,
+        text_encoding: Option<PyTextEncoding>,
+        timestamps: Option<PyTimestampMode>,
+    ) -> Self {
+        let mut doc = match text_encoding {
+            Some(encoding) => am::Automerge::new_with_encoding(encoding.into()),
+            None => am::Automerge::new(),
+        };
+        if let Some(id) = actor_id {
+            doc.set_actor(ActorId::from(id));
+        }
+        let mut inner = Inner::new(doc);
+        if let Some(timestamps) = timestamps {
+            inner.timestamps = timestamps;
+        }
+        Document {
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
+    #[getter]
+    fn text_encoding(&self) -> PyResult<PyTextEncoding> {
+        let inner = self
             .inner
-            .write()
+            .read()
             .map_err(|e| PyException::new_err(e.to_string()))?;
-        if other_inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot merge with an active transaction",
-            ));
-        }
-        inner
-            .doc
-            .merge(&mut other_inner.doc)
-            .map(|change_hashes| change_hashes.into_iter().map(|h| PyChangeHash(h)).collect())
-            .map_err(|e| PyException::new_err(e.to_string()))
+        Ok(inner.doc.text_encoding().into())
     }
 
-    fn diff(
-        &self,
-        before_heads: Vec<PyChangeHash>,
-        after_heads: Vec<PyChangeHash>,
-    ) -> PyResult<Vec<PyPatch>> {
+    /// How timestamps are read from the document. Defaults to `TimestampMode.Naive`, which
+    /// returns naive datetimes in local time. `TimestampMode.Utc` returns timezone-aware
+    /// datetimes and `TimestampMode.Millis` returns integer milliseconds since the epoch, which
+    /// is cheapest.
+    #[getter]
+    fn timestamps(&self) -> PyResult<PyTimestampMode> {
         let inner = self
             .inner
             .read()
             .map_err(|e| PyException::new_err(e.to_string()))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot diff with an active transaction",
-            ));
-        }
-        let before_heads: Vec<ChangeHash> = before_heads.iter().map(|h| h.0).collect();
-        let after_heads: Vec<ChangeHash> = after_heads.iter().map(|h| h.0).collect();
-        Ok(inner
-            .doc
-            .diff(
-                &before_heads,
-                &after_heads,
-                am::patches::TextRepresentation::Array,
-            )
-            .into_iter()
-            .map(|p| PyPatch(p))
-            .collect())
+        Ok(inner.timestamps)
+    }
+
+    #[setter]
+    fn set_timestamps(&self, timestamps: PyTimestampMode) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        inner.timestamps = timestamps;
+        Ok(())
     }
 
-    fn generate_sync_message(&self, state: &mut PySyncState) -> PyResult<Option<PyMessage>> {
+    fn get_actor<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
         let inner = self
             .inner
             .read()
             .map_err(|e| PyException::new_err(e.to_string()))?;
         if inner.tx.is_some() {
             return Err(PyException::new_err(
-                "cannot sync with an active transaction",
+                "cannot get actor id with an active transaction",
             ));
         }
-        Ok(inner.doc.generate_sync_message(&mut state.0).map(PyMessage))
+
+        Ok(PyBytes::new(py, inner.doc.get_actor().to_bytes()))
     }
 
-    fn receive_sync_message(
-        &mut self,
-        state: &mut PySyncState,
-        message: &mut PyMessage,
-    ) -> PyResult<()> {
+    fn set_actor(&mut self, actor_id: &[u8]) -> PyResult<()> {
         let mut inner = self
             .inner
             .write()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
         if inner.tx.is_some() {
             return Err(PyException::new_err(
-                "cannot sync with an active transaction",
+                "cannot set actor with an active transaction",
             ));
         }
-        inner
-            .doc
-            .receive_sync_message(&mut state.0, message.0.clone())
-            .map_err(|e| PyException::new_err(e.to_string()))
+
+        inner.doc.set_actor(ActorId::from(actor_id));
+        Ok(())
+    }
+
+    /// Start a transaction. `message` and `timestamp` are recorded on the resulting change unless
+    /// overridden when calling `Transaction.commit`.
+    #[pyo3(signature = (message = None, timestamp = None))]
+    fn transaction(
+        &self,
+        message: Option<String>,
+        timestamp: Option<&PyDateTime>,
+    ) -> PyResult<Transaction> {
+        let timestamp = timestamp.map(datetime_to_timestamp).transpose()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err("transaction already active"));
+        }
+
+        // Here we're transmuting the lifetime of the transaction to `static`, which is okay
+        // because we are then storing the transaction in `Inner` which means the document will
+        // live as long as the transaction.
+        let tx = unsafe {
+            transmute::<am::transaction::Transaction<'_>, am::transaction::Transaction<'static>>(
+                inner.doc.transaction(),
+            )
+        };
+        inner.tx = Some(tx);
+        inner.tx_generation += 1;
+        Ok(Transaction {
+            inner: Arc::clone(&self.inner),
+            generation: inner.tx_generation,
+            message,
+            timestamp,
+        })
+    }
+
+    /// Call `callback` with a new transaction and commit it once the callback returns, or roll it
+    /// back if the callback raises. Returns the hash of the new change (None if nothing changed)
+    /// along with the patches it made.
+    #[pyo3(signature = (callback, message = None))]
+    fn change(
+        &self,
+        py: Python<'_>,
+        callback: &PyAny,
+        message: Option<String>,
+    ) -> PyResult<(Option<PyChangeHash>, Vec<PyPatch>)> {
+        let before = self.get_heads()?;
+        let tx = self.transaction(message, None)?;
+        if let Err(e) = callback.call1((tx.clone().into_py(py),)) {
+            // The callback may have finished the transaction itself.
+            if tx.is_active()? {
+                if let Err(rollback_err) = tx.rollback() {
+                    rollback_err.set_cause(py, Some(e));
+                    return Err(rollback_err);
+                }
+            }
+            return Err(e);
+        }
+        // If the callback committed the transaction itself there is no hash left to report.
+        let hash = if tx.is_active()? {
+            tx.commit(None, None)?
+        } else {
+            None
+        };
+        let after = self.get_heads()?;
+        Ok((hash, self.diff(before, after)?))
+    }
+
+    /// Revert the most recent local change that hasn't been undone yet, as a new change. Only
+    /// changes made by this document's own transactions are undone, never ones received from
+    /// other documents. Returns the hash of the new change, or None if there was nothing to undo.
+    fn undo(&self) -> PyResult<Option<PyChangeHash>> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot undo with an active transaction",
+            ));
+        }
+        let Some(hash) = inner.undo_stack.pop() else {
+            return Ok(None);
+        };
+        let inner = &mut *inner;
+        let undone = revert_change(&mut inner.doc, hash, &mut inner.replaced)
+            .map_err(|e| PyException::new_err(format!("error undoing: {}", e)))?;
+        inner.redo_stack.extend(undone);
+        inner.trim_history();
+        Ok(undone.map(PyChangeHash))
+    }
+
+    /// Revert the most recent `undo`, as a new change. Returns its hash, or None if there was
+    /// nothing to redo.
+    fn redo(&self) -> PyResult<Option<PyChangeHash>> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot redo with an active transaction",
+            ));
+        }
+        let Some(hash) = inner.redo_stack.pop() else {
+            return Ok(None);
+        };
+        let inner = &mut *inner;
+        let redone = revert_change(&mut inner.doc, hash, &mut inner.replaced)
+            .map_err(|e| PyException::new_err(format!("error redoing: {}", e)))?;
+        match redone {
+            Some(redone) => inner.push_undo(redone),
+            None => inner.trim_history(),
+        }
+        Ok(redone.map(PyChangeHash))
+    }
+
+    fn can_undo(&self) -> PyResult<bool> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(!inner.undo_stack.is_empty())
+    }
+
+    fn can_redo(&self) -> PyResult<bool> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(!inner.redo_stack.is_empty())
+    }
+
+    /// How many local changes `undo` can go back through. Defaults to 100.
+    #[getter]
+    fn undo_depth(&self) -> PyResult<usize> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.undo_depth)
+    }
+
+    #[setter]
+    fn set_undo_depth(&self, depth: usize) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        inner.undo_depth = depth;
+        inner.trim_history();
+        Ok(())
+    }
+
+    fn save<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        // The lock is taken with the GIL released, and released before the GIL is reacquired, so
+        // that a thread waiting on the lock can never be holding the GIL we need.
+        let bytes = py.allow_threads(|| {
+            let mut inner = self
+                .inner
+                .write()
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            if inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot save with an active transaction",
+                ));
+            }
+
+            inner.saved_heads = inner.doc.get_heads();
+            Ok(inner.doc.save())
+        })?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    /// Encode the changes made since the last call to `save` or `save_incremental`, or since the
+    /// document was loaded. The result can be appended to a previous save and passed to `load`, or
+    /// applied to another document with `load_incremental`.
+    fn save_incremental<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let bytes = py.allow_threads(|| {
+            let mut inner = self
+                .inner
+                .write()
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            if inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot save with an active transaction",
+                ));
+            }
+
+            let bytes = inner.doc.save_after(&inner.saved_heads);
+            inner.saved_heads = inner.doc.get_heads();
+            Ok(bytes)
+        })?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    /// Summary statistics for this document: the number of changes, ops, reachable objects
+    /// (including the root) and actors. Counting objects walks the whole document. The size in
+    /// bytes of a compressed `save` is only computed if `save_size` is true, since it means
+    /// encoding the whole document; otherwise `Stats.save_size` is None.
+    #[pyo3(signature = (save_size = false))]
+    fn stats(&self, save_size: bool) -> PyResult<PyStats> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot get stats with an active transaction",
+            ));
+        }
+
+        let stats = inner.doc.stats();
+        Ok(PyStats {
+            num_changes: stats.num_changes as usize,
+            num_ops: stats.num_ops as usize,
+            num_objects: count_objects(&inner.doc, &am::ROOT),
+            num_actors: stats.num_actors as usize,
+            save_size: save_size.then(|| inner.doc.save().len()),
+        })
+    }
+
+    /// Apply the output of `save` or `save_incremental` to this document.
+    fn load_incremental(&mut self, py: Python<'_>, bytes: &[u8]) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot load with an active transaction",
+            ));
+        }
+
+        let mut log = inner.patch_log();
+        inner
+            .doc
+            .load_incremental_log_patches(bytes, &mut log)
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let observed = inner.observed(&mut log);
+        drop(inner);
+        notify(py, observed)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (bytes, text_encoding = None, timestamps = None))]
+    fn load(
+        py: Python<'_>,
+        bytes: &[u8],
+        text_encoding: Option<PyTextEncoding>,
+        timestamps: Option<PyTimestampMode>,
+    ) -> PyResult<Self> {
+        let mut options = LoadOptions::new();
+        if let Some(encoding) = text_encoding {
+            options = options.text_encoding(encoding.into());
+        }
+        let doc = py
+            .allow_threads(|| am::Automerge::load_with_options(bytes, options))
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let mut inner = Inner::new(doc);
+        // Everything loaded from `bytes` is already saved by whoever produced them.
+        inner.saved_heads = inner.doc.get_heads();
+        if let Some(timestamps) = timestamps {
+            inner.timestamps = timestamps;
+        }
+        Ok(Self {
+            inner: Arc::new(RwLock::new(inner)),
+        })
+    }
+
+    /// Create a document whose root holds the contents of the JSON object `data`, written in a
+    /// single change.
+    #[staticmethod]
+    #[pyo3(signature = (data, actor_id = None))]
+    fn from_json(data: &str, actor_id: Option<&[u8]>) -> PyResult<Self> {
+        let value: serde_json::Value =
+            serde_json::from_str(data).map_err(|e| PyException::new_err(e.to_string()))?;
+        if !value.is_object() {
+            return Err(PyException::new_err(
+                "from_json requires a JSON object at the top level",
+            ));
+        }
+        let doc = Document::new(actor_id, None, None);
+        {
+            let mut inner = doc
+                .inner
+                .write()
+                .expect("new document lock is not poisoned");
+            let mut tx = inner.doc.transaction();
+            populate_json(&mut tx, &am::ROOT, &value)
+                .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+            tx.commit();
+        }
+        Ok(doc)
+    }
+
+    /// Create a document whose root holds the contents of the dict `obj`, written in a single
+    /// change. Nested values are imported as by `Transaction.put_object`.
+    #[staticmethod]
+    #[pyo3(signature = (obj, actor_id = None))]
+    fn from_py(obj: &PyDict, actor_id: Option<&[u8]>) -> PyResult<Self> {
+        let doc = Document::new(actor_id, None, None);
+        {
+            let mut inner = doc
+                .inner
+                .write()
+                .expect("new document lock is not poisoned");
+            let mut tx = inner.doc.transaction();
+            populate(&mut tx, &am::ROOT, ObjType::Map, Some(obj))?;
+            tx.commit();
+        }
+        Ok(doc)
+    }
+
+    /// Copy this document, optionally as it was at `heads`. The fork gets `actor_id` as its actor,
+    /// or a new random actor if none is given, so it never shares an actor with this document.
+    #[pyo3(signature = (heads = None, actor_id = None))]
+    fn fork(
+        &self,
+        py: Python<'_>,
+        heads: Option<Vec<PyChangeHash>>,
+        actor_id: Option<&[u8]>,
+    ) -> PyResult<Document> {
+        let heads = get_heads(heads);
+        let (new_doc, timestamps) = py.allow_threads(|| {
+            let inner = self
+                .inner
+                .read()
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            if inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot fork with an active transaction",
+                ));
+            }
+            match heads {
+                Some(heads) => inner.doc.fork_at(&heads),
+                None => Ok(inner.doc.fork()),
+            }
+            .map(|doc| (doc, inner.timestamps))
+            .map_err(|e| PyException::new_err(e.to_string()))
+        })?;
+        let new_doc = match actor_id {
+            Some(id) => new_doc.with_actor(ActorId::from(id)),
+            None => new_doc,
+        };
+        let mut inner = Inner::new(new_doc);
+        inner.timestamps = timestamps;
+        Ok(Document {
+            inner: Arc::new(RwLock::new(inner)),
+        })
+    }
+
+    /// Copy this document as it was at `heads`. Equivalent to `fork(heads, actor_id)`.
+    #[pyo3(signature = (heads, actor_id = None))]
+    fn fork_at(
+        &self,
+        py: Python<'_>,
+        heads: Vec<PyChangeHash>,
+        actor_id: Option<&[u8]>,
+    ) -> PyResult<Document> {
+        self.fork(py, Some(heads), actor_id)
+    }
+
+    /// Merge the changes from `other` into this document and return its new heads. With
+    /// `return_patches=True` the result is a `(heads, patches)` tuple that also holds the patches
+    /// the merge made to this document.
+    #[pyo3(signature = (other, return_patches = false))]
+    fn merge(&self, py: Python<'_>, other: &Document, return_patches: bool) -> PyResult<PyObject> {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
+            return Err(PyException::new_err("cannot merge a document with itself"));
+        }
+        let (change_hashes, patches, observed) = py.allow_threads(|| {
+            let (mut inner, mut other_inner) = write_both(&self.inner, &other.inner)?;
+            if inner.tx.is_some() || other_inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot merge with an active transaction",
+                ));
+            }
+            let mut log = inner.patch_log_returning(return_patches);
+            let change_hashes = inner
+                .doc
+                .merge_and_log_patches(&mut other_inner.doc, &mut log)
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            let (patches, observed) = inner.observed_returning(&mut log, return_patches);
+            Ok((change_hashes, patches, observed))
+        })?;
+        notify(py, observed)?;
+        let change_hashes: Vec<PyChangeHash> =
+            change_hashes.into_iter().map(PyChangeHash).collect();
+        Ok(match patches {
+            Some(patches) => (change_hashes, patches).into_py(py),
+            None => change_hashes.into_py(py),
+        })
+    }
+
+    /// Apply changes obtained from another document's `get_changes`, given either as `Change`
+    /// objects or as their encoded bytes.
+    fn apply_changes(&mut self, py: Python<'_>, changes: Vec<&PyAny>) -> PyResult<()> {
+        let changes = changes
+            .into_iter()
+            .map(import_change)
+            .collect::<PyResult<Vec<_>>>()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot apply changes with an active transaction",
+            ));
+        }
+        let mut log = inner.patch_log();
+        inner
+            .doc
+            .apply_changes_log_patches(changes, &mut log)
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let observed = inner.observed(&mut log);
+        drop(inner);
+        notify(py, observed)
+    }
+
+    fn diff(
+        &self,
+        before_heads: Vec<PyChangeHash>,
+        after_heads: Vec<PyChangeHash>,
+    ) -> PyResult<Vec<PyPatch>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot diff with an active transaction",
+            ));
+        }
+        let before_heads: Vec<ChangeHash> = before_heads.iter().map(|h| h.0).collect();
+        let after_heads: Vec<ChangeHash> = after_heads.iter().map(|h| h.0).collect();
+        Ok(inner.py_patches(inner.doc.diff(&before_heads, &after_heads)))
+    }
+
+    /// The patches made since the last call to `diff_incremental`, or since the document was
+    /// created if this is the first call.
+    fn diff_incremental(&self) -> PyResult<Vec<PyPatch>> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot diff with an active transaction",
+            ));
+        }
+        let heads = inner.doc.get_heads();
+        let patches = inner.doc.diff(&inner.diff_heads, &heads);
+        inner.diff_heads = heads;
+        Ok(inner.py_patches(patches))
+    }
+
+    /// Which parts of the text `obj_id` each of `change_sets` added to or deleted from the text
+    /// as of `baseline`. Each change set is a list of change hashes whose dependencies are all in
+    /// `baseline` or in the set itself.
+    ///
+    /// Returns an `Attribution` per change set. Positions refer to the text with the baseline and
+    /// every change set applied, and deleted text is placed where it would have been.
+    fn attribute(
+        &self,
+        obj_id: PyObjId,
+        baseline: Vec<PyChangeHash>,
+        change_sets: Vec<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<PyAttribution>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot attribute with an active transaction",
+            ));
+        }
+        let doc = &inner.doc;
+        let obj = &obj_id.0;
+        if doc.object_type(obj) != Ok(ObjType::Text) {
+            return Err(PyException::new_err("can only attribute text objects"));
+        }
+
+        let baseline: Vec<ChangeHash> = baseline.into_iter().map(|h| h.0).collect();
+        let change_sets: Vec<Vec<ChangeHash>> = change_sets
+            .into_iter()
+            .map(|set| {
+                baseline
+                    .iter()
+                    .copied()
+                    .chain(set.into_iter().map(|h| h.0))
+                    .collect()
+            })
+            .collect();
+        let mut all: Vec<ChangeHash> = change_sets.iter().flatten().copied().collect();
+        all.sort();
+        all.dedup();
+        inner.check_known(&baseline)?;
+        inner.check_known(&all)?;
+        let visible = |heads: &[ChangeHash]| -> PyResult<HashSet<Vec<u8>>> {
+            Ok(text_elements(doc, obj, heads)
+                .map_err(|e| PyException::new_err(e.to_string()))?
+                .into_iter()
+                .map(|(cursor, _, _)| cursor)
+                .collect())
+        };
+        let before =
+            text_elements(doc, obj, &baseline).map_err(|e| PyException::new_err(e.to_string()))?;
+        let after =
+            text_elements(doc, obj, &all).map_err(|e| PyException::new_err(e.to_string()))?;
+        let after_len = doc.length_at(obj, &all);
+        let in_sets = change_sets
+            .iter()
+            .map(|heads| visible(heads))
+            .collect::<PyResult<Vec<_>>>()?;
+        let in_before: HashSet<&Vec<u8>> = before.iter().map(|(cursor, _, _)| cursor).collect();
+        let positions: HashMap<&Vec<u8>, usize> = after
+            .iter()
+            .map(|(cursor, pos, _)| (cursor, *pos))
+            .collect();
+
+        let mut attributions = vec![PyAttribution::default(); change_sets.len()];
+        for (i, (cursor, pos, _)) in after.iter().enumerate() {
+            if in_before.contains(cursor) {
+                continue;
+            }
+            let Some(set) = in_sets.iter().position(|set| set.contains(cursor)) else {
+                continue;
+            };
+            let end = after.get(i + 1).map_or(after_len, |(_, next, _)| *next);
+            let added = &mut attributions[set].added;
+            match added.last_mut() {
+                Some(last) if last.1 == *pos => last.1 = end,
+                _ => added.push((*pos, end)),
+            }
+        }
+        for (i, (cursor, _, text)) in before.iter().enumerate() {
+            if positions.contains_key(cursor) {
+                continue;
+            }
+            let Some(set) = in_sets.iter().position(|set| !set.contains(cursor)) else {
+                continue;
+            };
+            let pos = before[i + 1..]
+                .iter()
+                .find_map(|(next, _, _)| positions.get(next).copied())
+                .unwrap_or(after_len);
+            let deleted = &mut attributions[set].deleted;
+            match deleted.last_mut() {
+                Some(last) if last.0 == pos => last.1.push_str(text),
+                _ => deleted.push((pos, text.clone())),
+            }
+        }
+        Ok(attributions)
+    }
+
+    /// The next message to send to the peer whose sync state is `state`, or None if there is
+    /// nothing to send. With `max_message_size`, changes which would take the encoded message over
+    /// that many bytes are left for later messages, though a message always includes at least one
+    /// change if it has any to send.
+    #[pyo3(signature = (state, max_message_size = None))]
+    fn generate_sync_message(
+        &self,
+        py: Python<'_>,
+        state: &mut PySyncState,
+        max_message_size: Option<usize>,
+    ) -> PyResult<Option<PyMessage>> {
+        py.allow_threads(|| {
+            let inner = self
+                .inner
+                .read()
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            if inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot sync with an active transaction",
+                ));
+            }
+            let sent = state.0.sent_hashes.clone();
+            let Some(message) = inner.doc.generate_sync_message(&mut state.0) else {
+                return Ok(None);
+            };
+            Ok(Some(PyMessage(match max_message_size {
+                Some(max) => limit_message_size(&inner.doc, &mut state.0, &sent, message, max),
+                None => message,
+            })))
+        })
+    }
+
+    /// Apply a sync message from a peer. With `return_patches=True` the patches it made to this
+    /// document are returned, otherwise None.
+    #[pyo3(signature = (state, message, return_patches = false))]
+    fn receive_sync_message(
+        &self,
+        py: Python<'_>,
+        state: &mut PySyncState,
+        message: &PyMessage,
+        return_patches: bool,
+    ) -> PyResult<Option<Vec<PyPatch>>> {
+        let message = message.0.clone();
+        let (patches, observed) = py.allow_threads(|| {
+            let mut inner = self
+                .inner
+                .write()
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            if inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot sync with an active transaction",
+                ));
+            }
+            let mut log = inner.patch_log_returning(return_patches);
+            inner
+                .doc
+                .receive_sync_message_log_patches(&mut state.0, message, &mut log)
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            Ok(inner.observed_returning(&mut log, return_patches))
+        })?;
+        notify(py, observed)?;
+        Ok(patches)
+    }
+
+    /// Sync this document with `other` until neither has anything left to send, as if they were
+    /// peers starting with fresh sync states. Returns the number of rounds and the total number of
+    /// messages exchanged, or raises if they haven't converged after 100 rounds.
+    fn sync_with(&self, py: Python<'_>, other: &Document) -> PyResult<(usize, usize)> {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
+            return Ok((0, 0));
+        }
+        let (rounds, messages, observed, other_observed) = py.allow_threads(|| {
+            let (mut inner, mut other_inner) = write_both(&self.inner, &other.inner)?;
+            if inner.tx.is_some() || other_inner.tx.is_some() {
+                return Err(PyException::new_err(
+                    "cannot sync with an active transaction",
+                ));
+            }
+            let (mut state, mut other_state) = (am::sync::State::new(), am::sync::State::new());
+            let (mut log, mut other_log) = (inner.patch_log(), other_inner.patch_log());
+            let (mut rounds, mut messages) = (0, 0);
+            loop {
+                let mut sent = false;
+                if let Some(message) = inner.doc.generate_sync_message(&mut state) {
+                    other_inner
+                        .doc
+                        .receive_sync_message_log_patches(&mut other_state, message, &mut other_log)
+                        .map_err(|e| PyException::new_err(e.to_string()))?;
+                    (sent, messages) = (true, messages + 1);
+                }
+                if let Some(message) = other_inner.doc.generate_sync_message(&mut other_state) {
+                    inner
+                        .doc
+                        .receive_sync_message_log_patches(&mut state, message, &mut log)
+                        .map_err(|e| PyException::new_err(e.to_string()))?;
+                    (sent, messages) = (true, messages + 1);
+                }
+                if !sent {
+                    break;
+                }
+                rounds += 1;
+                if rounds == MAX_SYNC_ROUNDS {
+                    return Err(PyException::new_err(format!(
+                        "documents did not converge after {} sync rounds",
+                        MAX_SYNC_ROUNDS
+                    )));
+                }
+            }
+            Ok((
+                rounds,
+                messages,
+                inner.observed(&mut log),
+                other_inner.observed(&mut other_log),
+            ))
+        })?;
+        notify(py, observed)?;
+        notify(py, other_observed)?;
+        Ok((rounds, messages))
+    }
+
+    /// Register `callback` to be called with a list of patches whenever `merge`, `apply_changes`,
+    /// `receive_sync_message` or `load_incremental` change the document. Pass None to remove it.
+    fn on_patch(&self, callback: Option<PyObject>) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.on_patch = callback;
+        Ok(())
+    }
+
+    fn get_heads(&self) -> PyResult<Vec<PyChangeHash>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.get_heads())
+    }
+
+    fn get_last_local_change(&self) -> PyResult<Option<PyChange>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner
+            .doc
+            .get_last_local_change()
+            .map(|c| PyChange(c.to_owned())))
+    }
+
+    fn object_type(&self, obj_id: PyObjId) -> PyResult<PyObjType> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.object_type(obj_id)
+    }
+
+    fn get_changes(&self, have_deps: Vec<PyChangeHash>) -> PyResult<Vec<PyChange>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot get changes with an active transaction",
+            ));
+        }
+
+        let changes: Vec<ChangeHash> = have_deps.iter().map(|h| h.0).collect();
+        Ok(inner
+            .doc
+            .get_changes(&changes)
+            .into_iter()
+            .map(|c| PyChange(c.to_owned()))
+            .collect())
+    }
+
+    fn get_change_by_hash(&self, hash: PyChangeHash) -> PyResult<Option<PyChange>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.doc.get_change_by_hash(&hash.0).map(PyChange))
+    }
+
+    /// Every change in the document in topological order, each paired with the heads of the
+    /// document once it and the changes before it are applied. With `patches`, each entry also
+    /// includes the patches which that change made.
+    #[pyo3(signature = (patches = false))]
+    fn history(&self, py: Python<'_>, patches: bool) -> PyResult<Vec<PyObject>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot get history with an active transaction",
+            ));
+        }
+
+        let mut heads: Vec<ChangeHash> = Vec::new();
+        let mut history = Vec::new();
+        for change in inner.doc.get_changes(&[]) {
+            let before = heads.clone();
+            heads.retain(|head| !change.deps().contains(head));
+            heads.push(change.hash());
+            heads.sort();
+            let change = PyChange(change.to_owned());
+            let heads_after: Vec<PyChangeHash> = heads.iter().copied().map(PyChangeHash).collect();
+            history.push(if patches {
+                let patches = inner.py_patches(inner.doc.diff(&before, &heads));
+                (change, heads_after, patches).into_py(py)
+            } else {
+                (change, heads_after).into_py(py)
+            });
+        }
+        Ok(history)
+    }
+
+    /// Changes which are in `other` but not in this document.
+    fn get_changes_added(&self, other: &Document) -> PyResult<Vec<PyChange>> {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
+            return Ok(Vec::new());
+        }
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let other_inner = other
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner
+            .doc
+            .get_changes_added(&other_inner.doc)
+            .into_iter()
+            .map(PyChange)
+            .collect())
+    }
+
+    /// A SHA-256 digest of the sorted heads, which is the same for any two documents with the same
+    /// changes.
+    fn fingerprint<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let mut heads = inner.doc.get_heads();
+        heads.sort();
+        let mut hasher = Sha256::new();
+        for head in heads {
+            hasher.update(head.as_ref());
+        }
+        Ok(PyBytes::new(py, &hasher.finalize()))
+    }
+
+    /// Hashes of changes that are depended on but not yet in this document, including any of
+    /// `heads` that are missing. Changes with missing dependencies are queued by `apply_changes`
+    /// until those dependencies arrive.
+    #[pyo3(signature = (heads = None))]
+    fn get_missing_deps(&self, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyChangeHash>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot get missing deps with an active transaction",
+            ));
+        }
+
+        let heads = get_heads(heads).unwrap_or_default();
+        Ok(inner
+            .doc
+            .get_missing_deps(&heads)
+            .into_iter()
+            .map(PyChangeHash)
+            .collect())
+    }
+
+    /// `get(obj_id, prop, heads=None)`, where `obj_id` may be left out to read from the root and
+    /// `prop` may be a tuple of props leading through nested objects.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<(PyValue<'_>, PyObjId)>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let path = prop.required("get", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some((obj_id, prop)) = inner.follow(obj_id, path, &heads)? else {
+            return Ok(None);
+        };
+        inner.get(obj_id, prop, heads)
+    }
+
+    /// `get_all(obj_id, prop, heads=None)`, where `obj_id` may be left out to read from the root and
+    /// `prop` may be a tuple of props leading through nested objects.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_all(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Vec<(PyValue<'_>, PyObjId)>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let path = prop.required("get_all", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some((obj_id, prop)) = inner.follow(obj_id, path, &heads)? else {
+            return Ok(Vec::new());
+        };
+        inner.get_all(obj_id, prop, heads)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_str(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<String>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_str", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "str", expect_str)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_int(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<i128>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_int", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "int", expect_int)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_bool(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<bool>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_bool", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "boolean", expect_bool)
+    }
+
+    /// With `zero_copy`, returns a read-only memoryview over the bytes rather than a copy of them.
+    /// Reading the same value again returns a view of the same buffer while it's still alive.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None), zero_copy = false))]
+    fn get_bytes(
+        &self,
+        py: Python<'_>,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+        zero_copy: bool,
+    ) -> PyResult<Option<PyObject>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_bytes", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if zero_copy {
+            let shared = inner.get_shared_bytes(obj_id, prop, heads)?;
+            drop(inner);
+            return shared.map(|b| PyBytesBuffer(b).memoryview(py)).transpose();
+        }
+        let bytes = inner.get_typed(obj_id, prop, heads, "bytes", expect_bytes)?;
+        Ok(bytes.map(|b| PyBytes::new(py, &b).into()))
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_map(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<PyObjId>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_map", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "map", expect_map)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_list(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<PyObjId>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_list", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "list", expect_list)
+    }
+
+    /// The value of `prop` in `obj_id`, with scalars as plain Python values and objects as their
+    /// ID, or `default` if `prop` is missing. `obj_id` may be left out to read from the root.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), default = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_or(
+        &self,
+        py: Python<'_>,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        default: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<PyObject> {
+        let (obj_id, [prop, default, heads]) = root_args(obj_id, [prop, default, heads])?;
+        let prop = prop.required("get_or", "prop")?.extract()?;
+        let default = default.required("get_or", "default")?.into_py(py);
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_or(py, obj_id, prop, default, heads)
+    }
+
+    /// Whether `obj_id` has a value at `prop`.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn has(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<bool> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("has", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some(prop) = inner.resolve_key(&obj_id, prop, &heads) else {
+            return Ok(false);
+        };
+        Ok(inner.get(obj_id, prop, heads)?.is_some())
+    }
+
+    /// Iterate lazily over the keys of a map, or the element IDs of a list or text object.
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn keys(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<PyRange> {
+        PyRange::whole(&self.inner, obj_id, RangeYield::Keys, heads)
+    }
+
+    /// Iterate lazily over the `(key, value, obj_id)` entries of the map `obj_id` with keys from
+    /// `start_key` (inclusive) to `end_key` (exclusive).
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), start_key = None, end_key = None, heads = None))]
+    fn map_range(
+        &self,
+        obj_id: PyObjId,
+        start_key: Option<String>,
+        end_key: Option<String>,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyRange {
+        let start = start_key.map_or(Bound::Unbounded, Bound::Included);
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::Map {
+                start,
+                end: end_key,
+            },
+            RangeYield::Entries,
+            heads,
+        )
+    }
+
+    /// Iterate lazily over the `(index, value, obj_id)` entries of the list or text `obj_id` from
+    /// `start` (inclusive) to `end` (exclusive).
+    #[pyo3(signature = (obj_id, start = None, end = None, heads = None))]
+    fn list_range(
+        &self,
+        obj_id: PyObjId,
+        start: Option<usize>,
+        end: Option<usize>,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyRange {
+        let start = start.unwrap_or(0);
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::list(start, end),
+            RangeYield::Entries,
+            heads,
+        )
+    }
+
+    /// Resolve `path` from the root, returning the value and ID found there or None if any part of
+    /// the path is missing. `path` is either a sequence of props like `("todos", 3, "title")` or
+    /// a string like `"/todos/3/title"`, in which numeric segments index into lists and text.
+    fn lookup(
+        &self,
+        path: &PyAny,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Option<(PyValue<'_>, PyObjId)>> {
+        let path = extract_path(path)?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.lookup(path, heads)
+    }
+
+    /// The `(path, value, obj_id)` matches of the JSONPath-like `expr`, such as
+    /// `$.todos[*].title` or `$.todos[?(@.done == false)]`. Supported are child keys and indices,
+    /// `*` wildcards, and filters comparing a relative path with a string, number, boolean or
+    /// null using `==` or `!=`.
+    #[pyo3(signature = (expr, heads = None))]
+    fn query(
+        &self,
+        expr: &str,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<(Vec<PyProp>, PyValue<'_>, PyObjId)>> {
+        let steps = parse_query(expr)?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.query(&steps, heads)
+    }
+
+    /// The parents of `obj_id` as `(parent, prop)` pairs, starting with its immediate parent and
+    /// ending with the root.
+    fn parents(
+        &self,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<(PyObjId, PyProp)>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.parents(obj_id, heads)
+    }
+
+    /// The props leading from the root to `obj_id`.
+    fn path(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyProp>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let parents = inner.parents(obj_id, heads)?;
+        Ok(parents.into_iter().rev().map(|(_, prop)| prop).collect())
+    }
+
+    /// Iterate lazily over the `(value, obj_id)` pairs of a map, list or text object.
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn values(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<PyRange> {
+        PyRange::whole(&self.inner, obj_id, RangeYield::Values, heads)
+    }
+
+    /// Iterate lazily over the `(value, obj_id)` pairs of the list or text `obj_id` from `start`
+    /// (inclusive) to `end` (exclusive). Elements outside the range are never read.
+    #[pyo3(signature = (obj_id, start, end, heads = None))]
+    fn values_range(
+        &self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyRange {
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::list(start, Some(end)),
+            RangeYield::Values,
+            heads,
+        )
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn length(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<usize> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.length(obj_id, heads))
+    }
+
+    fn text(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<String> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.text(obj_id, heads)
+    }
+
+    /// The text of `obj_id` from `start` (inclusive) to `end` (exclusive), without reading the rest
+    /// of the object.
+    #[pyo3(signature = (obj_id, start, end, heads = None))]
+    fn text_range(
+        &self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<String> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.text_range(obj_id, start, end, heads))
+    }
+
+    fn marks(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyMark>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.marks(obj_id, heads)
+    }
+
+    /// The marks active on the character at `index`, as a `{name: value}` dict.
+    fn marks_at(
+        &self,
+        obj_id: PyObjId,
+        index: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<HashMap<String, PyScalarValue>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.marks_at(obj_id, index, heads)
+    }
+
+    fn get_cursor(
+        &self,
+        obj_id: PyObjId,
+        position: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyCursor> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_cursor(obj_id, position, heads)
+    }
+
+    /// The positions of the non-overlapping occurrences of `needle` in the text `obj_id`, in the
+    /// document's text encoding. With `cursors` set each match is a `(position, cursor)` pair
+    /// instead.
+    #[pyo3(signature = (obj_id, needle, heads = None, cursors = false))]
+    fn find_text(
+        &self,
+        py: Python<'_>,
+        obj_id: PyObjId,
+        needle: &str,
+        heads: Option<Vec<PyChangeHash>>,
+        cursors: bool,
+    ) -> PyResult<Vec<PyObject>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let found = inner.find_text(obj_id, needle, heads, cursors)?;
+        Ok(found
+            .into_iter()
+            .map(|(pos, cursor)| match cursor {
+                Some(cursor) => (pos, cursor).into_py(py),
+                None => pos.into_py(py),
+            })
+            .collect())
+    }
+
+    fn get_cursor_position(
+        &self,
+        obj_id: PyObjId,
+        cursor: PyCursor,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<usize> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_cursor_position(obj_id, cursor, heads)
+    }
+
+    fn spans(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PySpan>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.spans(obj_id, heads)
+    }
+
+    /// A read-only view of this document as it was at `heads`, whose reads all default to those
+    /// heads.
+    fn view_at(&self, heads: Vec<PyChangeHash>) -> PyResult<DocumentView> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if inner.tx.is_some() {
+            return Err(PyException::new_err(
+                "cannot create a view with an active transaction",
+            ));
+        }
+        let heads: Vec<ChangeHash> = heads.into_iter().map(|h| h.0).collect();
+        inner.check_known(&heads)?;
+        Ok(DocumentView {
+            inner: Arc::clone(&self.inner),
+            heads,
+        })
+    }
+
+    /// The root map as a `MapProxy`. Indexing the document itself is shorthand for indexing its
+    /// root, e.g. `doc["config"]["flags"][0]`.
+    #[getter]
+    fn root(&self) -> PyMapProxy {
+        PyMapProxy(Proxy::root(&self.inner, None, None))
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: String) -> PyResult<PyObject> {
+        self.root().__getitem__(py, key)
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &PyAny) -> PyResult<bool> {
+        self.root().__contains__(py, key)
+    }
+
+    fn __iter__(&self) -> PyResult<PyRange> {
+        self.root().keys()
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn to_py(
+        &self,
+        py: Python<'_>,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyObject> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.to_py(py, obj_id, heads)
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None, indent = None, counters = "number", timestamps = "millis", bytes = "hex"))]
+    fn to_json(
+        &self,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+        indent: Option<usize>,
+        counters: &str,
+        timestamps: &str,
+        bytes: &str,
+    ) -> PyResult<String> {
+        let options = JsonOptions::new(counters, timestamps, bytes)?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let value = inner.to_json(obj_id, heads, &options)?;
+        json_to_string(&value, indent)
+    }
+}
+
+/// A read-only view of a document as it was at `heads`. Every read defaults to those heads.
+#[pyclass]
+struct DocumentView {
+    inner: Arc<RwLock<Inner>>,
+    heads: Vec<ChangeHash>,
+}
+
+impl DocumentView {
+    fn read(&self) -> PyResult<RwLockReadGuard<'_, Inner>> {
+        self.inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    fn heads_arg(&self) -> Option<Vec<PyChangeHash>> {
+        Some(self.heads.iter().copied().map(PyChangeHash).collect())
+    }
+}
+
+#[pymethods]
+impl DocumentView {
+    #[getter]
+    fn heads(&self) -> Vec<PyChangeHash> {
+        self.heads.iter().copied().map(PyChangeHash).collect()
+    }
+
+    fn object_type(&self, obj_id: PyObjId) -> PyResult<PyObjType> {
+        self.read()?.object_type(obj_id)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None)))]
+    fn get(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+    ) -> PyResult<Option<(PyValue<'_>, PyObjId)>> {
+        let (obj_id, [prop]) = root_args(obj_id, [prop])?;
+        let path = prop.required("get", "prop")?.extract()?;
+        let inner = self.read()?;
+        let heads = self.heads_arg();
+        let Some((obj_id, prop)) = inner.follow(obj_id, path, &heads)? else {
+            return Ok(None);
+        };
+        inner.get(obj_id, prop, heads)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None)))]
+    fn get_all(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+    ) -> PyResult<Vec<(PyValue<'_>, PyObjId)>> {
+        let (obj_id, [prop]) = root_args(obj_id, [prop])?;
+        let path = prop.required("get_all", "prop")?.extract()?;
+        let inner = self.read()?;
+        let heads = self.heads_arg();
+        let Some((obj_id, prop)) = inner.follow(obj_id, path, &heads)? else {
+            return Ok(Vec::new());
+        };
+        inner.get_all(obj_id, prop, heads)
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT)))]
+    fn keys(&self, obj_id: PyObjId) -> PyResult<PyRange> {
+        PyRange::whole(&self.inner, obj_id, RangeYield::Keys, self.heads_arg())
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT)))]
+    fn values(&self, obj_id: PyObjId) -> PyResult<PyRange> {
+        PyRange::whole(&self.inner, obj_id, RangeYield::Values, self.heads_arg())
+    }
+
+    fn values_range(&self, obj_id: PyObjId, start: usize, end: usize) -> PyRange {
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::list(start, Some(end)),
+            RangeYield::Values,
+            self.heads_arg(),
+        )
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), start_key = None, end_key = None))]
+    fn map_range(
+        &self,
+        obj_id: PyObjId,
+        start_key: Option<String>,
+        end_key: Option<String>,
+    ) -> PyRange {
+        let start = start_key.map_or(Bound::Unbounded, Bound::Included);
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::Map {
+                start,
+                end: end_key,
+            },
+            RangeYield::Entries,
+            self.heads_arg(),
+        )
+    }
+
+    #[pyo3(signature = (obj_id, start = None, end = None))]
+    fn list_range(&self, obj_id: PyObjId, start: Option<usize>, end: Option<usize>) -> PyRange {
+        let start = start.unwrap_or(0);
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::list(start, end),
+            RangeYield::Entries,
+            self.heads_arg(),
+        )
+    }
+
+    fn lookup(&self, path: &PyAny) -> PyResult<Option<(PyValue<'_>, PyObjId)>> {
+        let path = extract_path(path)?;
+        self.read()?.lookup(path, self.heads_arg())
+    }
+
+    fn query(&self, expr: &str) -> PyResult<Vec<(Vec<PyProp>, PyValue<'_>, PyObjId)>> {
+        let steps = parse_query(expr)?;
+        self.read()?.query(&steps, self.heads_arg())
+    }
+
+    fn parents(&self, obj_id: PyObjId) -> PyResult<Vec<(PyObjId, PyProp)>> {
+        self.read()?.parents(obj_id, self.heads_arg())
+    }
+
+    fn path(&self, obj_id: PyObjId) -> PyResult<Vec<PyProp>> {
+        let parents = self.read()?.parents(obj_id, self.heads_arg())?;
+        Ok(parents.into_iter().rev().map(|(_, prop)| prop).collect())
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT)))]
+    fn length(&self, obj_id: PyObjId) -> PyResult<usize> {
+        Ok(self.read()?.length(obj_id, self.heads_arg()))
+    }
+
+    fn text(&self, obj_id: PyObjId) -> PyResult<String> {
+        self.read()?.text(obj_id, self.heads_arg())
+    }
+
+    fn text_range(&self, obj_id: PyObjId, start: usize, end: usize) -> PyResult<String> {
+        Ok(self
+            .read()?
+            .text_range(obj_id, start, end, self.heads_arg()))
+    }
+
+    fn marks(&self, obj_id: PyObjId) -> PyResult<Vec<PyMark>> {
+        self.read()?.marks(obj_id, self.heads_arg())
+    }
+
+    fn marks_at(&self, obj_id: PyObjId, index: usize) -> PyResult<HashMap<String, PyScalarValue>> {
+        self.read()?.marks_at(obj_id, index, self.heads_arg())
+    }
+
+    fn spans(&self, obj_id: PyObjId) -> PyResult<Vec<PySpan>> {
+        self.read()?.spans(obj_id, self.heads_arg())
+    }
+
+    #[getter]
+    fn root(&self) -> PyMapProxy {
+        PyMapProxy(Proxy::root(&self.inner, None, Some(self.heads.clone())))
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: String) -> PyResult<PyObject> {
+        self.root().__getitem__(py, key)
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &PyAny) -> PyResult<bool> {
+        self.root().__contains__(py, key)
+    }
+
+    fn __iter__(&self) -> PyResult<PyRange> {
+        self.root().keys()
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT)))]
+    fn to_py(&self, py: Python<'_>, obj_id: PyObjId) -> PyResult<PyObject> {
+        self.read()?.to_py(py, obj_id, self.heads_arg())
+    }
+}
+
+#[derive(Clone)]
+#[pyclass]
+struct Transaction {
+    inner: Arc<RwLock<Inner>>,
+    /// The `tx_generation` of the transaction this handle started.
+    generation: u64,
+    message: Option<String>,
+    timestamp: Option<i64>,
+}
+
+impl Transaction {
+    fn commit_options(&self, message: Option<String>, timestamp: Option<i64>) -> CommitOptions {
+        let mut options = CommitOptions::default();
+        if let Some(message) = message.or_else(|| self.message.clone()) {
+            options.set_message(message);
+        }
+        if let Some(timestamp) = timestamp.or(self.timestamp) {
+            options.set_time(timestamp);
+        }
+        options
+    }
+
+    /// Whether this transaction is still open, rather than committed or rolled back.
+    fn is_active(&self) -> PyResult<bool> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        Ok(inner.active_tx(self.generation).is_ok())
+    }
+}
+
+#[pymethods]
+impl Transaction {
+    #[pyo3(name = "__enter__")]
+    fn enter(&self) -> PyResult<Transaction> {
+        Ok(self.clone())
+    }
+
+    #[pyo3(name = "__exit__")]
+    fn exit(
+        &self,
+        exc_type: Option<&PyAny>,
+        _exc_value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        if inner.tx.is_none() {
+            // Already committed or rolled back inside the block.
+            return Ok(());
+        }
+        if inner.tx_generation != self.generation {
+            // Leave another transaction alone, and don't mask an exception raised in the block.
+            return match exc_type {
+                Some(_) => Ok(()),
+                None => Err(PyException::new_err(
+                    "transaction no longer active, another transaction was started",
+                )),
+            };
+        }
+        let tx = inner.take_tx(self.generation)?;
+        if exc_type.is_some() {
+            tx.rollback();
+        } else {
+            let (hash, _) = tx.commit_with(self.commit_options(None, None));
+            inner.record_local(hash);
+        }
+        Ok(())
+    }
+
+    /// Commit the transaction, optionally overriding the message and timestamp given when it was
+    /// started, and return the hash of the new change (or None if nothing was changed). Leaving
+    /// a `with` block afterwards does nothing.
+    #[pyo3(signature = (message = None, timestamp = None))]
+    fn commit(
+        &self,
+        message: Option<String>,
+        timestamp: Option<&PyDateTime>,
+    ) -> PyResult<Option<PyChangeHash>> {
+        let timestamp = timestamp.map(datetime_to_timestamp).transpose()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.take_tx(self.generation)?;
+        let (hash, _) = tx.commit_with(self.commit_options(message, timestamp));
+        inner.record_local(hash);
+        Ok(hash.map(PyChangeHash))
+    }
+
+    /// Discard every operation in the transaction.
+    fn rollback(&self) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.take_tx(self.generation)?;
+        tx.rollback();
+        Ok(())
+    }
+
+    fn get_heads(&self) -> PyResult<Vec<PyChangeHash>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.get_heads())
+    }
+
+    fn object_type(&self, obj_id: PyObjId) -> PyResult<PyObjType> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.object_type(obj_id)
+    }
+
+    /// `get(obj_id, prop, heads=None)`, where `obj_id` may be left out to read from the root and
+    /// `prop` may be a tuple of props leading through nested objects.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<(PyValue<'_>, PyObjId)>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let path = prop.required("get", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some((obj_id, prop)) = inner.follow(obj_id, path, &heads)? else {
+            return Ok(None);
+        };
+        inner.get(obj_id, prop, heads)
+    }
+
+    /// `get_all(obj_id, prop, heads=None)`, where `obj_id` may be left out to read from the root and
+    /// `prop` may be a tuple of props leading through nested objects.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_all(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Vec<(PyValue<'_>, PyObjId)>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let path = prop.required("get_all", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some((obj_id, prop)) = inner.follow(obj_id, path, &heads)? else {
+            return Ok(Vec::new());
+        };
+        inner.get_all(obj_id, prop, heads)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_str(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<String>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_str", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "str", expect_str)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_int(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<i128>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_int", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "int", expect_int)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_bool(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<bool>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_bool", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "boolean", expect_bool)
+    }
+
+    /// With `zero_copy`, returns a read-only memoryview over the bytes rather than a copy of them.
+    /// Reading the same value again returns a view of the same buffer while it's still alive.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None), zero_copy = false))]
+    fn get_bytes(
+        &self,
+        py: Python<'_>,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+        zero_copy: bool,
+    ) -> PyResult<Option<PyObject>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_bytes", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        if zero_copy {
+            let shared = inner.get_shared_bytes(obj_id, prop, heads)?;
+            drop(inner);
+            return shared.map(|b| PyBytesBuffer(b).memoryview(py)).transpose();
+        }
+        let bytes = inner.get_typed(obj_id, prop, heads, "bytes", expect_bytes)?;
+        Ok(bytes.map(|b| PyBytes::new(py, &b).into()))
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_map(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<PyObjId>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_map", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "map", expect_map)
+    }
+
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_list(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<Option<PyObjId>> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("get_list", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_typed(obj_id, prop, heads, "list", expect_list)
+    }
+
+    /// The value of `prop` in `obj_id`, with scalars as plain Python values and objects as their
+    /// ID, or `default` if `prop` is missing. `obj_id` may be left out to read from the root.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), default = OptionalArg(None), heads = OptionalArg(None)))]
+    fn get_or(
+        &self,
+        py: Python<'_>,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        default: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<PyObject> {
+        let (obj_id, [prop, default, heads]) = root_args(obj_id, [prop, default, heads])?;
+        let prop = prop.required("get_or", "prop")?.extract()?;
+        let default = default.required("get_or", "default")?.into_py(py);
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_or(py, obj_id, prop, default, heads)
+    }
+
+    /// Whether `obj_id` has a value at `prop`.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), heads = OptionalArg(None)))]
+    fn has(
+        &self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        heads: OptionalArg<'_>,
+    ) -> PyResult<bool> {
+        let (obj_id, [prop, heads]) = root_args(obj_id, [prop, heads])?;
+        let prop = prop.required("has", "prop")?.extract()?;
+        let heads = heads.extract()?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let Some(prop) = inner.resolve_key(&obj_id, prop, &heads) else {
+            return Ok(false);
+        };
+        Ok(inner.get(obj_id, prop, heads)?.is_some())
+    }
+
+    /// Iterate lazily over the keys of a map, or the element IDs of a list or text object.
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn keys(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<PyRange> {
+        PyRange::whole(&self.inner, obj_id, RangeYield::Keys, heads)
+    }
+
+    /// Iterate lazily over the `(key, value, obj_id)` entries of the map `obj_id` with keys from
+    /// `start_key` (inclusive) to `end_key` (exclusive).
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), start_key = None, end_key = None, heads = None))]
+    fn map_range(
+        &self,
+        obj_id: PyObjId,
+        start_key: Option<String>,
+        end_key: Option<String>,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyRange {
+        let start = start_key.map_or(Bound::Unbounded, Bound::Included);
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::Map {
+                start,
+                end: end_key,
+            },
+            RangeYield::Entries,
+            heads,
+        )
+    }
+
+    /// Iterate lazily over the `(index, value, obj_id)` entries of the list or text `obj_id` from
+    /// `start` (inclusive) to `end` (exclusive).
+    #[pyo3(signature = (obj_id, start = None, end = None, heads = None))]
+    fn list_range(
+        &self,
+        obj_id: PyObjId,
+        start: Option<usize>,
+        end: Option<usize>,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyRange {
+        let start = start.unwrap_or(0);
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::list(start, end),
+            RangeYield::Entries,
+            heads,
+        )
+    }
+
+    /// Resolve `path` from the root, returning the value and ID found there or None if any part of
+    /// the path is missing. `path` is either a sequence of props like `("todos", 3, "title")` or
+    /// a string like `"/todos/3/title"`, in which numeric segments index into lists and text.
+    fn lookup(
+        &self,
+        path: &PyAny,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Option<(PyValue<'_>, PyObjId)>> {
+        let path = extract_path(path)?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.lookup(path, heads)
+    }
+
+    /// The `(path, value, obj_id)` matches of the JSONPath-like `expr`, such as
+    /// `$.todos[*].title` or `$.todos[?(@.done == false)]`. Supported are child keys and indices,
+    /// `*` wildcards, and filters comparing a relative path with a string, number, boolean or
+    /// null using `==` or `!=`.
+    #[pyo3(signature = (expr, heads = None))]
+    fn query(
+        &self,
+        expr: &str,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<(Vec<PyProp>, PyValue<'_>, PyObjId)>> {
+        let steps = parse_query(expr)?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.query(&steps, heads)
+    }
+
+    /// The parents of `obj_id` as `(parent, prop)` pairs, starting with its immediate parent and
+    /// ending with the root.
+    fn parents(
+        &self,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Vec<(PyObjId, PyProp)>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.parents(obj_id, heads)
+    }
+
+    /// The props leading from the root to `obj_id`.
+    fn path(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyProp>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let parents = inner.parents(obj_id, heads)?;
+        Ok(parents.into_iter().rev().map(|(_, prop)| prop).collect())
+    }
+
+    /// Iterate lazily over the `(value, obj_id)` pairs of a map, list or text object.
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn values(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<PyRange> {
+        PyRange::whole(&self.inner, obj_id, RangeYield::Values, heads)
+    }
+
+    /// Iterate lazily over the `(value, obj_id)` pairs of the list or text `obj_id` from `start`
+    /// (inclusive) to `end` (exclusive). Elements outside the range are never read.
+    #[pyo3(signature = (obj_id, start, end, heads = None))]
+    fn values_range(
+        &self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyRange {
+        PyRange::new(
+            &self.inner,
+            obj_id,
+            RangeCursor::list(start, Some(end)),
+            RangeYield::Values,
+            heads,
+        )
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn length(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<usize> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.length(obj_id, heads))
+    }
+
+    fn text(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<String> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.text(obj_id, heads)
+    }
+
+    #[pyo3(signature = (obj_id, start, end, heads = None))]
+    fn text_range(
+        &self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<String> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(inner.text_range(obj_id, start, end, heads))
+    }
+
+    fn marks(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyMark>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.marks(obj_id, heads)
+    }
+
+    /// The marks active on the character at `index`, as a `{name: value}` dict.
+    fn marks_at(
+        &self,
+        obj_id: PyObjId,
+        index: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<HashMap<String, PyScalarValue>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.marks_at(obj_id, index, heads)
+    }
+
+    fn get_cursor(
+        &self,
+        obj_id: PyObjId,
+        position: usize,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyCursor> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_cursor(obj_id, position, heads)
+    }
+
+    /// The positions of the non-overlapping occurrences of `needle` in the text `obj_id`, in the
+    /// document's text encoding. With `cursors` set each match is a `(position, cursor)` pair
+    /// instead.
+    #[pyo3(signature = (obj_id, needle, heads = None, cursors = false))]
+    fn find_text(
+        &self,
+        py: Python<'_>,
+        obj_id: PyObjId,
+        needle: &str,
+        heads: Option<Vec<PyChangeHash>>,
+        cursors: bool,
+    ) -> PyResult<Vec<PyObject>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let found = inner.find_text(obj_id, needle, heads, cursors)?;
+        Ok(found
+            .into_iter()
+            .map(|(pos, cursor)| match cursor {
+                Some(cursor) => (pos, cursor).into_py(py),
+                None => pos.into_py(py),
+            })
+            .collect())
+    }
+
+    fn get_cursor_position(
+        &self,
+        obj_id: PyObjId,
+        cursor: PyCursor,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<usize> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.get_cursor_position(obj_id, cursor, heads)
+    }
+
+    fn spans(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PySpan>> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.spans(obj_id, heads)
+    }
+
+    /// The root map as a `MapProxy`. Indexing the transaction itself is shorthand for indexing its
+    /// root, e.g. `tx["config"]["flags"][0]`.
+    #[getter]
+    fn root(&self) -> PyMapProxy {
+        PyMapProxy(Proxy::root(&self.inner, Some(self.generation), None))
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: String) -> PyResult<PyObject> {
+        self.root().__getitem__(py, key)
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &PyAny) -> PyResult<bool> {
+        self.root().__contains__(py, key)
+    }
+
+    fn __iter__(&self) -> PyResult<PyRange> {
+        self.root().keys()
+    }
+
+    fn __setitem__(&self, key: String, value: &PyAny) -> PyResult<()> {
+        self.root().__setitem__(key, value)
+    }
+
+    fn __delitem__(&self, key: String) -> PyResult<()> {
+        self.root().__delitem__(key)
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None))]
+    fn to_py(
+        &self,
+        py: Python<'_>,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<PyObject> {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        inner.to_py(py, obj_id, heads)
+    }
+
+    #[pyo3(signature = (obj_id = PyObjId(am::ROOT), heads = None, indent = None, counters = "number", timestamps = "millis", bytes = "hex"))]
+    fn to_json(
+        &self,
+        obj_id: PyObjId,
+        heads: Option<Vec<PyChangeHash>>,
+        indent: Option<usize>,
+        counters: &str,
+        timestamps: &str,
+        bytes: &str,
+    ) -> PyResult<String> {
+        let options = JsonOptions::new(counters, timestamps, bytes)?;
+        let inner = self
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let value = inner.to_json(obj_id, heads, &options)?;
+        json_to_string(&value, indent)
+    }
+
+    /// `put(obj_id, prop, value_type, value)`, where `obj_id` may be left out to write to the root.
+    /// `prop` may be a tuple of props, in which case missing maps along the path are created.
+    /// `value_type` may be left out to infer it from `value`: bool, None, str and bytes map to the
+    /// matching types, an int is an `Int`, or a `Uint` if it's too large, and a float is always an
+    /// `F64`. With `strict`, ints which don't fit in an `Int` and subclasses of these types are
+    /// rejected rather than guessed at.
+    #[pyo3(signature = (
+        obj_id = OptionalArg(None),
+        prop = OptionalArg(None),
+        value_type = OptionalArg(None),
+        value = OptionalArg(None),
+        *,
+        strict = false
+    ))]
+    fn put(
+        &mut self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        value_type: OptionalArg<'_>,
+        value: OptionalArg<'_>,
+        strict: bool,
+    ) -> PyResult<()> {
+        let (obj_id, [prop, value_type, value]) = root_args(obj_id, [prop, value_type, value])?;
+        let path: PyPropPath = prop.required("put", "prop")?.extract()?;
+        let value = match (value_type.0, value.0) {
+            (Some(value_type), Some(value)) => import_scalar(value, &value_type.extract()?)?,
+            // With `value_type` left out, `value` may have landed in its place.
+            (Some(value), None) | (None, Some(value)) if strict => infer_scalar_strict(value)?,
+            (Some(value), None) | (None, Some(value)) => infer_scalar(value)?,
+            (None, None) => return Err(missing_arg("put", "value")),
+        };
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let (obj_id, prop) = follow_mut(tx, obj_id.0, path, true)?;
+        tx.put(obj_id, prop, value)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    }
+
+    /// `put_object(obj_id, prop, value)`, where `obj_id` may be left out to write to the root.
+    /// `prop` may be a tuple of props, in which case missing maps along the path are created.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), value = OptionalArg(None)))]
+    fn put_object(
+        &mut self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        value: OptionalArg<'_>,
+    ) -> PyResult<PyObjId> {
+        let (obj_id, [prop, value]) = root_args(obj_id, [prop, value])?;
+        let path: PyPropPath = prop.required("put_object", "prop")?.extract()?;
+        let value = value.required("put_object", "value")?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let Imported::Object(objtype, content) = import_value(value)? else {
+            return Err(PyException::new_err("put_object requires an object value"));
+        };
+        let (obj_id, prop) = follow_mut(tx, obj_id.0, path, true)?;
+        let id = tx
+            .put_object(obj_id, prop, objtype)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+        populate(tx, &id, objtype, content)?;
+        Ok(PyObjId(id))
+    }
+
+    fn insert(
+        &mut self,
+        obj_id: PyObjId,
+        index: isize,
+        value_type: &PyScalarType,
+        value: &PyAny,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let index = resolve_index(index, tx.length(&obj_id.0), true)?;
+        tx.insert(obj_id.0, index, import_scalar(value, value_type)?)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    }
+
+    fn insert_object(&mut self, obj_id: PyObjId, index: isize, value: &PyAny) -> PyResult<PyObjId> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let Imported::Object(objtype, content) = import_value(value)? else {
+            return Err(PyException::new_err(
+                "insert_object requires an object value",
+            ));
+        };
+        let index = resolve_index(index, tx.length(&obj_id.0), true)?;
+        let id = tx
+            .insert_object(obj_id.0, index, objtype)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+        populate(tx, &id, objtype, content)?;
+        Ok(PyObjId(id))
+    }
+
+    /// `increment(obj_id, prop, value)`, where `obj_id` may be left out to write to the root and
+    /// `prop` may be a tuple of props leading through existing nested objects.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None), value = OptionalArg(None)))]
+    fn increment(
+        &mut self,
+        obj_id: OptionalArg<'_>,
+        prop: OptionalArg<'_>,
+        value: OptionalArg<'_>,
+    ) -> PyResult<()> {
+        let (obj_id, [prop, value]) = root_args(obj_id, [prop, value])?;
+        let path: PyPropPath = prop.required("increment", "prop")?.extract()?;
+        let value: i64 = value.required("increment", "value")?.extract()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let (obj_id, prop) = follow_mut(tx, obj_id.0, path, false)?;
+        tx.increment(obj_id, prop, value)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    }
+
+    /// Put each `(prop, ScalarType, value)` triple of `items` into `obj_id`. A negative index
+    /// counts back from the end of the list.
+    fn put_many(
+        &mut self,
+        obj_id: PyObjId,
+        items: Vec<(PyKey, PyScalarType, &PyAny)>,
+    ) -> PyResult<()> {
+        let items = items
+            .into_iter()
+            .map(|(key, value_type, value)| Ok((key, import_scalar(value, &value_type)?)))
+            .collect::<PyResult<Vec<_>>>()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        // Puts don't change the length, so every index is resolved against the same one, and
+        // all of them are resolved before anything is written.
+        let len = tx.length(&obj_id.0);
+        let items = items
+            .into_iter()
+            .map(|(key, value)| match key {
+                PyKey::Key(key) => Ok((Prop::Map(key), value)),
+                PyKey::Index(index) => Ok((Prop::Seq(resolve_index(index, len, false)?), value)),
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        for (prop, value) in items {
+            tx.put(&obj_id.0, prop, value)
+                .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Insert `values`, a list of `(ScalarType, value)` pairs, into the list `obj_id` at `index`.
+    fn insert_many(
+        &mut self,
+        obj_id: PyObjId,
+        index: isize,
+        values: Vec<(PyScalarType, &PyAny)>,
+    ) -> PyResult<()> {
+        self.splice(obj_id, index, 0, values)
+    }
+
+    /// Delete `delete_count` elements of the list `obj_id` starting at `pos` and insert `values`,
+    /// a list of `(ScalarType, value)` pairs, in their place.
+    fn splice(
+        &mut self,
+        obj_id: PyObjId,
+        pos: isize,
+        delete_count: isize,
+        values: Vec<(PyScalarType, &PyAny)>,
+    ) -> PyResult<()> {
+        let values = values
+            .into_iter()
+            .map(|(value_type, value)| import_scalar(value, &value_type))
+            .collect::<PyResult<Vec<_>>>()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let pos = resolve_index(pos, tx.length(&obj_id.0), true)?;
+        tx.splice(obj_id.0, pos, delete_count, values)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    }
+
+    fn splice_text(
+        &mut self,
+        obj_id: PyObjId,
+        pos: isize,
+        delete_count: isize,
+        text: &str,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let pos = resolve_index(pos, tx.length(&obj_id.0), true)?;
+        tx.splice_text(obj_id.0, pos, delete_count, text)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    }
+
+    /// `delete(obj_id, prop)`, where `obj_id` may be left out to delete from the root and `prop`
+    /// may be a tuple of props leading through existing nested objects.
+    #[pyo3(signature = (obj_id = OptionalArg(None), prop = OptionalArg(None)))]
+    fn delete(&mut self, obj_id: OptionalArg<'_>, prop: OptionalArg<'_>) -> PyResult<()> {
+        let (obj_id, [prop]) = root_args(obj_id, [prop])?;
+        let path: PyPropPath = prop.required("delete", "prop")?.extract()?;
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let (obj_id, prop) = follow_mut(tx, obj_id.0, path, false)?;
+        tx.delete(obj_id, prop)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (obj_id, start, end, name, value_type, value, expand = PyExpand(ExpandMark::After)))]
+    fn mark(
+        &mut self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        name: &str,
+        value_type: &PyScalarType,
+        value: &PyAny,
+        expand: PyExpand,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let value = import_scalar(value, value_type)?;
+        tx.mark(
+            obj_id.0,
+            Mark::new(name.to_owned(), value, start, end),
+            expand.0,
+        )
+        .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    #[pyo3(signature = (obj_id, start, end, name, expand = PyExpand(ExpandMark::After)))]
+    fn unmark(
+        &mut self,
+        obj_id: PyObjId,
+        start: usize,
+        end: usize,
+        name: &str,
+        expand: PyExpand,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        tx.unmark(obj_id.0, name, start, end, expand.0)
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    /// Replace the contents of the text `obj_id` with `new_value`, splicing in only the parts that
+    /// differ. With `base_heads`, `new_value` is taken as an edit of the text as it was at those
+    /// heads, such as a copy edited outside the document, and changes made since are kept.
+    #[pyo3(signature = (obj_id, new_value, base_heads = None))]
+    fn update_text(
+        &mut self,
+        obj_id: PyObjId,
+        new_value: &str,
+        base_heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let base = get_heads(base_heads);
+        if let Some(base) = &base {
+            // Unknown heads would otherwise read as an empty base text.
+            inner.check_known(base)?;
+        }
+        let tx = inner.active_tx(self.generation)?;
+        match base {
+            Some(base) => update_text_from(tx, &obj_id.0, &base, new_value),
+            None => tx.update_text(&obj_id.0, new_value),
+        }
+        .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    fn split_block(&mut self, obj_id: PyObjId, index: usize) -> PyResult<PyObjId> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        tx.split_block(obj_id.0, index)
+            .map_err(|e| PyException::new_err(e.to_string()))
+            .map(PyObjId)
+    }
+
+    fn join_block(&mut self, obj_id: PyObjId, index: usize) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        tx.join_block(obj_id.0, index)
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    fn replace_block(&mut self, obj_id: PyObjId, index: usize) -> PyResult<PyObjId> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        tx.replace_block(obj_id.0, index)
+            .map_err(|e| PyException::new_err(e.to_string()))
+            .map(PyObjId)
+    }
+
+    fn update_spans(
+        &mut self,
+        obj_id: PyObjId,
+        spans: Vec<PySpan>,
+        default_expand: Option<PyExpand>,
+        per_mark_expands: Option<HashMap<String, PyExpand>>,
+    ) -> PyResult<()> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        let tx = inner.active_tx(self.generation)?;
+        let mut config = UpdateSpansConfig::default();
+        if let Some(expand) = default_expand {
+            config = config.with_default_expand(expand.0);
+        }
+        for (name, expand) in per_mark_expands.unwrap_or_default() {
+            config = config.with_mark_expand(name, expand.0);
+        }
+        tx.update_spans(obj_id.0, config, spans.into_iter().map(|s| s.0))
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+}
+
+fn datetime_to_timestamp(datetime: &PyDateTime) -> PyResult<i64> {
+    Ok((datetime.call_method0("timestamp")?.extract::<f64>()? * 1000.0).round() as i64)
+}
+
+/// The contents of any object supporting the buffer protocol, whatever its item format or memory
+/// layout, copied straight out of its buffer.
+fn buffer_bytes(value: &PyAny) -> PyResult<Vec<u8>> {
+    if let Ok(bytes) = value.downcast::<PyBytes>() {
+        return Ok(bytes.as_bytes().to_owned());
+    }
+    let py = value.py();
+    let mut view = std::mem::MaybeUninit::<ffi::Py_buffer>::uninit();
+    // SAFETY: the view is only used once PyObject_GetBuffer has filled it in, and is released
+    // before returning. PyBuffer_ToContiguous writes exactly `view.len` bytes.
+    unsafe {
+        if ffi::PyObject_GetBuffer(value.as_ptr(), view.as_mut_ptr(), ffi::PyBUF_FULL_RO) == -1 {
+            return Err(PyErr::fetch(py));
+        }
+        let view = view.assume_init_mut();
+        let mut bytes = vec![0u8; view.len as usize];
+        let res = ffi::PyBuffer_ToContiguous(bytes.as_mut_ptr().cast(), view, view.len, b'C' as _);
+        ffi::PyBuffer_Release(view);
+        if res == -1 {
+            return Err(PyErr::fetch(py));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Whether `value` is a bytes-like object which should be inferred as `Bytes`: bytes, bytearray
+/// or memoryview. Other buffers, such as arrays, need an explicit `ScalarType.Bytes`.
+fn is_bytes_like(value: &PyAny) -> bool {
+    value.is_instance_of::<PyBytes>()
+        || value.is_instance_of::<PyByteArray>()
+        || is_memoryview(value)
+}
+
+fn is_memoryview(value: &PyAny) -> bool {
+    // SAFETY: PyMemoryView_Check only inspects the type of a valid object pointer.
+    unsafe { ffi::PyMemoryView_Check(value.as_ptr()) != 0 }
+}
+
+/// Midnight UTC on `date`, in milliseconds since the epoch.
+fn date_to_timestamp(date: &PyDate) -> PyResult<i64> {
+    // 719163 is the proleptic Gregorian ordinal of 1970-01-01.
+    let days = date.call_method0("toordinal")?.extract::<i64>()? - 719163;
+    Ok(days * 86_400_000)
+}
+
+/// A timestamp given as a datetime, a date or integer milliseconds since the epoch. Naive
+/// datetimes are taken to be in local time.
+fn import_timestamp(value: &PyAny) -> PyResult<i64> {
+    // `datetime` is a subclass of `date` so it must be checked first.
+    if let Ok(datetime) = value.downcast::<PyDateTime>() {
+        datetime_to_timestamp(datetime)
+    } else if let Ok(date) = value.downcast::<PyDate>() {
+        date_to_timestamp(date)
+    } else if value.is_instance_of::<PyLong>() && !value.is_instance_of::<PyBool>() {
+        value.extract::<i64>()
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "expected a datetime, date or int timestamp, got {}",
+            value.repr()?
+        )))
+    }
+}
+
+fn import_scalar(value: &PyAny, scalar_type: &PyScalarType) -> Result<ScalarValue, PyErr> {
+    Ok(match scalar_type {
+        PyScalarType::Bytes => ScalarValue::Bytes(buffer_bytes(value)?),
+        PyScalarType::Str => ScalarValue::Str(value.extract::<String>()?.into()),
+        PyScalarType::Int => ScalarValue::Int(value.extract::<i64>()?),
+        PyScalarType::Uint => ScalarValue::Uint(value.extract::<u64>()?),
+        PyScalarType::F64 => ScalarValue::F64(value.extract::<f64>()?),
+        PyScalarType::Counter => match value.extract::<PyRef<PyCounter>>() {
+            Ok(c) => ScalarValue::counter(c.value),
+            Err(_) => ScalarValue::counter(value.extract::<i64>()?),
+        },
+        PyScalarType::Timestamp => ScalarValue::Timestamp(import_timestamp(value)?),
+        PyScalarType::Boolean => ScalarValue::Boolean(value.extract::<bool>()?),
+        PyScalarType::Unknown => {
+            let (type_code, bytes) = value.extract::<(u8, &[u8])>()?;
+            // Codes 0-9 are the known scalar types and the encoding only has room for four bits.
+            if !(10..16).contains(&type_code) {
+                return Err(PyException::new_err(format!(
+                    "invalid type code for unknown scalar: {}",
+                    type_code
+                )));
+            }
+            ScalarValue::Unknown {
+                type_code,
+                bytes: bytes.to_owned(),
+            }
+        }
+        PyScalarType::Null => ScalarValue::Null,
+    })
+}
+
+/// Infer a scalar from a plain Python value, for places where no explicit `ScalarType` is given.
+/// bool is `Boolean`, None is `Null`, str is `Str` and bytes, bytearray and memoryview are
+/// `Bytes`. An int is an `Int` if it fits in 64 signed bits, else a `Uint` if it fits in 64
+/// unsigned bits, else an `F64`. A float is always an `F64`, even if it holds a whole number.
+/// Datetimes and dates are `Timestamp`s and a `Counter` is a `Counter`. Anything else which
+/// converts to one of these types is inferred as that type.
+fn infer_scalar(value: &PyAny) -> PyResult<ScalarValue> {
+    // `bool` is a subclass of `int` so it must be checked first.
+    if let Ok(b) = value.downcast::<PyBool>() {
+        Ok(ScalarValue::Boolean(b.is_true()))
+    } else if value.is_none() {
+        Ok(ScalarValue::Null)
+    } else if let Ok(c) = value.extract::<PyRef<PyCounter>>() {
+        Ok(ScalarValue::counter(c.value))
+    } else if let Ok(s) = value.extract::<String>() {
+        Ok(ScalarValue::Str(s.into()))
+    } else if is_bytes_like(value) {
+        Ok(ScalarValue::Bytes(buffer_bytes(value)?))
+    } else if let Ok(i) = value.extract::<i64>() {
+        Ok(ScalarValue::Int(i))
+    } else if let Ok(u) = value.extract::<u64>() {
+        Ok(ScalarValue::Uint(u))
+    } else if let Ok(f) = value.extract::<f64>() {
+        Ok(ScalarValue::F64(f))
+    } else if let Ok(dt) = value.downcast::<PyDateTime>() {
+        Ok(ScalarValue::Timestamp(datetime_to_timestamp(dt)?))
+    } else if let Ok(date) = value.downcast::<PyDate>() {
+        Ok(ScalarValue::Timestamp(date_to_timestamp(date)?))
+    } else {
+        Err(PyException::new_err(format!(
+            "cannot infer scalar type of {}",
+            value.repr()?
+        )))
+    }
+}
+
+/// Like `infer_scalar`, but rejecting values whose scalar type is ambiguous: ints which don't fit
+/// in an `Int`, and subclasses of bool, int, float, str or bytes or other values which merely
+/// convert to them.
+fn infer_scalar_strict(value: &PyAny) -> PyResult<ScalarValue> {
+    if value.is_exact_instance_of::<PyLong>() {
+        return value.extract::<i64>().map(ScalarValue::Int).map_err(|_| {
+            PyTypeError::new_err(format!(
+                "ambiguous value {}: ints outside the range of ScalarType.Int need an explicit type",
+                value
+            ))
+        });
+    }
+    let exact = value.is_none()
+        || value.is_exact_instance_of::<PyBool>()
+        || value.is_exact_instance_of::<PyFloat>()
+        || value.is_exact_instance_of::<PyString>()
+        || value.is_exact_instance_of::<PyBytes>()
+        || value.is_exact_instance_of::<PyByteArray>()
+        || is_memoryview(value)
+        || value.is_instance_of::<PyDate>()
+        || value.is_instance_of::<PyCounter>();
+    if !exact {
+        return Err(PyTypeError::new_err(format!(
+            "ambiguous value {} of type {}: give an explicit ScalarType",
+            value.repr()?,
+            value.get_type().name()?
+        )));
+    }
+    infer_scalar(value)
+}
+
+/// A Python value on its way into a document.
+enum Imported<'a> {
+    /// An object, along with the Python value to fill it with, if any.
+    Object(ObjType, Option<&'a PyAny>),
+    Scalar(ScalarValue),
+}
+
+/// Work out what a Python value should become in the document. Dicts and lists become maps and
+/// lists, a bare `ObjType` becomes an empty object and an `(ObjType, content)` tuple (e.g.
+/// `(ObjType.Text, "hello")`) becomes an object with that content. `(ScalarType, value)` tuples
+/// are imported with the given type and anything else is inferred with `infer_scalar`.
+fn import_value(value: &PyAny) -> PyResult<Imported<'_>> {
+    if let Ok(objtype) = value.extract::<PyRef<PyObjType>>() {
+        Ok(Imported::Object((&*objtype).into(), None))
+    } else if value.downcast::<PyDict>().is_ok() {
+        Ok(Imported::Object(ObjType::Map, Some(value)))
+    } else if value.downcast::<PyList>().is_ok() {
+        Ok(Imported::Object(ObjType::List, Some(value)))
+    } else if let Ok((objtype, content)) = value.extract::<(PyRef<PyObjType>, &PyAny)>() {
+        Ok(Imported::Object((&*objtype).into(), Some(content)))
+    } else if let Ok((scalar_type, v)) = value.extract::<(PyScalarType, &PyAny)>() {
+        Ok(Imported::Scalar(import_scalar(v, &scalar_type)?))
+    } else {
+        infer_scalar(value).map(Imported::Scalar)
+    }
+}
+
+/// Recursively fill a freshly created object with the contents of a Python value.
+fn populate(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    objtype: ObjType,
+    content: Option<&PyAny>,
+) -> PyResult<()> {
+    let Some(content) = content else {
+        return Ok(());
+    };
+    match objtype {
+        ObjType::Map | ObjType::Table => {
+            for (k, v) in content.downcast::<PyDict>()? {
+                put_value(tx, obj, Prop::Map(k.extract()?), v)?;
+            }
+        }
+        ObjType::List => {
+            for (i, v) in content.downcast::<PyList>()?.iter().enumerate() {
+                insert_value(tx, obj, i, v)?;
+            }
+        }
+        ObjType::Text => {
+            tx.splice_text(obj, 0, 0, content.extract::<&str>()?)
+                .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+fn put_value(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    prop: Prop,
+    value: &PyAny,
+) -> PyResult<()> {
+    match import_value(value)? {
+        Imported::Object(objtype, content) => {
+            let id = tx
+                .put_object(obj, prop, objtype)
+                .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+            populate(tx, &id, objtype, content)
+        }
+        Imported::Scalar(v) => tx
+            .put(obj, prop, v)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e))),
+    }
+}
+
+fn insert_value(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    index: usize,
+    value: &PyAny,
+) -> PyResult<()> {
+    match import_value(value)? {
+        Imported::Object(objtype, content) => {
+            let id = tx
+                .insert_object(obj, index, objtype)
+                .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?;
+            populate(tx, &id, objtype, content)
+        }
+        Imported::Scalar(v) => tx
+            .insert(obj, index, v)
+            .map_err(|e| PyException::new_err(format!("error putting: {}", e))),
+    }
+}
+
+/// Write the entries of a JSON object or array into the empty map or list `obj`. JSON strings
+/// become scalar strings rather than text objects.
+fn populate_json(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: &am::ObjId,
+    content: &serde_json::Value,
+) -> Result<(), am::AutomergeError> {
+    match content {
+        serde_json::Value::Object(map) => {
+            for (k, v) in map {
+                match json_objtype(v) {
+                    Some(objtype) => {
+                        let id = tx.put_object(obj, k.as_str(), objtype)?;
+                        populate_json(tx, &id, v)?;
+                    }
+                    None => tx.put(obj, k.as_str(), json_scalar(v))?,
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                match json_objtype(v) {
+                    Some(objtype) => {
+                        let id = tx.insert_object(obj, i, objtype)?;
+                        populate_json(tx, &id, v)?;
+                    }
+                    None => tx.insert(obj, i, json_scalar(v))?,
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn json_objtype(value: &serde_json::Value) -> Option<ObjType> {
+    match value {
+        serde_json::Value::Object(_) => Some(ObjType::Map),
+        serde_json::Value::Array(_) => Some(ObjType::List),
+        _ => None,
+    }
+}
+
+fn json_scalar(value: &serde_json::Value) -> ScalarValue {
+    match value {
+        serde_json::Value::Bool(b) => ScalarValue::Boolean(*b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                ScalarValue::Int(i)
+            } else if let Some(u) = n.as_u64() {
+                ScalarValue::Uint(u)
+            } else {
+                ScalarValue::F64(n.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        serde_json::Value::String(s) => ScalarValue::Str(s.as_str().into()),
+        _ => ScalarValue::Null,
+    }
+}
+
+/// Convert a nested Python value (dicts, lists and scalars) into a hydrated automerge value.
+fn import_hydrated(value: &PyAny) -> PyResult<am::hydrate::Value> {
+    if let Ok(dict) = value.downcast::<PyDict>() {
+        Ok(am::hydrate::Value::Map(import_hydrated_map(dict)?))
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        Ok(list
+            .iter()
+            .map(import_hydrated)
+            .collect::<PyResult<Vec<_>>>()?
+            .into())
+    } else {
+        infer_scalar(value).map(am::hydrate::Value::Scalar)
+    }
+}
+
+fn import_hydrated_map(dict: &PyDict) -> PyResult<am::hydrate::Map> {
+    Ok(dict
+        .iter()
+        .map(|(k, v)| Ok((k.extract::<String>()?, import_hydrated(v)?)))
+        .collect::<PyResult<HashMap<String, am::hydrate::Value>>>()?
+        .into())
+}
+
+/// Convert a hydrated automerge value into plain Python dicts, lists and scalars.
+fn export_hydrated(py: Python<'_>, value: &am::hydrate::Value) -> PyResult<PyObject> {
+    Ok(match value {
+        am::hydrate::Value::Scalar(s) => export_scalar(py, s.clone(), PyTimestampMode::Naive)?.1,
+        am::hydrate::Value::Map(map) => export_hydrated_map(py, map)?.into(),
+        am::hydrate::Value::List(list) => PyList::new(
+            py,
+            list.iter()
+                .map(|v| export_hydrated(py, &v.value))
+                .collect::<PyResult<Vec<_>>>()?,
+        )
+        .into(),
+        am::hydrate::Value::Text(text) => String::from(text).into_py(py),
+    })
+}
+
+fn export_hydrated_map<'py>(py: Python<'py>, map: &am::hydrate::Map) -> PyResult<&'py PyDict> {
+    // Hydrated maps are unordered, sort them to match the order of `keys`.
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    let dict = PyDict::new(py);
+    for (k, v) in entries {
+        dict.set_item(k, export_hydrated(py, &v.value)?)?;
+    }
+    Ok(dict)
+}
+
+#[pyclass(name = "SyncState")]
+struct PySyncState(am::sync::State);
+
+#[pymethods]
+impl PySyncState {
+    #[new]
+    pub fn new() -> PySyncState {
+        PySyncState(am::sync::State::new())
+    }
+
+    /// Encode the parts of the state which are worth keeping across reconnects.
+    pub fn encode<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.0.encode())
+    }
+
+    /// The heads we know the other peer has in common with us.
+    #[getter]
+    fn shared_heads(&self) -> Vec<PyChangeHash> {
+        self.0
+            .shared_heads
+            .iter()
+            .copied()
+            .map(PyChangeHash)
+            .collect()
+    }
+
+    /// The heads of the other peer, if it has told us them yet.
+    #[getter]
+    fn their_heads(&self) -> Option<Vec<PyChangeHash>> {
+        self.0
+            .their_heads
+            .as_ref()
+            .map(|heads| heads.iter().copied().map(PyChangeHash).collect())
+    }
+
+    /// Hashes of the changes we have sent to the other peer in this session.
+    #[getter]
+    fn sent_hashes(&self) -> Vec<PyChangeHash> {
+        self.0
+            .sent_hashes
+            .iter()
+            .copied()
+            .map(PyChangeHash)
+            .collect()
+    }
+
+    /// Whether both peers are known to have exactly the current heads of `doc`.
+    fn in_sync(&self, doc: &Document) -> PyResult<bool> {
+        let inner = doc
+            .inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let mut heads = inner.doc.get_heads();
+        heads.sort();
+        let same = |other: &[ChangeHash]| {
+            let mut other = other.to_vec();
+            other.sort();
+            other == heads
+        };
+        Ok(same(&self.0.shared_heads) && self.0.their_heads.as_deref().is_some_and(same))
+    }
+
+    #[staticmethod]
+    pub fn decode(bytes: &[u8]) -> PyResult<PySyncState> {
+        Ok(PySyncState(
+            am::sync::State::decode(bytes).map_err(|e| PyException::new_err(e.to_string()))?,
+        ))
+    }
+}
+
+#[pyclass(name = "Message")]
+struct PyMessage(am::sync::Message);
+
+#[pymethods]
+impl PyMessage {
+    pub fn encode<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.0.clone().encode())
+    }
+
+    #[staticmethod]
+    pub fn decode(bytes: &[u8]) -> PyResult<PyMessage> {
+        Ok(PyMessage(
+            am::sync::Message::decode(bytes).map_err(|e| PyException::new_err(e.to_string()))?,
+        ))
+    }
+
+    /// The heads of the sender.
+    #[getter]
+    fn heads(&self) -> Vec<PyChangeHash> {
+        self.0.heads.iter().copied().map(PyChangeHash).collect()
+    }
+
+    /// Hashes of changes the sender is asking for.
+    #[getter]
+    fn need(&self) -> Vec<PyChangeHash> {
+        self.0.need.iter().copied().map(PyChangeHash).collect()
+    }
+
+    /// What the sender already has, as `(last_sync, bloom)` pairs: the heads as of the last
+    /// successful sync, and the encoded bloom filter of the changes added since.
+    #[getter]
+    fn have<'py>(&self, py: Python<'py>) -> Vec<(Vec<PyChangeHash>, &'py PyBytes)> {
+        self.0
+            .have
+            .iter()
+            .map(|have| {
+                let last_sync = have.last_sync.iter().copied().map(PyChangeHash).collect();
+                (last_sync, PyBytes::new(py, &have.bloom.to_bytes()))
+            })
+            .collect()
+    }
+
+    /// The number of changes included. A message may send many changes as one compressed
+    /// document chunk, which has to be decoded to count them.
+    #[getter]
+    fn num_changes(&self) -> PyResult<usize> {
+        self.0
+            .changes
+            .iter()
+            .map(|chunk| match chunk.get(8) {
+                // See `is_compressed` for the chunk types.
+                Some(0) => am::Automerge::load(chunk).map(|doc| doc.get_changes(&[]).len()),
+                _ => am::Change::from_bytes(chunk.to_vec())
+                    .map(|_| 1)
+                    .map_err(Into::into),
+            })
+            .sum::<Result<usize, am::AutomergeError>>()
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    /// The number of chunks the changes are sent in.
+    #[getter]
+    fn num_chunks(&self) -> usize {
+        self.0.changes.len()
+    }
+
+    /// The total size in bytes of the changes included.
+    #[getter]
+    fn changes_size(&self) -> usize {
+        self.0.changes.iter().map(|chunk| chunk.len()).sum()
+    }
+
+    /// Whether any changes are sent compressed, either as a document chunk or as deflated change
+    /// chunks.
+    #[getter]
+    fn is_compressed(&self) -> bool {
+        // A chunk's type follows 4 magic bytes and a 4 byte checksum: 0 is a document, 1 a change
+        // and 2 a deflated change.
+        self.0
+            .changes
+            .iter()
+            .any(|chunk| matches!(chunk.get(8), Some(0 | 2)))
+    }
+}
+
+#[pyfunction]
+fn random_actor_id<'py>(py: Python<'py>) -> &'py PyBytes {
+    PyBytes::new(py, ActorId::random().to_bytes())
+}
+
+create_exception!(
+    _automerge,
+    TypeMismatchError,
+    PyException,
+    "Raised by the typed getters when the value found has a different type."
+);
+
+/// A Python module implemented in Rust.
+#[pymodule]
+fn _automerge(py: Python, m: &PyModule) -> PyResult<()> {
+    // Classes
+    m.add_class::<Document>()?;
+    m.add_class::<Transaction>()?;
+    m.add_class::<DocumentView>()?;
+    m.add_class::<PyChange>()?;
+    m.add_class::<PySyncState>()?;
+    m.add_class::<PyMessage>()?;
+    m.add_class::<PyRange>()?;
+    m.add_class::<PyMapProxy>()?;
+    m.add_class::<PyListProxy>()?;
+    m.add_class::<PyTextProxy>()?;
+    m.add_class::<PyObjIdClass>()?;
+    m.add_class::<PyCounter>()?;
+    m.add_class::<PyBytesBuffer>()?;
+    m.add_class::<PyPatch>()?;
+    m.add_class::<PyPutMap>()?;
+    m.add_class::<PyPutSeq>()?;
+    m.add_class::<PyInsert>()?;
+    m.add_class::<PySpliceText>()?;
+    m.add_class::<PyIncrement>()?;
+    m.add_class::<PyConflict>()?;
+    m.add_class::<PyDeleteMap>()?;
+    m.add_class::<PyDeleteSeq>()?;
+    m.add_class::<PyMarks>()?;
+    m.add_class::<PyStats>()?;
+    m.add_class::<PyAttribution>()?;
+    m.add_class::<PyChangeOp>()?;
+    m.add_class::<PyTextSpan>()?;
+    m.add_class::<PyBlockSpan>()?;
+
+    // Enums
+    m.add_class::<PyObjType>()?;
+    m.add_class::<PyScalarType>()?;
+    m.add_class::<PyExpandMark>()?;
+    m.add_class::<PyTextEncoding>()?;
+    m.add_class::<PyTimestampMode>()?;
+
+    // Constants
+    m.add("ROOT", PyObjId(am::ROOT))?;
+    m.add("TypeMismatchError", py.get_type::<TypeMismatchError>())?;
+
+    // Functions
+    m.add_function(wrap_pyfunction!(random_actor_id, m)?)?;
+    Ok(())
+}
+
+/// How many entries a `PyRange` reads from the document at a time.
+const RANGE_CHUNK: usize = 64;
+
+/// Where a `PyRange` will continue reading from, and where it stops.
+enum RangeCursor {
+    Map {
+        start: Bound<String>,
+        end: Option<String>,
+    },
+    /// `start` is only used for the first chunk. After that, reading resumes just past the
+    /// element the previous chunk ended on, so that inserts and deletes made while iterating
+    /// don't cause elements to be skipped or yielded twice.
+    List {
+        start: usize,
+        end: Option<usize>,
+        last: Option<ChunkEnd>,
+    },
+}
+
+impl RangeCursor {
+    fn list(start: usize, end: Option<usize>) -> Self {
+        RangeCursor::List {
+            start,
+            end,
+            last: None,
+        }
+    }
+}
+
+/// The end of the chunk a list `PyRange` read last.
+struct ChunkEnd {
+    /// The index the last element of the chunk was read at.
+    index: usize,
+    /// The elements of the chunk, in order. Only kept when reading the live document, as the
+    /// list can't change underneath a read at fixed heads.
+    elems: Vec<am::Cursor>,
+}
+
+impl ChunkEnd {
+    /// The index to resume reading the live list at.
+    ///
+    /// This never seeks an element by its cursor: automerge's lookup of a deleted element with
+    /// nothing visible after it disagrees with its own debug check, and panics.
+    fn resume<R: ReadDoc>(&self, doc: &R, obj_id: &am::ObjId) -> usize {
+        let Some(last) = self.elems.last() else {
+            return self.index + 1;
+        };
+        if doc.get_cursor(obj_id, self.index, None).ok().as_ref() == Some(last) {
+            return self.index + 1;
+        }
+        // Something at or before the last element changed, so find the last element of the chunk
+        // that is still visible with one pass over the list.
+        let positions: HashMap<String, usize> = doc
+            .keys(obj_id)
+            .zip(doc.list_range(obj_id, ..).map(|item| item.index))
+            .collect();
+        self.elems
+            .iter()
+            .rev()
+            .find_map(|elem| positions.get(&elem.to_string()))
+            // If the whole chunk was deleted, resume where it started.
+            .map_or(
+                (self.index + 1).saturating_sub(self.elems.len()),
+                |position| position + 1,
+            )
+    }
+}
+
+/// Which part of each entry a `PyRange` yields.
+#[derive(Clone, Copy)]
+enum RangeYield {
+    /// `(key or index, value, obj_id)` tuples.
+    Entries,
+    Keys,
+    /// `(value, obj_id)` tuples.
+    Values,
+}
+
+impl RangeYield {
+    fn project(
+        self,
+        py: Python<'_>,
+        prop: PyProp,
+        value: am::Value<'_>,
+        id: am::ObjId,
+        timestamps: PyTimestampMode,
+    ) -> PyObject {
+        let value = PyValue(value.into_owned(), timestamps);
+        match self {
+            RangeYield::Entries => (prop, value, PyObjId(id)).into_py(py),
+            RangeYield::Keys => prop.into_py(py),
+            RangeYield::Values => (value, PyObjId(id)).into_py(py),
+        }
+    }
+}
+
+fn read_range_chunk<R: ReadDoc>(
+    py: Python<'_>,
+    doc: &R,
+    obj_id: &am::ObjId,
+    cursor: &mut RangeCursor,
+    yields: RangeYield,
+    heads: Option<&[ChangeHash]>,
+    timestamps: PyTimestampMode,
+) -> Vec<PyObject> {
+    match cursor {
+        RangeCursor::Map { start, end } => {
+            let range = (
+                start.clone(),
+                end.clone().map_or(Bound::Unbounded, Bound::Excluded),
+            );
+            let items: Vec<_> = match heads {
+                Some(heads) => doc.map_range_at(obj_id, range, heads),
+                None => doc.map_range(obj_id, range),
+            }
+            .take(RANGE_CHUNK)
+            .collect();
+            if let Some(item) = items.last() {
+                *start = Bound::Excluded(item.key.to_string());
+            }
+            items
+                .into_iter()
+                .map(|item| {
+                    let id = item.id();
+                    let prop = PyProp(Prop::Map(item.key.into_owned()));
+                    yields.project(py, prop, item.value.into_value(), id, timestamps)
+                })
+                .collect()
+        }
+        RangeCursor::List { start, end, last } => {
+            let from = match (last.as_ref(), heads) {
+                (None, _) => *start,
+                (Some(last), Some(_)) => last.index + 1,
+                (Some(last), None) => last.resume(doc, obj_id),
+            };
+            let range = (
+                Bound::Included(from),
+                end.map_or(Bound::Unbounded, Bound::Excluded),
+            );
+            let elem_id = |index: usize| doc.get_cursor(obj_id, index, heads).ok();
+            let items: Vec<_> = match heads {
+                Some(heads) => doc.list_range_at(obj_id, range, heads),
+                None => doc.list_range(obj_id, range),
+            }
+            .take(RANGE_CHUNK)
+            .collect();
+            let elems: Vec<_> = match heads {
+                Some(_) => Vec::new(),
+                None => items
+                    .iter()
+                    .filter_map(|item| elem_id(item.index))
+                    .collect(),
+            };
+            let end_index = items.last().map(|item| item.index);
+            let values = items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| match yields {
+                    // The element ID of a list item is the cursor that points at it.
+                    RangeYield::Keys => elems
+                        .get(i)
+                        .cloned()
+                        .or_else(|| elem_id(item.index))
+                        .map(|cursor| cursor.to_string())
+                        .into_py(py),
+                    _ => {
+                        let id = item.id();
+                        let prop = PyProp(Prop::Seq(item.index));
+                        yields.project(py, prop, item.value.into_value(), id, timestamps)
+                    }
+                })
+                .collect();
+            if let Some(index) = end_index {
+                *last = Some(ChunkEnd { index, elems });
+            }
+            values
+        }
+    }
+}
+
+/// A lazy iterator over part of a map or list, returned by `keys`, `values`, `map_range` and
+/// `list_range`. Entries are read from the document a chunk at a time, so it reflects changes
+/// made while iterating.
+#[pyclass(name = "Range")]
+struct PyRange {
+    inner: Arc<RwLock<Inner>>,
+    obj_id: am::ObjId,
+    cursor: RangeCursor,
+    yields: RangeYield,
+    heads: Option<Vec<ChangeHash>>,
+    buffer: VecDeque<PyObject>,
+}
+
+impl PyRange {
+    fn new(
+        inner: &Arc<RwLock<Inner>>,
+        obj_id: PyObjId,
+        cursor: RangeCursor,
+        yields: RangeYield,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> Self {
+        PyRange {
+            inner: Arc::clone(inner),
+            obj_id: obj_id.0,
+            cursor,
+            yields,
+            heads: get_heads(heads),
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Iterate over every key of a map, or every value of a list or text object.
+    fn whole(
+        inner: &Arc<RwLock<Inner>>,
+        obj_id: PyObjId,
+        yields: RangeYield,
+        heads: Option<Vec<PyChangeHash>>,
+    ) -> PyResult<Self> {
+        let objtype = inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))?
+            .object_type(obj_id.clone())?;
+        let cursor = match (ObjType::from(&objtype), yields) {
+            (ObjType::Map | ObjType::Table, _) => RangeCursor::Map {
+                start: Bound::Unbounded,
+                end: None,
+            },
+            _ => RangeCursor::list(0, None),
+        };
+        Ok(PyRange::new(inner, obj_id, cursor, yields, heads))
+    }
+}
+
+#[pymethods]
+impl PyRange {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        if self.buffer.is_empty() {
+            let inner = self
+                .inner
+                .read()
+                .map_err(|e| PyException::new_err(e.to_string()))?;
+            self.buffer = inner
+                .range_chunk(
+                    py,
+                    &self.obj_id,
+                    &mut self.cursor,
+                    self.yields,
+                    self.heads.as_deref(),
+                )
+                .into();
+        }
+        Ok(self.buffer.pop_front())
+    }
+}
+
+/// The state shared by `MapProxy`, `ListProxy` and `TextProxy`: a reference to one object in a
+/// document, either live or as of `heads`. Proxies obtained from a `Transaction` can modify the
+/// object while that transaction is active; all others are read-only.
+#[derive(Clone)]
+struct Proxy {
+    inner: Arc<RwLock<Inner>>,
+    obj_id: am::ObjId,
+    /// The `tx_generation` of the transaction writes go through, or None if read-only.
+    generation: Option<u64>,
+    heads: Option<Vec<ChangeHash>>,
+}
+
+impl Proxy {
+    fn root(
+        inner: &Arc<RwLock<Inner>>,
+        generation: Option<u64>,
+        heads: Option<Vec<ChangeHash>>,
+    ) -> Self {
+        Proxy {
+            inner: Arc::clone(inner),
+            obj_id: am::ROOT,
+            generation,
+            heads,
+        }
+    }
+
+    fn heads(&self) -> Option<Vec<PyChangeHash>> {
+        self.heads
+            .as_ref()
+            .map(|heads| heads.iter().copied().map(PyChangeHash).collect())
+    }
+
+    fn read(&self) -> PyResult<RwLockReadGuard<'_, Inner>> {
+        self.inner
+            .read()
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut am::transaction::Transaction<'static>) -> PyResult<T>,
+    ) -> PyResult<T> {
+        let Some(generation) = self.generation else {
+            return Err(PyException::new_err(
+                "cannot modify a document outside a transaction",
+            ));
+        };
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
+        f(inner.active_tx(generation)?)
+    }
+
+    /// A nested object becomes another proxy; a scalar becomes a plain Python value.
+    fn wrap(&self, py: Python<'_>, value: am::Value<'_>, id: am::ObjId) -> PyResult<PyObject> {
+        let proxy = Proxy {
+            inner: Arc::clone(&self.inner),
+            obj_id: id,
+            generation: self.generation,
+            heads: self.heads.clone(),
+        };
+        Ok(match value {
+            am::Value::Object(ObjType::Map | ObjType::Table) => {
+                Py::new(py, PyMapProxy(proxy))?.into_py(py)
+            }
+            am::Value::Object(ObjType::List) => Py::new(py, PyListProxy(proxy))?.into_py(py),
+            am::Value::Object(ObjType::Text) => Py::new(py, PyTextProxy(proxy))?.into_py(py),
+            am::Value::Scalar(s) => export_scalar(py, s.into_owned(), self.read()?.timestamps)?.1,
+        })
+    }
+
+    fn get(&self, py: Python<'_>, prop: Prop) -> PyResult<Option<PyObject>> {
+        let value = self
+            .read()?
+            .get(PyObjId(self.obj_id.clone()), PyProp(prop), self.heads())?;
+        value.map(|(v, id)| self.wrap(py, v.0, id.0)).transpose()
+    }
+
+    fn len(&self) -> PyResult<usize> {
+        Ok(self
+            .read()?
+            .length(PyObjId(self.obj_id.clone()), self.heads()))
+    }
+
+    /// Resolve a possibly negative Python index, raising IndexError if it is out of range.
+    fn index(&self, index: isize) -> PyResult<usize> {
+        resolve_index(index, self.len()?, false)
+    }
+
+    /// The bounds of `slice` over this sequence and the positions it visits, in order.
+    fn slice(&self, slice: &PySlice) -> PyResult<(PySliceIndices, Vec<usize>)> {
+        let indices = slice.indices(self.len()? as c_long)?;
+        let positions = (0..indices.slicelength)
+            .map(|i| (indices.start + i * indices.step) as usize)
+            .collect();
+        Ok((indices, positions))
+    }
+
+    /// The element at each position of this sequence, as `__getitem__` returns it.
+    fn elements(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        (0..self.len()?)
+            .map(|i| Ok(self.get(py, Prop::Seq(i))?.unwrap_or_else(|| py.None())))
+            .collect()
+    }
+
+    /// The position of the first element equal to `value` between `start` and `stop`, which
+    /// are bounded as for `list.index`.
+    fn find(
+        &self,
+        py: Python<'_>,
+        value: &PyAny,
+        start: isize,
+        stop: Option<isize>,
+    ) -> PyResult<usize> {
+        let elements = self.elements(py)?;
+        let bound = |i: isize| {
+            if i < 0 {
+                elements.len().saturating_sub(i.unsigned_abs())
+            } else {
+                (i as usize).min(elements.len())
+            }
+        };
+        let (start, stop) = (bound(start), stop.map_or(elements.len(), bound));
+        for (i, element) in elements.iter().enumerate().take(stop).skip(start) {
+            if element.as_ref(py).eq(value)? {
+                return Ok(i);
+            }
+        }
+        Err(PyValueError::new_err(format!(
+            "{} is not in the sequence",
+            value.repr()?
+        )))
+    }
+
+    fn count(&self, py: Python<'_>, value: &PyAny) -> PyResult<usize> {
+        let mut count = 0;
+        for element in self.elements(py)? {
+            if element.as_ref(py).eq(value)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn reversed(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let mut elements = self.elements(py)?;
+        elements.reverse();
+        Ok(PyList::new(py, elements).call_method0("__iter__")?.into())
+    }
+
+    /// Like `get`, but with a nested object as a copy of its contents rather than a proxy, for
+    /// values which are about to be removed from the document.
+    fn get_detached(&self, py: Python<'_>, prop: Prop) -> PyResult<Option<PyObject>> {
+        self.get(py, prop)?
+            .map(|value| Ok(detach(value.into_ref(py))?.into_py(py)))
+            .transpose()
+    }
+
+    fn to_py(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.read()?
+            .to_py(py, PyObjId(self.obj_id.clone()), self.heads())
+    }
+
+    fn repr(&self, py: Python<'_>, name: &str) -> PyResult<String> {
+        Ok(format!("{}({})", name, self.to_py(py)?.as_ref(py).repr()?))
+    }
+
+    /// Proxies compare equal to each other and to plain Python values by content.
+    fn richcmp(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        let other = match extract_proxy(other) {
+            Some(proxy) => proxy.to_py(py)?,
+            None => other.into_py(py),
+        };
+        Ok(self
+            .to_py(py)?
+            .as_ref(py)
+            .rich_compare(other, op)?
+            .into_py(py))
+    }
+}
+
+fn map_entries<R: ReadDoc>(
+    doc: &R,
+    obj: &am::ObjId,
+    heads: Option<&[ChangeHash]>,
+) -> Vec<(String, am::ObjId, am::Value<'static>)> {
+    match heads {
+        Some(heads) => doc.map_range_at(obj, .., heads),
+        None => doc.map_range(obj, ..),
+    }
+    .map(|item| {
+        let id = item.id();
+        (
+            item.key.into_owned(),
+            id,
+            item.value.into_value().into_owned(),
+        )
+    })
+    .collect()
+}
+
+/// `value`, or the contents of `value` if it is a proxy. Proxies are detached before being
+/// written so that writing one doesn't need to read the document while it is locked for writing.
+fn detach(value: &PyAny) -> PyResult<&PyAny> {
+    match extract_proxy(value) {
+        Some(proxy) => Ok(proxy.to_py(value.py())?.into_ref(value.py())),
+        None => Ok(value),
+    }
+}
+
+fn extract_proxy(value: &PyAny) -> Option<Proxy> {
+    if let Ok(map) = value.extract::<PyRef<PyMapProxy>>() {
+        Some(map.0.clone())
+    } else if let Ok(list) = value.extract::<PyRef<PyListProxy>>() {
+        Some(list.0.clone())
+    } else if let Ok(text) = value.extract::<PyRef<PyTextProxy>>() {
+        Some(text.0.clone())
+    } else {
+        None
+    }
+}
+
+/// A map object, usable as a `collections.abc.MutableMapping`.
+#[pyclass(name = "MapProxy")]
+struct PyMapProxy(Proxy);
+
+#[pymethods]
+impl PyMapProxy {
+    #[getter]
+    fn obj_id(&self) -> PyObjId {
+        PyObjId(self.0.obj_id.clone())
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: String) -> PyResult<PyObject> {
+        self.0
+            .get(py, Prop::Map(key.clone()))?
+            .ok_or_else(|| PyKeyError::new_err(key))
+    }
+
+    fn __setitem__(&self, key: String, value: &PyAny) -> PyResult<()> {
+        let value = detach(value)?;
+        self.0
+            .write(|tx| put_value(tx, &self.0.obj_id, Prop::Map(key), value))
+    }
+
+    fn __delitem__(&self, key: String) -> PyResult<()> {
+        self.0.write(|tx| {
+            if tx
+                .get(&self.0.obj_id, key.as_str())
+                .map_err(|e| PyException::new_err(e.to_string()))?
+                .is_none()
+            {
+                return Err(PyKeyError::new_err(key));
+            }
+            tx.delete(&self.0.obj_id, key.as_str())
+                .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))
+        })
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        self.0.len()
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &PyAny) -> PyResult<bool> {
+        match key.extract::<String>() {
+            Ok(key) => Ok(self.0.get(py, Prop::Map(key))?.is_some()),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn __iter__(&self) -> PyResult<PyRange> {
+        self.keys()
+    }
+
+    fn keys(&self) -> PyResult<PyRange> {
+        PyRange::whole(
+            &self.0.inner,
+            PyObjId(self.0.obj_id.clone()),
+            RangeYield::Keys,
+            self.0.heads(),
+        )
+    }
+
+    fn values(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        Ok(self.items(py)?.into_iter().map(|(_, v)| v).collect())
+    }
+
+    fn items(&self, py: Python<'_>) -> PyResult<Vec<(String, PyObject)>> {
+        self.entries()?
+            .into_iter()
+            .map(|(key, id, value)| Ok((key, self.0.wrap(py, value, id)?)))
+            .collect()
+    }
+
+    #[pyo3(signature = (key, default = None))]
+    fn get(&self, py: Python<'_>, key: String, default: Option<PyObject>) -> PyResult<PyObject> {
+        Ok(self
+            .0
+            .get(py, Prop::Map(key))?
+            .unwrap_or_else(|| default.unwrap_or_else(|| py.None())))
+    }
+
+    /// Remove `key` and return its value, or `default` if it is missing. A nested object is
+    /// returned as a copy of its contents.
+    #[pyo3(signature = (key, default = OptionalArg(None)))]
+    fn pop(&self, py: Python<'_>, key: String, default: OptionalArg<'_>) -> PyResult<PyObject> {
+        let Some(value) = self.0.get_detached(py, Prop::Map(key.clone()))? else {
+            return default
+                .0
+                .map(|default| default.into_py(py))
+                .ok_or_else(|| PyKeyError::new_err(key));
+        };
+        self.0.write(|tx| {
+            tx.delete(&self.0.obj_id, key.as_str())
+                .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))
+        })?;
+        Ok(value)
+    }
+
+    /// Remove the first key and return it with its value, as `pop` does.
+    fn popitem(&self, py: Python<'_>) -> PyResult<(String, PyObject)> {
+        let Some(key) = self.entries()?.into_iter().next().map(|(key, _, _)| key) else {
+            return Err(PyKeyError::new_err("popitem(): map is empty"));
+        };
+        let value = self.pop(py, key.clone(), OptionalArg(None))?;
+        Ok((key, value))
+    }
+
+    fn clear(&self) -> PyResult<()> {
+        let entries = self.entries()?;
+        self.0.write(|tx| {
+            for (key, _, _) in entries {
+                tx.delete(&self.0.obj_id, key)
+                    .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Put each item of `other`, a mapping or an iterable of `(key, value)` pairs, and then each
+    /// keyword argument, as `dict.update` does.
+    #[pyo3(signature = (other = None, **kwargs))]
+    fn update(&self, other: Option<&PyAny>, kwargs: Option<&PyDict>) -> PyResult<()> {
+        let mut items = Vec::new();
+        if let Some(other) = other {
+            if other.hasattr("keys")? {
+                for key in other.call_method0("keys")?.iter()? {
+                    let key = key?;
+                    items.push((key.extract::<String>()?, detach(other.get_item(key)?)?));
+                }
+            } else {
+                for item in other.iter()? {
+                    let (key, value): (String, &PyAny) = item?.extract()?;
+                    items.push((key, detach(value)?));
+                }
+            }
+        }
+        for (key, value) in kwargs.into_iter().flatten() {
+            items.push((key.extract()?, detach(value)?));
+        }
+        self.0.write(|tx| {
+            for (key, value) in items {
+                put_value(tx, &self.0.obj_id, Prop::Map(key), value)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// The value of `key`, first putting `default` there if it is missing.
+    #[pyo3(signature = (key, default = None))]
+    fn setdefault(
+        &self,
+        py: Python<'_>,
+        key: String,
+        default: Option<&PyAny>,
+    ) -> PyResult<PyObject> {
+        if let Some(value) = self.0.get(py, Prop::Map(key.clone()))? {
+            return Ok(value);
+        }
+        self.__setitem__(
+            key.clone(),
+            default.unwrap_or_else(|| py.None().into_ref(py)),
+        )?;
+        self.__getitem__(py, key)
+    }
+
+    fn to_py(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.0.to_py(py)
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        self.0.repr(py, "MapProxy")
+    }
+
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        self.0.richcmp(py, other, op)
+    }
+}
+
+impl PyMapProxy {
+    fn entries(&self) -> PyResult<Vec<(String, am::ObjId, am::Value<'static>)>> {
+        let inner = self.0.read()?;
+        Ok(match inner.tx.as_ref() {
+            Some(tx) => map_entries(tx, &self.0.obj_id, self.0.heads.as_deref()),
+            None => map_entries(&inner.doc, &self.0.obj_id, self.0.heads.as_deref()),
+        })
+    }
+}
+
+/// A list object, usable as a `collections.abc.MutableSequence`. Indices may be negative, and
+/// slices are supported as for `list`. Values removed or moved by `pop`, `reverse` and the like
+/// are copied as plain Python values, so nested objects come back as dicts and lists.
+#[pyclass(name = "ListProxy")]
+struct PyListProxy(Proxy);
+
+#[pymethods]
+impl PyListProxy {
+    #[getter]
+    fn obj_id(&self) -> PyObjId {
+        PyObjId(self.0.obj_id.clone())
+    }
+
+    fn __getitem__(&self, py: Python<'_>, index: &PyAny) -> PyResult<PyObject> {
+        if let Ok(slice) = index.downcast::<PySlice>() {
+            let (_, positions) = self.0.slice(slice)?;
+            let items = positions
+                .into_iter()
+                .map(|i| Ok(self.0.get(py, Prop::Seq(i))?.unwrap_or_else(|| py.None())))
+                .collect::<PyResult<Vec<_>>>()?;
+            return Ok(PyList::new(py, items).into());
+        }
+        let index = self.0.index(index.extract()?)?;
+        self.0
+            .get(py, Prop::Seq(index))?
+            .ok_or_else(|| PyIndexError::new_err(format!("index {} out of range", index)))
+    }
+
+    fn __setitem__(&self, index: &PyAny, value: &PyAny) -> PyResult<()> {
+        if let Ok(slice) = index.downcast::<PySlice>() {
+            let values = value
+                .iter()?
+                .map(|value| detach(value?))
+                .collect::<PyResult<Vec<_>>>()?;
+            let (indices, positions) = self.0.slice(slice)?;
+            return self.0.write(|tx| {
+                if indices.step == 1 {
+                    let start = indices.start as usize;
+                    tx.splice(
+                        &self.0.obj_id,
+                        start,
+                        positions.len() as isize,
+                        Vec::<ScalarValue>::new(),
+                    )
+                    .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))?;
+                    for (i, value) in values.into_iter().enumerate() {
+                        insert_value(tx, &self.0.obj_id, start + i, value)?;
+                    }
+                } else {
+                    if values.len() != positions.len() {
+                        return Err(PyValueError::new_err(format!(
+                            "attempt to assign sequence of size {} to extended slice of size {}",
+                            values.len(),
+                            positions.len()
+                        )));
+                    }
+                    for (i, value) in positions.into_iter().zip(values) {
+                        put_value(tx, &self.0.obj_id, Prop::Seq(i), value)?;
+                    }
+                }
+                Ok(())
+            });
+        }
+        let index = self.0.index(index.extract()?)?;
+        let value = detach(value)?;
+        self.0
+            .write(|tx| put_value(tx, &self.0.obj_id, Prop::Seq(index), value))
+    }
+
+    fn __delitem__(&self, index: &PyAny) -> PyResult<()> {
+        let mut positions = match index.downcast::<PySlice>() {
+            Ok(slice) => self.0.slice(slice)?.1,
+            Err(_) => vec![self.0.index(index.extract()?)?],
+        };
+        positions.sort_unstable();
+        self.0.write(|tx| {
+            for i in positions.into_iter().rev() {
+                tx.delete(&self.0.obj_id, i)
+                    .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn __reversed__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.0.reversed(py)
+    }
+
+    #[pyo3(signature = (value, start = 0, stop = None))]
+    fn index(
+        &self,
+        py: Python<'_>,
+        value: &PyAny,
+        start: isize,
+        stop: Option<isize>,
+    ) -> PyResult<usize> {
+        self.0.find(py, value, start, stop)
+    }
+
+    fn count(&self, py: Python<'_>, value: &PyAny) -> PyResult<usize> {
+        self.0.count(py, value)
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        self.0.len()
+    }
+
+    fn __contains__(&self, py: Python<'_>, value: &PyAny) -> PyResult<bool> {
+        self.0
+            .to_py(py)?
+            .as_ref(py)
+            .downcast::<PyList>()?
+            .contains(value)
     }
 
-    fn get_heads(&self) -> PyResult<Vec<PyChangeHash>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(inner.get_heads())
+    /// Insert `value` before `index`, inferring its type as `Transaction.put_object` does for
+    /// nested values.
+    fn insert(&self, index: isize, value: &PyAny) -> PyResult<()> {
+        let len = self.0.len()?;
+        let index = if index < 0 {
+            len.saturating_sub(index.unsigned_abs())
+        } else {
+            (index as usize).min(len)
+        };
+        self.0
+            .write(|tx| insert_value(tx, &self.0.obj_id, index, value))
     }
 
-    fn get_last_local_change(&self) -> PyResult<Option<PyChange>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(inner
-            .doc
-            .get_last_local_change()
-            .map(|c| PyChange(c.to_owned())))
+    fn append(&self, value: &PyAny) -> PyResult<()> {
+        self.extend(PyList::new(value.py(), [value]))
     }
 
-    fn object_type(&self, obj_id: PyObjId) -> PyResult<PyObjType> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.object_type(obj_id)
+    fn extend(&self, values: &PyAny) -> PyResult<()> {
+        let values = values
+            .iter()?
+            .map(|value| detach(value?))
+            .collect::<PyResult<Vec<_>>>()?;
+        let len = self.0.len()?;
+        self.0.write(|tx| {
+            for (i, value) in values.into_iter().enumerate() {
+                insert_value(tx, &self.0.obj_id, len + i, value)?;
+            }
+            Ok(())
+        })
     }
 
-    fn get_changes(&self, have_deps: Vec<PyChangeHash>) -> PyResult<Vec<PyChange>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        if inner.tx.is_some() {
-            return Err(PyException::new_err(
-                "cannot get changes with an active transaction",
-            ));
-        }
+    fn __iadd__(&self, values: &PyAny) -> PyResult<()> {
+        self.extend(values)
+    }
 
-        let changes: Vec<ChangeHash> = have_deps.iter().map(|h| h.0).collect();
-        Ok(inner
-            .doc
-            .get_changes(&changes)
-            .into_iter()
-            .map(|c| PyChange(c.to_owned()))
-            .collect())
+    /// Remove the element at `index` and return it.
+    #[pyo3(signature = (index = -1))]
+    fn pop(&self, py: Python<'_>, index: isize) -> PyResult<PyObject> {
+        let index = self.0.index(index)?;
+        let value = self
+            .0
+            .get_detached(py, Prop::Seq(index))?
+            .unwrap_or_else(|| py.None());
+        self.0.write(|tx| {
+            tx.delete(&self.0.obj_id, index)
+                .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))
+        })?;
+        Ok(value)
     }
 
-    fn get(
-        &self,
-        obj_id: PyObjId,
-        prop: PyProp,
-        heads: Option<Vec<PyChangeHash>>,
-    ) -> PyResult<Option<(PyValue, PyObjId)>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.get(obj_id, prop, heads)
+    /// Remove the first element equal to `value`.
+    fn remove(&self, py: Python<'_>, value: &PyAny) -> PyResult<()> {
+        let index = self.0.find(py, value, 0, None)?;
+        self.0.write(|tx| {
+            tx.delete(&self.0.obj_id, index)
+                .map_err(|e| PyException::new_err(format!("error deleting: {}", e)))
+        })
     }
 
-    fn keys(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<String>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.keys(obj_id, heads)
+    fn reverse(&self, py: Python<'_>) -> PyResult<()> {
+        let len = self.0.len()?;
+        let values = (0..len)
+            .rev()
+            .map(|i| {
+                Ok(self
+                    .0
+                    .get_detached(py, Prop::Seq(i))?
+                    .unwrap_or_else(|| py.None()))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        self.__setitem__(PySlice::full(py), PyList::new(py, values))
     }
 
-    fn values(
-        &self,
-        obj_id: PyObjId,
-        heads: Option<Vec<PyChangeHash>>,
-    ) -> PyResult<Vec<(PyValue, PyObjId)>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.values(obj_id, heads)
+    fn clear(&self, py: Python<'_>) -> PyResult<()> {
+        self.__delitem__(PySlice::full(py))
     }
 
-    fn length(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<usize> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(inner.length(obj_id, heads))
+    fn to_py(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.0.to_py(py)
     }
 
-    fn text(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<String> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.text(obj_id, heads)
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        self.0.repr(py, "ListProxy")
     }
 
-    fn marks(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyMark>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.marks(obj_id, heads)
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        self.0.richcmp(py, other, op)
     }
 }
 
-#[derive(Clone)]
-#[pyclass]
-struct Transaction {
-    inner: Arc<RwLock<Inner>>,
-}
+/// A text object, usable as a `collections.abc.Sequence` of characters. Positions are counted
+/// in the document's text encoding.
+#[pyclass(name = "TextProxy")]
+struct PyTextProxy(Proxy);
 
 #[pymethods]
-impl Transaction {
-    #[pyo3(name = "__enter__")]
-    fn enter(&self) -> PyResult<Transaction> {
-        Ok(self.clone())
+impl PyTextProxy {
+    #[getter]
+    fn obj_id(&self) -> PyObjId {
+        PyObjId(self.0.obj_id.clone())
     }
 
-    #[pyo3(name = "__exit__")]
-    fn exit(
-        &self,
-        exc_type: Option<&PyAny>,
-        exc_value: Option<&PyAny>,
-        traceback: Option<&PyAny>,
-    ) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        if let Some(tx) = inner.tx.take() {
-            if let Some(exc_type) = exc_type {
-                tx.rollback();
-            } else {
-                tx.commit();
+    fn __getitem__(&self, py: Python<'_>, index: &PyAny) -> PyResult<PyObject> {
+        if let Ok(slice) = index.downcast::<PySlice>() {
+            let (indices, positions) = self.0.slice(slice)?;
+            if indices.step == 1 {
+                let end = indices.start as usize + positions.len();
+                return Ok(self
+                    .0
+                    .read()?
+                    .text_range(
+                        PyObjId(self.0.obj_id.clone()),
+                        indices.start as usize,
+                        end,
+                        self.0.heads(),
+                    )
+                    .into_py(py));
             }
+            let mut text = String::new();
+            for i in positions {
+                if let Some(c) = self.0.get(py, Prop::Seq(i))? {
+                    text.push_str(c.extract(py)?);
+                }
+            }
+            return Ok(text.into_py(py));
         }
-        Ok(())
+        let index = self.0.index(index.extract()?)?;
+        self.0
+            .get(py, Prop::Seq(index))?
+            .ok_or_else(|| PyIndexError::new_err(format!("index {} out of range", index)))
     }
 
-    fn get_heads(&self) -> PyResult<Vec<PyChangeHash>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(inner.get_heads())
+    /// Replace the character at `index`, or the characters in a slice of step 1, with the string
+    /// `value`.
+    fn __setitem__(&self, index: &PyAny, value: &str) -> PyResult<()> {
+        let (start, len) = self.span(index)?;
+        self.0.write(|tx| {
+            tx.splice_text(&self.0.obj_id, start, len, value)
+                .map_err(|e| PyException::new_err(format!("error splicing: {}", e)))
+        })
     }
 
-    fn object_type(&self, obj_id: PyObjId) -> PyResult<PyObjType> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.object_type(obj_id)
+    fn __delitem__(&self, index: &PyAny) -> PyResult<()> {
+        self.__setitem__(index, "")
     }
 
-    fn get(
+    fn __reversed__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.0.reversed(py)
+    }
+
+    /// The position of the first character equal to `value`, as `Sequence.index` finds it.
+    #[pyo3(signature = (value, start = 0, stop = None))]
+    fn index(
         &self,
-        obj_id: PyObjId,
-        prop: PyProp,
-        heads: Option<Vec<PyChangeHash>>,
-    ) -> PyResult<Option<(PyValue, PyObjId)>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.get(obj_id, prop, heads)
+        py: Python<'_>,
+        value: &PyAny,
+        start: isize,
+        stop: Option<isize>,
+    ) -> PyResult<usize> {
+        self.0.find(py, value, start, stop)
     }
 
-    fn keys(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<String>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.keys(obj_id, heads)
+    fn count(&self, py: Python<'_>, value: &PyAny) -> PyResult<usize> {
+        self.0.count(py, value)
     }
 
-    fn values(
-        &self,
-        obj_id: PyObjId,
-        heads: Option<Vec<PyChangeHash>>,
-    ) -> PyResult<Vec<(PyValue, PyObjId)>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.values(obj_id, heads)
+    fn __len__(&self) -> PyResult<usize> {
+        self.0.len()
     }
 
-    fn length(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<usize> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        Ok(inner.length(obj_id, heads))
+    fn __contains__(&self, value: &str) -> PyResult<bool> {
+        Ok(self.__str__()?.contains(value))
     }
 
-    fn text(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<String> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.text(obj_id, heads)
+    fn __str__(&self) -> PyResult<String> {
+        self.0
+            .read()?
+            .text(PyObjId(self.0.obj_id.clone()), self.0.heads())
     }
 
-    fn marks(&self, obj_id: PyObjId, heads: Option<Vec<PyChangeHash>>) -> PyResult<Vec<PyMark>> {
-        let inner = self
-            .inner
-            .read()
-            .map_err(|e| PyException::new_err(e.to_string()))?;
-        inner.marks(obj_id, heads)
+    fn to_py(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.0.to_py(py)
     }
 
-    fn put(
-        &mut self,
-        obj_id: PyObjId,
-        prop: PyProp,
-        value_type: &PyScalarType,
-        value: &PyAny,
-    ) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
-        };
-        tx.put(obj_id.0, prop.0, import_scalar(value, value_type)?)
-            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        self.0.repr(py, "TextProxy")
     }
 
-    fn put_object(
-        &mut self,
-        obj_id: PyObjId,
-        prop: PyProp,
-        objtype: &PyObjType,
-    ) -> PyResult<PyObjId> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
-        };
-        tx.put_object(obj_id.0, prop.0, objtype.into())
-            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
-            .map(PyObjId)
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        self.0.richcmp(py, other, op)
     }
+}
 
-    fn insert(
-        &mut self,
-        obj_id: PyObjId,
-        index: usize,
-        value_type: &PyScalarType,
-        value: &PyAny,
-    ) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
+impl PyTextProxy {
+    /// The start and length of the characters an index or a slice of step 1 covers.
+    fn span(&self, index: &PyAny) -> PyResult<(usize, isize)> {
+        match index.downcast::<PySlice>() {
+            Ok(slice) => {
+                let (indices, positions) = self.0.slice(slice)?;
+                if indices.step != 1 {
+                    return Err(PyTypeError::new_err(
+                        "text can only be modified through slices of step 1",
+                    ));
+                }
+                Ok((indices.start as usize, positions.len() as isize))
+            }
+            Err(_) => Ok((self.0.index(index.extract()?)?, 1)),
+        }
+    }
+}
+
+/// One step of a path passed to `lookup`.
+enum PathSegment {
+    /// A key or index, where a negative index counts back from the end of the sequence.
+    Prop(PyKey),
+    /// A segment of a string path, which is an index if it is numeric and the object being
+    /// looked in is a sequence.
+    Key(String),
+}
+
+fn extract_path(path: &PyAny) -> PyResult<Vec<PathSegment>> {
+    if let Ok(path) = path.extract::<&str>() {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        if path.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Segments are escaped as in JSON pointers.
+        Ok(path
+            .split('/')
+            .map(|s| PathSegment::Key(s.replace("~1", "/").replace("~0", "~")))
+            .collect())
+    } else {
+        path.iter()?
+            .map(|prop| Ok(PathSegment::Prop(prop?.extract::<PyKey>()?)))
+            .collect()
+    }
+}
+
+/// One step of a query passed to `query`.
+#[derive(Debug)]
+enum QueryStep {
+    Key(String),
+    Index(isize),
+    Wildcard,
+    /// The children for which the value at `path`, relative to the child, equals `value`, or
+    /// differs from it if `negate` is set.
+    Filter {
+        path: Vec<QueryStep>,
+        negate: bool,
+        value: QueryLiteral,
+    },
+}
+
+#[derive(Debug)]
+enum QueryLiteral {
+    Str(String),
+    Number(f64),
+    Bool(bool),
+    Null,
+}
+
+impl QueryLiteral {
+    fn matches(&self, value: &am::Value<'_>) -> bool {
+        let Some(scalar) = value.to_scalar() else {
+            return false;
         };
-        tx.insert(obj_id.0, index, import_scalar(value, value_type)?)
-            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+        match (self, scalar) {
+            (QueryLiteral::Str(a), ScalarValue::Str(b)) => a == b.as_str(),
+            (QueryLiteral::Bool(a), ScalarValue::Boolean(b)) => a == b,
+            (QueryLiteral::Null, ScalarValue::Null) => true,
+            (QueryLiteral::Number(n), ScalarValue::Int(i)) => *i as f64 == *n,
+            (QueryLiteral::Number(n), ScalarValue::Uint(u)) => *u as f64 == *n,
+            (QueryLiteral::Number(n), ScalarValue::F64(f)) => f == n,
+            (QueryLiteral::Number(n), ScalarValue::Counter(c)) => i64::from(c) as f64 == *n,
+            _ => false,
+        }
+    }
+}
+
+fn parse_query(expr: &str) -> PyResult<Vec<QueryStep>> {
+    let mut parser = QueryParser {
+        chars: expr.chars().collect(),
+        pos: 0,
+    };
+    parser.skip_whitespace();
+    parser.expect('$')?;
+    let steps = parser.steps(true)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected character"));
+    }
+    Ok(steps)
+}
+
+struct QueryParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, msg: &str) -> PyErr {
+        PyException::new_err(format!("invalid query: {} at position {}", msg, self.pos))
+    }
+
+    fn expect(&mut self, c: char) -> PyResult<()> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Parse steps until the end of a path. Wildcards and filters are only allowed in the
+    /// top-level query, not in the relative paths of filters.
+    fn steps(&mut self, top_level: bool) -> PyResult<Vec<QueryStep>> {
+        let mut steps = Vec::new();
+        loop {
+            let step = match self.peek() {
+                Some('.') => {
+                    self.pos += 1;
+                    if self.peek() == Some('*') {
+                        self.pos += 1;
+                        QueryStep::Wildcard
+                    } else {
+                        QueryStep::Key(self.name()?)
+                    }
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                    let step = match self.peek() {
+                        Some('*') => {
+                            self.pos += 1;
+                            QueryStep::Wildcard
+                        }
+                        Some('?') => {
+                            self.pos += 1;
+                            self.filter()?
+                        }
+                        Some('\'' | '"') => QueryStep::Key(self.string()?),
+                        _ => QueryStep::Index(self.index()?),
+                    };
+                    self.skip_whitespace();
+                    self.expect(']')?;
+                    step
+                }
+                _ => return Ok(steps),
+            };
+            if !top_level && matches!(step, QueryStep::Wildcard | QueryStep::Filter { .. }) {
+                return Err(self.error("wildcards and filters are not allowed in filter paths"));
+            }
+            steps.push(step);
+        }
+    }
+
+    fn name(&mut self) -> PyResult<String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("expected a key"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn string(&mut self) -> PyResult<String> {
+        let quote = self.peek();
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some('\\') => {
+                    self.pos += 1;
+                    let Some(c) = self.peek() else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    s.push(c);
+                }
+                Some(c) if Some(c) == quote => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(c) => s.push(c),
+            }
+            self.pos += 1;
+        }
     }
 
-    fn insert_object(
-        &mut self,
-        obj_id: PyObjId,
-        index: usize,
-        objtype: &PyObjType,
-    ) -> PyResult<PyObjId> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
-        };
-        tx.insert_object(obj_id.0, index, objtype.into())
-            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
-            .map(PyObjId)
+    fn index(&mut self) -> PyResult<isize> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .map_err(|_| self.error("expected an index, key, '*' or filter"))
     }
 
-    fn increment(&mut self, obj_id: PyObjId, prop: PyProp, value: i64) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
+    /// Parse a filter after its `?`, as in `(@.done == false)`.
+    fn filter(&mut self) -> PyResult<QueryStep> {
+        self.skip_whitespace();
+        self.expect('(')?;
+        self.skip_whitespace();
+        self.expect('@')?;
+        let path = self.steps(false)?;
+        self.skip_whitespace();
+        let negate = match (self.peek(), self.chars.get(self.pos + 1)) {
+            (Some('='), Some('=')) => false,
+            (Some('!'), Some('=')) => true,
+            _ => return Err(self.error("expected '==' or '!='")),
         };
-        tx.increment(obj_id.0, prop.0, value)
-            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+        self.pos += 2;
+        self.skip_whitespace();
+        let value = self.literal()?;
+        self.skip_whitespace();
+        self.expect(')')?;
+        Ok(QueryStep::Filter {
+            path,
+            negate,
+            value,
+        })
     }
 
-    fn delete(&mut self, obj_id: PyObjId, prop: PyProp) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
-        };
-        tx.delete(obj_id.0, prop.0)
-            .map_err(|e| PyException::new_err(format!("error putting: {}", e)))
+    fn literal(&mut self) -> PyResult<QueryLiteral> {
+        if let Some('\'' | '"') = self.peek() {
+            return Ok(QueryLiteral::Str(self.string()?));
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '-' | '+' | '.'))
+        {
+            self.pos += 1;
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        match word.as_str() {
+            "true" => Ok(QueryLiteral::Bool(true)),
+            "false" => Ok(QueryLiteral::Bool(false)),
+            "null" => Ok(QueryLiteral::Null),
+            _ => word
+                .parse()
+                .map(QueryLiteral::Number)
+                .map_err(|_| self.error("expected a string, number, boolean or null")),
+        }
     }
+}
 
-    fn mark(
-        &mut self,
-        obj_id: PyObjId,
-        start: usize,
-        end: usize,
-        name: &str,
-        value_type: &PyScalarType,
-        value: &PyAny,
-        expand: &PyExpandMark,
-    ) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
-        };
-        let value = import_scalar(value, value_type)?;
-        tx.mark(
-            obj_id.0,
-            Mark::new(name.to_owned(), value, start, end),
-            expand.into(),
-        )
-        .map_err(|e| PyException::new_err(e.to_string()))
-    }
+type QueryMatch = (Vec<Prop>, am::Value<'static>, am::ObjId);
 
-    fn unmark(
-        &mut self,
-        obj_id: PyObjId,
-        start: usize,
-        end: usize,
-        name: &str,
-        expand: &PyExpandMark,
-    ) -> PyResult<()> {
-        let mut inner = self
-            .inner
-            .write()
-            .map_err(|e| PyException::new_err(format!("error getting write lock: {}", e)))?;
-        let Some(tx) = inner.tx.as_mut() else {
-            return Err(PyException::new_err("transaction no longer active"));
-        };
-        tx.unmark(obj_id.0, name, start, end, expand.into())
-            .map_err(|e| PyException::new_err(e.to_string()))
+/// Apply `steps` to the single match `start`, returning every match at the end of the path.
+fn run_query<R: ReadDoc>(
+    doc: &R,
+    start: QueryMatch,
+    steps: &[QueryStep],
+    heads: Option<&[ChangeHash]>,
+) -> Result<Vec<QueryMatch>, am::AutomergeError> {
+    let mut matches = vec![start];
+    for step in steps {
+        let mut next = Vec::new();
+        for (path, value, id) in matches {
+            let am::Value::Object(objtype) = value else {
+                continue;
+            };
+            for (prop, value, child) in query_children(doc, &id, objtype, step, heads)? {
+                let mut path = path.clone();
+                path.push(prop);
+                next.push((path, value, child));
+            }
+        }
+        matches = next;
     }
+    Ok(matches)
 }
 
-fn datetime_to_timestamp(datetime: &PyDateTime) -> PyResult<i64> {
-    Ok((datetime.call_method0("timestamp")?.extract::<f64>()? * 1000.0).round() as i64)
+/// The children of `obj` selected by `step`, as `(prop, value, id)` triples.
+fn query_children<R: ReadDoc>(
+    doc: &R,
+    obj: &am::ObjId,
+    objtype: ObjType,
+    step: &QueryStep,
+    heads: Option<&[ChangeHash]>,
+) -> Result<Vec<(Prop, am::Value<'static>, am::ObjId)>, am::AutomergeError> {
+    let is_seq = matches!(objtype, ObjType::List | ObjType::Text);
+    let prop = match step {
+        QueryStep::Key(key) if !is_seq => Prop::Map(key.clone()),
+        QueryStep::Index(index) if is_seq => {
+            let len = match heads {
+                Some(heads) => doc.length_at(obj, heads),
+                None => doc.length(obj),
+            };
+            let index = if *index < 0 {
+                len.checked_sub(index.unsigned_abs())
+            } else {
+                Some(*index as usize)
+            };
+            match index {
+                Some(index) => Prop::Seq(index),
+                None => return Ok(Vec::new()),
+            }
+        }
+        QueryStep::Key(_) | QueryStep::Index(_) => return Ok(Vec::new()),
+        QueryStep::Wildcard => return Ok(all_children(doc, obj, is_seq, heads)),
+        QueryStep::Filter {
+            path,
+            negate,
+            value,
+        } => {
+            let mut selected = Vec::new();
+            for (prop, child_value, child) in all_children(doc, obj, is_seq, heads) {
+                let start = (Vec::new(), child_value.clone(), child.clone());
+                let found = run_query(doc, start, path, heads)?
+                    .iter()
+                    .any(|(_, v, _)| value.matches(v));
+                if found != *negate {
+                    selected.push((prop, child_value, child));
+                }
+            }
+            return Ok(selected);
+        }
+    };
+    let found = match heads {
+        Some(heads) => doc.get_at(obj, prop.clone(), heads)?,
+        None => doc.get(obj, prop.clone())?,
+    };
+    Ok(found
+        .map(|(value, id)| (prop, value.into_owned(), id))
+        .into_iter()
+        .collect())
 }
 
-fn import_scalar(value: &PyAny, scalar_type: &PyScalarType) -> Result<ScalarValue, PyErr> {
-    Ok(match scalar_type {
-        PyScalarType::Bytes => ScalarValue::Bytes(value.extract::<&[u8]>()?.to_owned()),
-        PyScalarType::Str => ScalarValue::Str(value.extract::<String>()?.into()),
-        PyScalarType::Int => ScalarValue::Int(value.extract::<i64>()?),
-        PyScalarType::Uint => ScalarValue::Uint(value.extract::<u64>()?),
-        PyScalarType::F64 => ScalarValue::F64(value.extract::<f64>()?),
-        PyScalarType::Counter => todo!(),
-        PyScalarType::Timestamp => {
-            ScalarValue::Timestamp(datetime_to_timestamp(value.downcast::<PyDateTime>()?)?)
+fn all_children<R: ReadDoc>(
+    doc: &R,
+    obj: &am::ObjId,
+    is_seq: bool,
+    heads: Option<&[ChangeHash]>,
+) -> Vec<(Prop, am::Value<'static>, am::ObjId)> {
+    if is_seq {
+        match heads {
+            Some(heads) => doc.list_range_at(obj, .., heads),
+            None => doc.list_range(obj, ..),
         }
-        PyScalarType::Boolean => ScalarValue::Boolean(value.extract::<bool>()?),
-        PyScalarType::Unknown => todo!(),
-        PyScalarType::Null => ScalarValue::Null,
-    })
+        .map(|item| {
+            let id = item.id();
+            (
+                Prop::Seq(item.index),
+                item.value.into_value().into_owned(),
+                id,
+            )
+        })
+        .collect()
+    } else {
+        match heads {
+            Some(heads) => doc.map_range_at(obj, .., heads),
+            None => doc.map_range(obj, ..),
+        }
+        .map(|item| {
+            let id = item.id();
+            let prop = Prop::Map(item.key.to_string());
+            (prop, item.value.into_value().into_owned(), id)
+        })
+        .collect()
+    }
 }
 
-#[pyclass(name = "SyncState")]
-struct PySyncState(am::sync::State);
+#[derive(Debug, Clone)]
+pub struct PyProp(Prop);
 
-#[pymethods]
-impl PySyncState {
-    #[new]
-    pub fn new() -> PySyncState {
-        PySyncState(am::sync::State::new())
+impl<'a> FromPyObject<'a> for PyProp {
+    fn extract(prop: &'a PyAny) -> PyResult<Self> {
+        if let Ok(key) = prop.extract::<String>() {
+            return Ok(PyProp(Prop::Map(key)));
+        }
+        if let Ok(index) = prop.extract::<usize>() {
+            return Ok(PyProp(Prop::Seq(index)));
+        }
+        if prop.downcast::<PyLong>().is_ok() {
+            return Err(PyIndexError::new_err(format!(
+                "index {} out of range",
+                prop
+            )));
+        }
+        Err(PyTypeError::new_err(format!(
+            "expected a str key or an int index, not {}",
+            prop.get_type().name()?
+        )))
     }
 }
 
-#[pyclass(name = "Message")]
-struct PyMessage(am::sync::Message);
+impl IntoPy<PyObject> for PyProp {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        match self.0 {
+            Prop::Map(key) => key.into_py(py),
+            Prop::Seq(index) => index.into_py(py),
+        }
+    }
+}
 
-#[pymethods]
-impl PyMessage {
-    pub fn encode<'py>(&self, py: Python<'py>) -> &'py PyBytes {
-        PyBytes::new(py, &self.0.clone().encode())
+/// A prop as accepted by `get` and `delete`, where a negative index counts back from the end of
+/// the sequence as in Python.
+#[derive(Debug, Clone)]
+enum PyKey {
+    Key(String),
+    Index(isize),
+}
+
+impl<'a> FromPyObject<'a> for PyKey {
+    fn extract(prop: &'a PyAny) -> PyResult<Self> {
+        match prop.extract::<String>() {
+            Ok(key) => Ok(PyKey::Key(key)),
+            Err(_) => Ok(PyKey::Index(prop.extract()?)),
+        }
     }
+}
 
-    #[staticmethod]
-    pub fn decode(bytes: &[u8]) -> PyResult<PyMessage> {
-        Ok(PyMessage(
-            am::sync::Message::decode(bytes).map_err(|e| PyException::new_err(e.to_string()))?,
-        ))
+/// A prop, or a tuple of props leading from an object through its nested objects, as in
+/// `("settings", "theme", "color")`.
+#[derive(Debug, Clone)]
+struct PyPropPath(Vec<PyKey>);
+
+impl<'a> FromPyObject<'a> for PyPropPath {
+    fn extract(path: &'a PyAny) -> PyResult<Self> {
+        match path.downcast::<PyTuple>() {
+            Ok(path) => Ok(PyPropPath(path.extract()?)),
+            Err(_) => Ok(PyPropPath(vec![path.extract()?])),
+        }
     }
 }
 
-#[pyfunction]
-fn random_actor_id<'py>(py: Python<'py>) -> &'py PyBytes {
-    PyBytes::new(py, ActorId::random().to_bytes())
+/// The object holding the last segment of `path` from `obj`, and that segment as a prop. If
+/// `create` is set maps missing along the way are created, otherwise they raise KeyError.
+fn follow_mut(
+    tx: &mut am::transaction::Transaction<'_>,
+    obj: am::ObjId,
+    path: PyPropPath,
+    create: bool,
+) -> PyResult<(am::ObjId, Prop)> {
+    let mut obj = obj;
+    let mut segments = path.0.into_iter().peekable();
+    while let Some(key) = segments.next() {
+        let prop = match key {
+            PyKey::Key(key) => Prop::Map(key),
+            PyKey::Index(index) => Prop::Seq(resolve_index(index, tx.length(&obj), false)?),
+        };
+        if segments.peek().is_none() {
+            return Ok((obj, prop));
+        }
+        let existing = tx
+            .get(&obj, prop.clone())
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        obj = match existing {
+            Some((am::Value::Object(_), id)) => id,
+            Some((am::Value::Scalar(_), _)) => {
+                return Err(PyException::new_err(format!(
+                    "path segment {} is not an object",
+                    prop
+                )))
+            }
+            None if create => tx
+                .put_object(&obj, prop, ObjType::Map)
+                .map_err(|e| PyException::new_err(format!("error putting: {}", e)))?,
+            None => return Err(PyKeyError::new_err(prop.to_string())),
+        };
+    }
+    Err(PyException::new_err("path must not be empty"))
 }
 
-/// A Python module implemented in Rust.
-#[pymodule]
-fn _automerge(_py: Python, m: &PyModule) -> PyResult<()> {
-    // Classes
-    m.add_class::<Document>()?;
-    m.add_class::<Transaction>()?;
-    m.add_class::<PySyncState>()?;
-    m.add_class::<PyMessage>()?;
+/// An argument which may be left out. Unlike an `Option` argument, an explicit `None` is kept as
+/// `Some`, so that `put("key", None)` can be told apart from a missing value.
+#[derive(Clone, Copy)]
+struct OptionalArg<'a>(Option<&'a PyAny>);
 
-    // Enums
-    m.add_class::<PyObjType>()?;
-    m.add_class::<PyScalarType>()?;
-    m.add_class::<PyExpandMark>()?;
+impl<'a> FromPyObject<'a> for OptionalArg<'a> {
+    fn extract(arg: &'a PyAny) -> PyResult<Self> {
+        Ok(OptionalArg(Some(arg)))
+    }
+}
 
-    // Constants
-    m.add("ROOT", PyObjId(am::ROOT))?;
+impl<'a> OptionalArg<'a> {
+    fn required(self, method: &str, name: &str) -> PyResult<&'a PyAny> {
+        self.0.ok_or_else(|| missing_arg(method, name))
+    }
 
-    // Functions
-    m.add_function(wrap_pyfunction!(random_actor_id, m)?)?;
-    Ok(())
+    /// The argument, with `None` passed or left out both giving `None`.
+    fn extract<T: FromPyObject<'a>>(self) -> PyResult<Option<T>> {
+        Ok(self.0.map(|arg| arg.extract()).transpose()?.flatten())
+    }
 }
 
-#[derive(Debug)]
-pub struct PyProp(Prop);
+fn missing_arg(method: &str, name: &str) -> PyErr {
+    PyTypeError::new_err(format!("{}() missing required argument '{}'", method, name))
+}
 
-impl<'a> FromPyObject<'a> for PyProp {
-    fn extract(prop: &'a PyAny) -> PyResult<Self> {
-        Ok(PyProp(match prop.extract::<String>() {
-            Ok(s) => Prop::Map(s),
-            Err(_) => match prop.extract::<usize>() {
-                Ok(i) => Prop::Seq(i),
-                Err(_) => todo!(),
-            },
-        }))
+/// Bind the arguments of a method taking `obj_id` followed by `rest`. `obj_id` may be left out
+/// to address the root map, in which case a str, int or tuple lands in its place and is the first
+/// prop, and each positional argument after it belongs one place further on: `get("title", heads)`
+/// is `get(ROOT, "title", heads)`. Keyword arguments past the positional ones keep their names.
+fn root_args<'a, const N: usize>(
+    obj_id: OptionalArg<'a>,
+    rest: [OptionalArg<'a>; N],
+) -> PyResult<(PyObjId, [OptionalArg<'a>; N])> {
+    let first = match obj_id.0 {
+        None => return Ok((PyObjId(am::ROOT), rest)),
+        Some(first)
+            if !(first.is_instance_of::<PyString>()
+                || first.is_instance_of::<PyLong>()
+                || first.is_instance_of::<PyTuple>()) =>
+        {
+            return Ok((first.extract()?, rest));
+        }
+        Some(first) => first,
+    };
+    let positional = rest.iter().take_while(|arg| arg.0.is_some()).count();
+    if positional == N {
+        return Err(PyTypeError::new_err(format!(
+            "expected at most {} arguments when obj_id is left out",
+            N
+        )));
     }
+    let mut shifted = rest;
+    shifted[0] = OptionalArg(Some(first));
+    shifted[1..=positional].copy_from_slice(&rest[..positional]);
+    Ok((PyObjId(am::ROOT), shifted))
 }
 
-#[derive(Debug)]
+/// Resolve a possibly negative Python index into a sequence of length `len`, raising IndexError
+/// if it is out of range. `allow_end` admits `len` itself, the position after the last element.
+fn resolve_index(index: isize, len: usize, allow_end: bool) -> PyResult<usize> {
+    let resolved = if index < 0 {
+        len.checked_sub(index.unsigned_abs())
+    } else {
+        Some(index as usize)
+    };
+    resolved
+        .filter(|i| *i < len || (allow_end && *i == len))
+        .ok_or_else(|| PyIndexError::new_err(format!("index {} out of range", index)))
+}
+
+#[derive(Debug, Clone)]
 pub struct PyObjId(am::ObjId);
 
 impl<'a> FromPyObject<'a> for PyObjId {
     fn extract(prop: &'a PyAny) -> PyResult<Self> {
+        if let Ok(obj_id) = prop.downcast::<PyCell<PyObjIdClass>>() {
+            return Ok(PyObjId(obj_id.get().0.clone()));
+        }
         prop.extract::<&[u8]>()
             .and_then(|b| am::ObjId::try_from(b).map_err(|e| PyException::new_err(e.to_string())))
             .map(PyObjId)
@@ -816,12 +6357,210 @@ impl<'a> FromPyObject<'a> for PyObjId {
 
 impl IntoPy<PyObject> for PyObjId {
     fn into_py(self, py: Python<'_>) -> PyObject {
-        let bytes: &[u8] = &self.0.to_bytes();
-        bytes.into_py(py)
+        PyObjIdClass(self.0).into_py(py)
+    }
+}
+
+/// The ID of an object in a document, exposed to Python as `ObjId`. Methods take and return IDs as
+/// `PyObjId`, which converts to this class and also accepts its bytes form, as returned by
+/// `bytes(obj_id)`.
+#[pyclass(name = "ObjId", frozen)]
+#[derive(Debug)]
+pub struct PyObjIdClass(am::ObjId);
+
+/// The actor index given to an `am::ObjId::Id` built without a document to look the actor up in.
+/// The index is only a hint: automerge checks it against its actor table and falls back to
+/// searching by actor ID when it doesn't match.
+const UNKNOWN_ACTOR_INDEX: usize = 0;
+
+impl PyObjIdClass {
+    /// What identifies the object: the counter and actor of the operation which created it, or
+    /// None for the root. The actor index hint is left out, since IDs parsed from strings or
+    /// decoded from changes carry `UNKNOWN_ACTOR_INDEX` rather than a real one.
+    fn identity(&self) -> Option<(u64, &ActorId)> {
+        match &self.0 {
+            am::ObjId::Root => None,
+            am::ObjId::Id(counter, actor, _) => Some((*counter, actor)),
+        }
+    }
+}
+
+#[pymethods]
+impl PyObjIdClass {
+    #[new]
+    fn new(bytes: &[u8]) -> PyResult<Self> {
+        am::ObjId::try_from(bytes)
+            .map(PyObjIdClass)
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    /// Parse the string form of an object ID, "_root" or "<counter>@<actor hex>".
+    #[staticmethod]
+    fn from_str(s: &str) -> PyResult<Self> {
+        if s == "_root" {
+            return Ok(PyObjIdClass(am::ROOT));
+        }
+        let invalid = || PyException::new_err(format!("invalid object ID: {:?}", s));
+        let (counter, actor) = s.split_once('@').ok_or_else(invalid)?;
+        let counter = counter.parse::<u64>().map_err(|_| invalid())?;
+        let actor = hex::decode(actor).map_err(|_| invalid())?;
+        Ok(PyObjIdClass(am::ObjId::Id(
+            counter,
+            ActorId::from(actor),
+            UNKNOWN_ACTOR_INDEX,
+        )))
+    }
+
+    /// The counter of the operation which created the object, or None for the root.
+    #[getter]
+    fn counter(&self) -> Option<u64> {
+        match &self.0 {
+            am::ObjId::Root => None,
+            am::ObjId::Id(counter, _, _) => Some(*counter),
+        }
+    }
+
+    /// The actor which created the object, or None for the root.
+    #[getter]
+    fn actor(&self) -> Option<&[u8]> {
+        match &self.0 {
+            am::ObjId::Root => None,
+            am::ObjId::Id(_, actor, _) => Some(actor.to_bytes()),
+        }
+    }
+
+    #[getter]
+    fn is_root(&self) -> bool {
+        self.0 == am::ROOT
+    }
+
+    fn __bytes__<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.0.to_bytes())
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ObjId('{}')", self.0)
+    }
+
+    fn __richcmp__(&self, other: &PyAny, op: CompareOp, py: Python<'_>) -> PyObject {
+        // Bytes don't compare equal: they also hold a hint at the actor's index in the document,
+        // so one ID has several bytes forms and no hash could agree with all of them.
+        let Ok(other) = other.downcast::<PyCell<PyObjIdClass>>() else {
+            return py.NotImplemented();
+        };
+        let other = other.get();
+        match op {
+            CompareOp::Eq => (self.identity() == other.identity()).into_py(py),
+            CompareOp::Ne => (self.identity() != other.identity()).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+
+    fn __hash__(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.identity().hash(&mut hasher);
+        hasher.finish()
     }
 }
 
+/// The value of a counter, as read from a document. Counters compare equal to ints with the same
+/// value and can be passed back to `put` to write a counter.
+#[pyclass(name = "Counter", frozen)]
 #[derive(Debug)]
+pub struct PyCounter {
+    #[pyo3(get)]
+    value: i64,
+}
+
+#[pymethods]
+impl PyCounter {
+    #[new]
+    #[pyo3(signature = (value=0))]
+    fn new(value: i64) -> Self {
+        PyCounter { value }
+    }
+
+    fn __int__(&self) -> i64 {
+        self.value
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Counter({})", self.value)
+    }
+
+    fn __richcmp__(&self, other: &PyAny, op: CompareOp, py: Python<'_>) -> PyObject {
+        let other = if let Ok(c) = other.extract::<PyRef<PyCounter>>() {
+            c.value
+        } else if other.downcast::<PyBool>().is_err() && other.downcast::<PyLong>().is_ok() {
+            match other.extract::<i64>() {
+                Ok(i) => i,
+                Err(_) => return matches!(op, CompareOp::Ne).into_py(py),
+            }
+        } else {
+            return py.NotImplemented();
+        };
+        match op {
+            CompareOp::Eq => (self.value == other).into_py(py),
+            CompareOp::Ne => (self.value != other).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+
+    fn __hash__(&self, py: Python<'_>) -> PyResult<isize> {
+        // Hash like the equivalent int so that counters and ints can share dict keys.
+        self.value.into_py(py).as_ref(py).hash()
+    }
+}
+
+/// Bytes read from a document with `get_bytes(..., zero_copy=True)`, exposed to Python through
+/// the buffer protocol.
+#[pyclass(name = "BytesBuffer")]
+struct PyBytesBuffer(Arc<[u8]>);
+
+impl PyBytesBuffer {
+    fn memoryview(self, py: Python<'_>) -> PyResult<PyObject> {
+        let buffer = Py::new(py, self)?;
+        // SAFETY: PyMemoryView_FromObject returns a new reference or null with an exception set.
+        unsafe {
+            PyObject::from_owned_ptr_or_err(py, ffi::PyMemoryView_FromObject(buffer.as_ptr()))
+        }
+    }
+}
+
+#[pymethods]
+impl PyBytesBuffer {
+    unsafe fn __getbuffer__(
+        slf: &PyCell<Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        // SAFETY: the data lives as long as the buffer object, which the view holds a reference
+        // to, and is never written to.
+        let data = Arc::clone(&slf.borrow().0);
+        let res = ffi::PyBuffer_FillInfo(
+            view,
+            slf.as_ptr(),
+            data.as_ptr() as *mut c_void,
+            data.len() as ffi::Py_ssize_t,
+            1,
+            flags,
+        );
+        if res == -1 {
+            return Err(PyErr::fetch(slf.py()));
+        }
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PyChangeHash(am::ChangeHash);
 
 impl<'a> FromPyObject<'a> for PyChangeHash {
@@ -840,6 +6579,24 @@ impl IntoPy<PyObject> for PyChangeHash {
     }
 }
 
+#[derive(Debug)]
+pub struct PyCursor(am::Cursor);
+
+impl<'a> FromPyObject<'a> for PyCursor {
+    fn extract(v: &'a PyAny) -> PyResult<Self> {
+        v.extract::<&[u8]>()
+            .and_then(|b| am::Cursor::try_from(b).map_err(|e| PyException::new_err(e.to_string())))
+            .map(PyCursor)
+    }
+}
+
+impl IntoPy<PyObject> for PyCursor {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        let bytes: &[u8] = &self.0.to_bytes();
+        bytes.into_py(py)
+    }
+}
+
 #[derive(Debug)]
 #[pyclass(name = "ObjType")]
 pub enum PyObjType {
@@ -859,9 +6616,9 @@ impl PyObjType {
     }
 }
 
-impl Into<ObjType> for &PyObjType {
-    fn into(self) -> ObjType {
-        match self {
+impl From<&PyObjType> for ObjType {
+    fn from(objtype: &PyObjType) -> Self {
+        match objtype {
             PyObjType::Map => ObjType::Map,
             PyObjType::List => ObjType::List,
             PyObjType::Text => ObjType::Text,
@@ -888,48 +6645,148 @@ pub enum PyScalarType {
 pub struct PyScalarValue(am::ScalarValue);
 impl IntoPy<PyObject> for PyScalarValue {
     fn into_py(self, py: Python<'_>) -> PyObject {
-        match self.0 {
-            ScalarValue::Bytes(v) => (PyScalarType::Bytes, v.into_py(py)),
-            ScalarValue::Str(v) => (PyScalarType::Str, v.into_py(py)),
-            ScalarValue::Int(v) => (PyScalarType::Int, v.into_py(py)),
-            ScalarValue::Uint(v) => (PyScalarType::Uint, v.into_py(py)),
-            ScalarValue::F64(v) => (PyScalarType::F64, v.into_py(py)),
-            ScalarValue::Counter(v) => todo!(),
-            ScalarValue::Timestamp(v) => (
-                PyScalarType::Timestamp,
-                PyDateTime::from_timestamp(py, (v as f64) / 1000.0, None)
-                    .unwrap()
-                    .into_py(py),
-            ),
-            ScalarValue::Boolean(v) => (PyScalarType::Boolean, v.into_py(py)),
-            ScalarValue::Unknown { type_code, bytes } => todo!(),
-            ScalarValue::Null => (PyScalarType::Null, Python::None(py)),
-        }
-        .into_py(py)
+        export_tagged_scalar(py, self.0, PyTimestampMode::Naive).into_py(py)
+    }
+}
+
+/// Convert `value` to Python, failing for timestamps which don't fit in a `datetime`.
+fn export_scalar(
+    py: Python<'_>,
+    value: ScalarValue,
+    timestamps: PyTimestampMode,
+) -> PyResult<(PyScalarType, PyObject)> {
+    Ok(match value {
+        ScalarValue::Bytes(v) => (PyScalarType::Bytes, v.into_py(py)),
+        ScalarValue::Str(v) => (PyScalarType::Str, v.into_py(py)),
+        ScalarValue::Int(v) => (PyScalarType::Int, v.into_py(py)),
+        ScalarValue::Uint(v) => (PyScalarType::Uint, v.into_py(py)),
+        ScalarValue::F64(v) => (PyScalarType::F64, v.into_py(py)),
+        ScalarValue::Counter(v) => (
+            PyScalarType::Counter,
+            PyCounter {
+                value: i64::from(v),
+            }
+            .into_py(py),
+        ),
+        ScalarValue::Timestamp(v) => (
+            PyScalarType::Timestamp,
+            match timestamps {
+                PyTimestampMode::Naive => {
+                    PyDateTime::from_timestamp(py, (v as f64) / 1000.0, None)?.into_py(py)
+                }
+                PyTimestampMode::Utc => {
+                    PyDateTime::from_timestamp(py, (v as f64) / 1000.0, Some(timezone_utc(py)))?
+                        .into_py(py)
+                }
+                PyTimestampMode::Millis => v.into_py(py),
+            },
+        ),
+        ScalarValue::Boolean(v) => (PyScalarType::Boolean, v.into_py(py)),
+        ScalarValue::Unknown { type_code, bytes } => (
+            PyScalarType::Unknown,
+            (type_code, PyBytes::new(py, &bytes)).into_py(py),
+        ),
+        ScalarValue::Null => (PyScalarType::Null, Python::None(py)),
+    })
+}
+
+/// Like `export_scalar`, for the `(ScalarType, value)` pairs which can't raise. A timestamp
+/// outside the range of `datetime` comes back as its milliseconds, which the `Timestamp` tag
+/// still identifies and which `put` accepts back.
+fn export_tagged_scalar(
+    py: Python<'_>,
+    value: ScalarValue,
+    timestamps: PyTimestampMode,
+) -> (PyScalarType, PyObject) {
+    match value {
+        ScalarValue::Timestamp(v) => export_scalar(py, value, timestamps)
+            .unwrap_or_else(|_| (PyScalarType::Timestamp, v.into_py(py))),
+        value => export_scalar(py, value, timestamps).expect("only timestamps can fail to convert"),
     }
 }
 
 impl<'a> FromPyObject<'a> for PyScalarValue {
     fn extract(v: &'a PyAny) -> PyResult<Self> {
         v.extract::<(PyScalarType, &PyAny)>()
-            .and_then(|(t, v)| import_scalar(v, &t).map(|v| PyScalarValue(v)))
+            .and_then(|(t, v)| import_scalar(v, &t).map(PyScalarValue))
     }
 }
 
-#[derive(Debug)]
-pub struct PyValue<'a>(am::Value<'a>);
+/// The name of the type of `value`, as used in TypeMismatchError messages.
+fn value_type_name(value: &am::Value<'_>) -> &'static str {
+    match value {
+        am::Value::Object(ObjType::Map) => "map",
+        am::Value::Object(ObjType::Table) => "table",
+        am::Value::Object(ObjType::List) => "list",
+        am::Value::Object(ObjType::Text) => "text",
+        am::Value::Scalar(s) => match s.as_ref() {
+            ScalarValue::Bytes(_) => "bytes",
+            ScalarValue::Str(_) => "str",
+            ScalarValue::Int(_) => "int",
+            ScalarValue::Uint(_) => "uint",
+            ScalarValue::F64(_) => "f64",
+            ScalarValue::Counter(_) => "counter",
+            ScalarValue::Timestamp(_) => "timestamp",
+            ScalarValue::Boolean(_) => "boolean",
+            ScalarValue::Unknown { .. } => "unknown",
+            ScalarValue::Null => "null",
+        },
+    }
+}
+
+// Conversions used by the typed getters, which return None for a value of any other type.
+
+fn expect_str(value: &am::Value<'_>, _: &am::ObjId) -> Option<String> {
+    match value.to_scalar()? {
+        ScalarValue::Str(s) => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+fn expect_int(value: &am::Value<'_>, _: &am::ObjId) -> Option<i128> {
+    match value.to_scalar()? {
+        ScalarValue::Int(i) => Some(i128::from(*i)),
+        ScalarValue::Uint(u) => Some(i128::from(*u)),
+        _ => None,
+    }
+}
+
+fn expect_bool(value: &am::Value<'_>, _: &am::ObjId) -> Option<bool> {
+    match value.to_scalar()? {
+        ScalarValue::Boolean(b) => Some(*b),
+        _ => None,
+    }
+}
+
+fn expect_bytes(value: &am::Value<'_>, _: &am::ObjId) -> Option<Vec<u8>> {
+    match value.to_scalar()? {
+        ScalarValue::Bytes(b) => Some(b.clone()),
+        _ => None,
+    }
+}
+
+fn expect_map(value: &am::Value<'_>, id: &am::ObjId) -> Option<PyObjId> {
+    matches!(value, am::Value::Object(ObjType::Map | ObjType::Table)).then(|| PyObjId(id.clone()))
+}
+
+fn expect_list(value: &am::Value<'_>, id: &am::ObjId) -> Option<PyObjId> {
+    matches!(value, am::Value::Object(ObjType::List)).then(|| PyObjId(id.clone()))
+}
+
+#[derive(Debug, Clone)]
+pub struct PyValue<'a>(am::Value<'a>, PyTimestampMode);
 
 impl<'a> IntoPy<PyObject> for PyValue<'a> {
     fn into_py(self, py: Python<'_>) -> PyObject {
         match self.0 {
             am::Value::Object(objtype) => PyObjType::from_objtype(objtype).into_py(py),
-            am::Value::Scalar(s) => PyScalarValue(s.as_ref().clone()).into_py(py),
+            am::Value::Scalar(s) => export_tagged_scalar(py, s.into_owned(), self.1).into_py(py),
         }
     }
 }
 
 #[pyclass(name = "Mark", get_all, set_all)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct PyMark {
     start: usize,
     end: usize,
@@ -937,10 +6794,21 @@ struct PyMark {
     value: PyScalarValue,
 }
 
+impl From<&Mark> for PyMark {
+    fn from(mark: &Mark) -> Self {
+        PyMark {
+            start: mark.start,
+            end: mark.end,
+            name: mark.name().to_owned(),
+            value: PyScalarValue(mark.value().clone()),
+        }
+    }
+}
+
 #[pymethods]
 impl PyMark {
-    fn __repr__(&self) -> String {
-        format!("{:?}", self)
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Mark", &["start", "end", "name", "value"])
     }
 }
 
@@ -953,9 +6821,9 @@ enum PyExpandMark {
     Neither,
 }
 
-impl Into<ExpandMark> for &PyExpandMark {
-    fn into(self) -> ExpandMark {
-        match self {
+impl From<&PyExpandMark> for ExpandMark {
+    fn from(expand: &PyExpandMark) -> Self {
+        match expand {
             PyExpandMark::Before => ExpandMark::Before,
             PyExpandMark::After => ExpandMark::After,
             PyExpandMark::Both => ExpandMark::Both,
@@ -964,12 +6832,104 @@ impl Into<ExpandMark> for &PyExpandMark {
     }
 }
 
+#[pyclass(name = "TextEncoding")]
+#[derive(Clone, Copy)]
+enum PyTextEncoding {
+    UnicodeCodePoint,
+    Utf8CodeUnit,
+    Utf16CodeUnit,
+    GraphemeCluster,
+}
+
+impl From<PyTextEncoding> for TextEncoding {
+    fn from(encoding: PyTextEncoding) -> Self {
+        match encoding {
+            PyTextEncoding::UnicodeCodePoint => TextEncoding::UnicodeCodePoint,
+            PyTextEncoding::Utf8CodeUnit => TextEncoding::Utf8CodeUnit,
+            PyTextEncoding::Utf16CodeUnit => TextEncoding::Utf16CodeUnit,
+            PyTextEncoding::GraphemeCluster => TextEncoding::GraphemeCluster,
+        }
+    }
+}
+
+impl From<TextEncoding> for PyTextEncoding {
+    fn from(encoding: TextEncoding) -> Self {
+        match encoding {
+            TextEncoding::UnicodeCodePoint => PyTextEncoding::UnicodeCodePoint,
+            TextEncoding::Utf8CodeUnit => PyTextEncoding::Utf8CodeUnit,
+            TextEncoding::Utf16CodeUnit => PyTextEncoding::Utf16CodeUnit,
+            TextEncoding::GraphemeCluster => PyTextEncoding::GraphemeCluster,
+        }
+    }
+}
+
+/// How timestamps read from a document are returned: as naive datetimes in local time, as
+/// timezone-aware datetimes in UTC, or as integer milliseconds since the epoch.
+#[pyclass(name = "TimestampMode")]
+#[derive(Debug, Clone, Copy)]
+enum PyTimestampMode {
+    Naive,
+    Utc,
+    Millis,
+}
+
+/// An expand argument, given either as an `ExpandMark` or as one of the strings "before",
+/// "after", "both" or "none".
+pub struct PyExpand(ExpandMark);
+
+impl<'a> FromPyObject<'a> for PyExpand {
+    fn extract(value: &'a PyAny) -> PyResult<Self> {
+        if let Ok(expand) = value.extract::<PyRef<PyExpandMark>>() {
+            return Ok(PyExpand((&*expand).into()));
+        }
+        Ok(PyExpand(match value.extract::<&str>()? {
+            "before" => ExpandMark::Before,
+            "after" => ExpandMark::After,
+            "both" => ExpandMark::Both,
+            "none" => ExpandMark::None,
+            other => {
+                return Err(PyException::new_err(format!(
+                "invalid expand: {:?}, expected one of \"before\", \"after\", \"both\" or \"none\"",
+                other
+            )))
+            }
+        }))
+    }
+}
+
+fn import_change(value: &PyAny) -> PyResult<am::Change> {
+    if let Ok(change) = value.extract::<PyRef<PyChange>>() {
+        Ok(change.0.clone())
+    } else {
+        let bytes = value.extract::<&[u8]>()?;
+        am::Change::from_bytes(bytes.to_vec()).map_err(|e| PyException::new_err(e.to_string()))
+    }
+}
+
 #[pyclass(name = "Change")]
 #[derive(Debug)]
 struct PyChange(am::Change);
 
 #[pymethods]
 impl PyChange {
+    /// Parse a single change from its encoding, as returned by `bytes` or `raw_bytes`.
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        am::Change::from_bytes(data.to_vec())
+            .map(PyChange)
+            .map_err(|e| PyException::new_err(e.to_string()))
+    }
+
+    /// Whether the checksum in the change's header matches its contents and, if `hash` is given,
+    /// whether the change has that hash.
+    #[pyo3(signature = (hash = None))]
+    fn verify(&self, hash: Option<PyChangeHash>) -> bool {
+        let actual = self.0.hash();
+        // A chunk starts with 4 magic bytes and then the first 4 bytes of its hash.
+        let checksum = self.0.raw_bytes().get(4..8);
+        checksum == Some(&actual.as_ref()[..4]) && hash.is_none_or(|hash| hash.0 == actual)
+    }
+
     fn __repr__(&self) -> String {
         format!("{:?}", self.0)
     }
@@ -1041,15 +7001,487 @@ impl PyChange {
     fn extra_bytes(&self) -> &[u8] {
         self.0.extra_bytes()
     }
+
+    /// The operations in this change, decoded.
+    fn ops(&self) -> Vec<PyChangeOp> {
+        let change = self.0.decode();
+        let start_op = u64::from(change.start_op);
+        change
+            .operations
+            .into_iter()
+            .enumerate()
+            .map(|(i, op)| {
+                let id = am::legacy::OpId(start_op + i as u64, change.actor_id.clone());
+                PyChangeOp::new(id, op)
+            })
+            .collect()
+    }
+}
+
+/// One operation of a change, as decoded by `Change.ops`. Operation and element IDs are given in
+/// their string form, "<counter>@<actor hex>", with "_head" for the start of a sequence.
+#[pyclass(name = "ChangeOp", get_all)]
+#[derive(Debug, Clone)]
+struct PyChangeOp {
+    id: String,
+    /// One of "make_map", "make_list", "make_text", "make_table", "put", "delete", "increment",
+    /// "mark_begin" or "mark_end".
+    action: &'static str,
+    obj: PyObjId,
+    /// The key in a map, or None for a sequence.
+    key: Option<String>,
+    /// The element in a sequence which this operation sets, deletes, or inserts after.
+    elem: Option<String>,
+    insert: bool,
+    /// The value put, the amount of an increment or the value of a mark.
+    value: Option<PyScalarValue>,
+    mark_name: Option<String>,
+    expand: bool,
+    /// The operations which this one overwrites.
+    pred: Vec<String>,
+}
+
+impl PyChangeOp {
+    fn new(id: am::legacy::OpId, op: am::legacy::Op) -> Self {
+        use am::legacy::{ElementId, Key, MarkData, ObjectId, OpType};
+        let obj = match op.obj {
+            ObjectId::Root => am::ROOT,
+            ObjectId::Id(id) => {
+                am::ObjId::Id(id.counter(), id.actor().clone(), UNKNOWN_ACTOR_INDEX)
+            }
+        };
+        let (key, elem) = match op.key {
+            Key::Map(key) => (Some(key.to_string()), None),
+            Key::Seq(ElementId::Head) => (None, Some("_head".to_owned())),
+            Key::Seq(ElementId::Id(id)) => (None, Some(id.to_string())),
+        };
+        let expand = matches!(
+            op.action,
+            OpType::MarkBegin(MarkData { expand: true, .. }) | OpType::MarkEnd(true)
+        );
+        let (action, value, mark_name) = match op.action {
+            OpType::Make(ObjType::Map) => ("make_map", None, None),
+            OpType::Make(ObjType::List) => ("make_list", None, None),
+            OpType::Make(ObjType::Text) => ("make_text", None, None),
+            OpType::Make(ObjType::Table) => ("make_table", None, None),
+            OpType::Put(value) => ("put", Some(value), None),
+            OpType::Delete => ("delete", None, None),
+            OpType::Increment(by) => ("increment", Some(ScalarValue::Int(by)), None),
+            OpType::MarkBegin(mark) => {
+                ("mark_begin", Some(mark.value), Some(mark.name.to_string()))
+            }
+            OpType::MarkEnd(_) => ("mark_end", None, None),
+        };
+        PyChangeOp {
+            id: id.to_string(),
+            action,
+            obj: PyObjId(obj),
+            key,
+            elem,
+            insert: op.insert,
+            value: value.map(PyScalarValue),
+            mark_name,
+            expand,
+            pred: op.pred.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyChangeOp {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(
+            slf,
+            "ChangeOp",
+            &[
+                "id",
+                "action",
+                "obj",
+                "key",
+                "elem",
+                "insert",
+                "value",
+                "mark_name",
+                "expand",
+                "pred",
+            ],
+        )
+    }
+}
+
+/// A repr in the style of a Python dataclass, `Name(field=value, ...)`, built from the reprs of
+/// the given attributes of `obj`.
+fn fields_repr(obj: &PyAny, name: &str, fields: &[&str]) -> PyResult<String> {
+    let fields = fields
+        .iter()
+        .map(|field| Ok(format!("{}={}", field, obj.getattr(*field)?.repr()?)))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(format!("{}({})", name, fields.join(", ")))
 }
 
 #[pyclass(name = "Patch")]
-#[derive(Debug)]
-struct PyPatch(am::Patch);
+#[derive(Debug, Clone)]
+struct PyPatch(am::Patch, PyTimestampMode);
 
 #[pymethods]
 impl PyPatch {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Patch", &["obj", "path", "action"])
+    }
+
+    #[getter]
+    fn obj(&self) -> PyObjId {
+        PyObjId(self.0.obj.clone())
+    }
+
+    #[getter]
+    fn path(&self) -> Vec<(PyObjId, PyProp)> {
+        self.0
+            .path
+            .iter()
+            .map(|(obj, prop)| (PyObjId(obj.clone()), PyProp(prop.clone())))
+            .collect()
+    }
+
+    #[getter]
+    fn action(&self, py: Python<'_>) -> PyObject {
+        match &self.0.action {
+            am::PatchAction::PutMap {
+                key,
+                value,
+                conflict,
+            } => PyPutMap {
+                key: key.clone(),
+                value: (PyValue(value.0.clone(), self.1), PyObjId(value.1.clone())),
+                conflict: *conflict,
+            }
+            .into_py(py),
+            am::PatchAction::PutSeq {
+                index,
+                value,
+                conflict,
+            } => PyPutSeq {
+                index: *index,
+                value: (PyValue(value.0.clone(), self.1), PyObjId(value.1.clone())),
+                conflict: *conflict,
+            }
+            .into_py(py),
+            am::PatchAction::Insert { index, values } => PyInsert {
+                index: *index,
+                values: values
+                    .iter()
+                    .map(|(v, id, _)| (PyValue(v.clone(), self.1), PyObjId(id.clone())))
+                    .collect(),
+            }
+            .into_py(py),
+            am::PatchAction::SpliceText {
+                index,
+                value,
+                marks,
+            } => PySpliceText {
+                index: *index,
+                value: value.make_string(),
+                marks: marks.as_ref().map(mark_set),
+            }
+            .into_py(py),
+            am::PatchAction::Increment { prop, value } => PyIncrement {
+                prop: PyProp(prop.clone()),
+                value: *value,
+            }
+            .into_py(py),
+            am::PatchAction::Conflict { prop } => PyConflict {
+                prop: PyProp(prop.clone()),
+            }
+            .into_py(py),
+            am::PatchAction::DeleteMap { key } => PyDeleteMap { key: key.clone() }.into_py(py),
+            am::PatchAction::DeleteSeq { index, length } => PyDeleteSeq {
+                index: *index,
+                length: *length,
+            }
+            .into_py(py),
+            am::PatchAction::Mark { marks } => PyMarks {
+                marks: marks.iter().map(PyMark::from).collect(),
+            }
+            .into_py(py),
+        }
+    }
+}
+
+fn mark_set(marks: &am::marks::MarkSet) -> HashMap<String, PyScalarValue> {
+    marks
+        .iter()
+        .map(|(name, value)| (name.to_owned(), PyScalarValue(value.clone())))
+        .collect()
+}
+
+pub struct PySpan(am::Span);
+
+impl IntoPy<PyObject> for PySpan {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        match self.0 {
+            am::Span::Text { text, marks } => PyTextSpan {
+                text,
+                marks: marks.map(|m| mark_set(&m)).unwrap_or_default(),
+            }
+            .into_py(py),
+            am::Span::Block(value) => PyBlockSpan(value).into_py(py),
+        }
+    }
+}
+
+impl<'a> FromPyObject<'a> for PySpan {
+    fn extract(v: &'a PyAny) -> PyResult<Self> {
+        if let Ok(span) = v.extract::<PyRef<PyTextSpan>>() {
+            let marks: MarkSet = span
+                .marks
+                .iter()
+                .map(|(name, value)| (name.clone(), value.0.clone()))
+                .collect();
+            Ok(PySpan(am::Span::Text {
+                text: span.text.clone(),
+                marks: (!marks.is_empty()).then(|| Arc::new(marks)),
+            }))
+        } else {
+            let span = v.extract::<PyRef<PyBlockSpan>>()?;
+            Ok(PySpan(am::Span::Block(span.0.clone())))
+        }
+    }
+}
+
+/// A run of text in a text object and the marks active over it.
+#[pyclass(name = "TextSpan", get_all)]
+#[derive(Debug, Clone)]
+struct PyTextSpan {
+    text: String,
+    marks: HashMap<String, PyScalarValue>,
+}
+
+#[pymethods]
+impl PyTextSpan {
+    #[new]
+    fn new(text: String, marks: Option<HashMap<String, PyScalarValue>>) -> Self {
+        PyTextSpan {
+            text,
+            marks: marks.unwrap_or_default(),
+        }
+    }
+
     fn __repr__(&self) -> String {
-        format!("{:?}", self.0)
+        format!("{:?}", self)
+    }
+}
+
+/// A block marker in a text object. `value` holds the attributes of the block as plain Python
+/// values.
+#[pyclass(name = "BlockSpan")]
+#[derive(Debug, Clone)]
+struct PyBlockSpan(am::hydrate::Map);
+
+#[pymethods]
+impl PyBlockSpan {
+    #[new]
+    fn new(value: &PyDict) -> PyResult<Self> {
+        Ok(PyBlockSpan(import_hydrated_map(value)?))
+    }
+
+    #[getter]
+    fn value<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        export_hydrated_map(py, &self.0)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// A key was created or updated in a map.
+#[pyclass(name = "PutMap", get_all)]
+#[derive(Debug, Clone)]
+struct PyPutMap {
+    key: String,
+    value: (PyValue<'static>, PyObjId),
+    conflict: bool,
+}
+
+/// An index in a sequence was updated.
+#[pyclass(name = "PutSeq", get_all)]
+#[derive(Debug, Clone)]
+struct PyPutSeq {
+    index: usize,
+    value: (PyValue<'static>, PyObjId),
+    conflict: bool,
+}
+
+/// One or more elements were inserted into a sequence.
+#[pyclass(name = "Insert", get_all)]
+#[derive(Debug, Clone)]
+struct PyInsert {
+    index: usize,
+    values: Vec<(PyValue<'static>, PyObjId)>,
+}
+
+/// Some text was spliced into a text object.
+#[pyclass(name = "SpliceText", get_all)]
+#[derive(Debug, Clone)]
+struct PySpliceText {
+    index: usize,
+    value: String,
+    marks: Option<HashMap<String, PyScalarValue>>,
+}
+
+/// A counter was incremented.
+#[pyclass(name = "Increment", get_all)]
+#[derive(Debug, Clone)]
+struct PyIncrement {
+    prop: PyProp,
+    value: i64,
+}
+
+/// A new conflict has appeared.
+#[pyclass(name = "Conflict", get_all)]
+#[derive(Debug, Clone)]
+struct PyConflict {
+    prop: PyProp,
+}
+
+/// A key was deleted from a map.
+#[pyclass(name = "DeleteMap", get_all)]
+#[derive(Debug, Clone)]
+struct PyDeleteMap {
+    key: String,
+}
+
+/// One or more indices were removed from a sequence.
+#[pyclass(name = "DeleteSeq", get_all)]
+#[derive(Debug, Clone)]
+struct PyDeleteSeq {
+    index: usize,
+    length: usize,
+}
+
+/// What one change set passed to `Document.attribute` did to a text.
+#[pyclass(name = "Attribution", get_all)]
+#[derive(Debug, Clone, Default)]
+struct PyAttribution {
+    /// `(start, end)` of each span of text the change set added.
+    added: Vec<(usize, usize)>,
+    /// `(position, text)` of each span of text the change set deleted.
+    deleted: Vec<(usize, String)>,
+}
+
+#[pymethods]
+impl PyAttribution {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Attribution", &["added", "deleted"])
+    }
+}
+
+#[pyclass(name = "Stats", get_all)]
+#[derive(Debug, Clone)]
+struct PyStats {
+    num_changes: usize,
+    num_ops: usize,
+    num_objects: usize,
+    num_actors: usize,
+    save_size: Option<usize>,
+}
+
+#[pymethods]
+impl PyStats {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(
+            slf,
+            "Stats",
+            &[
+                "num_changes",
+                "num_ops",
+                "num_objects",
+                "num_actors",
+                "save_size",
+            ],
+        )
+    }
+}
+
+/// Some marks within a text object were added or removed.
+#[pyclass(name = "Marks", get_all)]
+#[derive(Debug, Clone)]
+struct PyMarks {
+    marks: Vec<PyMark>,
+}
+
+#[pymethods]
+impl PyPutMap {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "PutMap", &["key", "value", "conflict"])
+    }
+}
+
+#[pymethods]
+impl PyPutSeq {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "PutSeq", &["index", "value", "conflict"])
+    }
+}
+
+#[pymethods]
+impl PyInsert {
+    /// Deprecated: automerge no longer reports marks on inserted elements, so this is always
+    /// None. Marks on text arrive with `SpliceText` patches instead.
+    #[getter]
+    fn marks(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        PyErr::warn(
+            py,
+            py.get_type::<PyDeprecationWarning>(),
+            "Insert.marks is always None and will be removed; read marks from SpliceText instead",
+            1,
+        )?;
+        Ok(None)
+    }
+
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Insert", &["index", "values"])
+    }
+}
+
+#[pymethods]
+impl PySpliceText {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "SpliceText", &["index", "value", "marks"])
+    }
+}
+
+#[pymethods]
+impl PyIncrement {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Increment", &["prop", "value"])
+    }
+}
+
+#[pymethods]
+impl PyConflict {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Conflict", &["prop"])
+    }
+}
+
+#[pymethods]
+impl PyDeleteMap {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "DeleteMap", &["key"])
+    }
+}
+
+#[pymethods]
+impl PyDeleteSeq {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "DeleteSeq", &["index", "length"])
+    }
+}
+
+#[pymethods]
+impl PyMarks {
+    fn __repr__(slf: &PyCell<Self>) -> PyResult<String> {
+        fields_repr(slf, "Marks", &["marks"])
     }
 }
@@ -1,58 +1,330 @@
-from typing import Optional, Type
+from typing import Callable, Generic, Iterable, Iterator, Literal, Mapping, Optional, Sequence, Type, TypeVar, overload
 from types import TracebackType
 from datetime import datetime
 from enum import Enum
 
-from automerge.core import ScalarValue, Value
+from automerge.core import ScalarValue, Thing, Value
+
+class ObjId:
+    def __init__(self, data: bytes) -> None: ...
+    @staticmethod
+    def from_str(s: str) -> ObjId: ...
+    counter: Optional[int]
+    actor: Optional[bytes]
+    is_root: bool
+    def __bytes__(self) -> bytes: ...
+    def __hash__(self) -> int: ...
+    def __eq__(self, other: object) -> bool: ...
+
+class Counter:
+    value: int
+    def __init__(self, value: int = 0) -> None: ...
+    def __int__(self) -> int: ...
+    def __hash__(self) -> int: ...
+    def __eq__(self, other: object) -> bool: ...
+
+# Object IDs can also be given in their bytes form.
+ObjIdLike = ObjId | bytes
+# A prop, or a tuple of props leading through nested objects.
+PropPath = str | int | tuple[str | int, ...]
 
 class Document:
-    def __init__(self, actor_id: Optional[bytes] = None) -> None: ...
+    def __init__(self, actor_id: Optional[bytes] = None, text_encoding: Optional[TextEncoding] = None, timestamps: Optional[TimestampMode] = None) -> None: ...
+    @property
+    def text_encoding(self) -> TextEncoding: ...
+    @property
+    def timestamps(self) -> TimestampMode: ...
+    @timestamps.setter
+    def timestamps(self, timestamps: TimestampMode) -> None: ...
     def get_actor(self) -> bytes: ...
     def set_actor(self, actor_id: bytes) -> None: ...
-    def transaction(self) -> Transaction: ...
+    def transaction(self, message: Optional[str] = None, timestamp: Optional[datetime] = None) -> Transaction: ...
+    def change(self, callback: Callable[[Transaction], object], message: Optional[str] = None) -> tuple[Optional[bytes], list[Patch]]: ...
+    def undo(self) -> Optional[bytes]: ...
+    def redo(self) -> Optional[bytes]: ...
+    def can_undo(self) -> bool: ...
+    def can_redo(self) -> bool: ...
+    @property
+    def undo_depth(self) -> int: ...
+    @undo_depth.setter
+    def undo_depth(self, depth: int) -> None: ...
     def save(self) -> bytes: ...
+    def save_incremental(self) -> bytes: ...
+    def load_incremental(self, data: bytes) -> None: ...
+    def stats(self, save_size: bool = False) -> Stats: ...
+    @staticmethod
+    def load(data: bytes, text_encoding: Optional[TextEncoding] = None, timestamps: Optional[TimestampMode] = None) -> Document: ...
     @staticmethod
-    def load(data: bytes) -> Document: ...
-    def fork(self, heads: Optional[list[bytes]] = None) -> Document: ...
-    def merge(self, other: Document) -> list[bytes]: ...
+    def from_json(data: str, actor_id: Optional[bytes] = None) -> Document: ...
+    @staticmethod
+    def from_py(obj: dict[str, NestedValue], actor_id: Optional[bytes] = None) -> Document: ...
+    def fork(self, heads: Optional[list[bytes]] = None, actor_id: Optional[bytes] = None) -> Document: ...
+    def fork_at(self, heads: list[bytes], actor_id: Optional[bytes] = None) -> Document: ...
+    @overload
+    def merge(self, other: Document, return_patches: Literal[False] = False) -> list[bytes]: ...
+    @overload
+    def merge(self, other: Document, return_patches: Literal[True]) -> tuple[list[bytes], list[Patch]]: ...
+    def apply_changes(self, changes: list[Change | bytes]) -> None: ...
+    def on_patch(self, callback: Optional[Callable[[list[Patch]], object]]) -> None: ...
     def diff(self, before_heads: list[bytes], after_heads: list[bytes]) -> list[Patch]: ...
+    def diff_incremental(self) -> list[Patch]: ...
+    def attribute(self, obj_id: ObjIdLike, baseline: list[bytes], change_sets: list[list[bytes]]) -> list[Attribution]: ...
     
-    def generate_sync_message(self, state: SyncState) -> Message: ...
-    def receive_sync_message(self, state: SyncState, msg: Message) -> None: ...
+    def generate_sync_message(self, state: SyncState, max_message_size: Optional[int] = None) -> Optional[Message]: ...
+    def sync_with(self, other: Document) -> tuple[int, int]: ...
+    @overload
+    def receive_sync_message(self, state: SyncState, message: Message, return_patches: Literal[False] = False) -> None: ...
+    @overload
+    def receive_sync_message(self, state: SyncState, message: Message, return_patches: Literal[True]) -> list[Patch]: ...
 
     def get_heads(self) -> list[bytes]: ...
     def get_last_local_change(self) -> Optional[Change]: ...
-    def object_type(self, obj_id: bytes) -> ObjType: ...
+    def object_type(self, obj_id: ObjIdLike) -> ObjType: ...
     def get_changes(self, have_deps: list[bytes]) -> list[Change]: ...
-    def get(self, obj_id: bytes, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, bytes]]: ...
-    def keys(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> list[str]: ...
-    def values(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> list[tuple[Value, bytes]]: ...
-    def length(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> int: ...
-    def text(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> str: ...
-    def marks(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> list[Mark]: ...
+    def get_change_by_hash(self, hash: bytes) -> Optional[Change]: ...
+    @overload
+    def history(self, patches: Literal[False] = False) -> list[tuple[Change, list[bytes]]]: ...
+    @overload
+    def history(self, patches: Literal[True]) -> list[tuple[Change, list[bytes], list[Patch]]]: ...
+    def get_changes_added(self, other: Document) -> list[Change]: ...
+    def fingerprint(self) -> bytes: ...
+    def get_missing_deps(self, heads: Optional[list[bytes]] = None) -> list[bytes]: ...
+    @overload
+    def get(self, obj_id: ObjIdLike, prop: PropPath, heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, ObjId]]: ...
+    @overload
+    def get(self, prop: PropPath, heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, ObjId]]: ...
+    @overload
+    def get_all(self, obj_id: ObjIdLike, prop: PropPath, heads: Optional[list[bytes]] = None) -> list[tuple[Value, ObjId]]: ...
+    @overload
+    def get_all(self, prop: PropPath, heads: Optional[list[bytes]] = None) -> list[tuple[Value, ObjId]]: ...
+    @overload
+    def get_str(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[str]: ...
+    @overload
+    def get_str(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[str]: ...
+    @overload
+    def get_int(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[int]: ...
+    @overload
+    def get_int(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[int]: ...
+    @overload
+    def get_bool(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[bool]: ...
+    @overload
+    def get_bool(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[bool]: ...
+    @overload
+    def get_bytes(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None, zero_copy: Literal[False] = False) -> Optional[bytes]: ...
+    @overload
+    def get_bytes(self, prop: str | int, heads: Optional[list[bytes]] = None, zero_copy: Literal[False] = False) -> Optional[bytes]: ...
+    @overload
+    def get_bytes(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None, *, zero_copy: Literal[True]) -> Optional[memoryview]: ...
+    @overload
+    def get_bytes(self, prop: str | int, heads: Optional[list[bytes]] = None, *, zero_copy: Literal[True]) -> Optional[memoryview]: ...
+    @overload
+    def get_map(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_map(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_list(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_list(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_or(self, obj_id: ObjIdLike, prop: str | int, default: _T, heads: Optional[list[bytes]] = None) -> ScalarValue | ObjId | _T: ...
+    @overload
+    def get_or(self, prop: str | int, default: _T, heads: Optional[list[bytes]] = None) -> ScalarValue | ObjId | _T: ...
+    @overload
+    def has(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> bool: ...
+    @overload
+    def has(self, prop: str | int, heads: Optional[list[bytes]] = None) -> bool: ...
+    def keys(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> Range[str]: ...
+    def parents(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[tuple[ObjId, str | int]]: ...
+    def map_range(self, obj_id: ObjIdLike = ROOT, start_key: Optional[str] = None, end_key: Optional[str] = None, heads: Optional[list[bytes]] = None) -> Range[tuple[str, Value, ObjId]]: ...
+    def list_range(self, obj_id: ObjIdLike, start: Optional[int] = None, end: Optional[int] = None, heads: Optional[list[bytes]] = None) -> Range[tuple[int, Value, ObjId]]: ...
+    def lookup(self, path: str | Sequence[str | int], heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, ObjId]]: ...
+    def query(self, expr: str, heads: Optional[list[bytes]] = None) -> list[tuple[list[str | int], Value, ObjId]]: ...
+    def path(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[str | int]: ...
+    def values(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> Range[tuple[Value, ObjId]]: ...
+    def values_range(self, obj_id: ObjIdLike, start: int, end: int, heads: Optional[list[bytes]] = None) -> Range[tuple[Value, ObjId]]: ...
+    def length(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> int: ...
+    def text(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> str: ...
+    def text_range(self, obj_id: ObjIdLike, start: int, end: int, heads: Optional[list[bytes]] = None) -> str: ...
+    def marks(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[Mark]: ...
+    def marks_at(self, obj_id: ObjIdLike, index: int, heads: Optional[list[bytes]] = None) -> dict[str, tuple[ScalarType, ScalarValue]]: ...
+    def get_cursor(self, obj_id: ObjIdLike, position: int, heads: Optional[list[bytes]] = None) -> bytes: ...
+    @overload
+    def find_text(self, obj_id: ObjIdLike, needle: str, heads: Optional[list[bytes]] = None, cursors: Literal[False] = False) -> list[int]: ...
+    @overload
+    def find_text(self, obj_id: ObjIdLike, needle: str, heads: Optional[list[bytes]] = None, *, cursors: Literal[True]) -> list[tuple[int, bytes]]: ...
+    def get_cursor_position(self, obj_id: ObjIdLike, cursor: bytes, heads: Optional[list[bytes]] = None) -> int: ...
+    def spans(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[Span]: ...
+    def view_at(self, heads: list[bytes]) -> DocumentView: ...
+    def to_py(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> Thing: ...
+    @property
+    def root(self) -> MapProxy: ...
+    def __getitem__(self, key: str) -> Item: ...
+    def __contains__(self, key: object) -> bool: ...
+    def __iter__(self) -> Range[str]: ...
+    def to_json(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None, indent: Optional[int] = None,
+                counters: Literal["number", "object"] = "number",
+                timestamps: Literal["millis", "iso"] = "millis",
+                bytes: Literal["hex", "array"] = "hex") -> str: ...
     
+class DocumentView:
+    @property
+    def heads(self) -> list[bytes]: ...
+    def object_type(self, obj_id: ObjIdLike) -> ObjType: ...
+    @overload
+    def get(self, obj_id: ObjIdLike, prop: PropPath) -> Optional[tuple[Value, ObjId]]: ...
+    @overload
+    def get(self, prop: PropPath) -> Optional[tuple[Value, ObjId]]: ...
+    @overload
+    def get_all(self, obj_id: ObjIdLike, prop: PropPath) -> list[tuple[Value, ObjId]]: ...
+    @overload
+    def get_all(self, prop: PropPath) -> list[tuple[Value, ObjId]]: ...
+    def keys(self, obj_id: ObjIdLike = ROOT) -> Range[str]: ...
+    def values(self, obj_id: ObjIdLike = ROOT) -> Range[tuple[Value, ObjId]]: ...
+    def values_range(self, obj_id: ObjIdLike, start: int, end: int) -> Range[tuple[Value, ObjId]]: ...
+    def map_range(self, obj_id: ObjIdLike = ROOT, start_key: Optional[str] = None, end_key: Optional[str] = None) -> Range[tuple[str, Value, ObjId]]: ...
+    def list_range(self, obj_id: ObjIdLike, start: Optional[int] = None, end: Optional[int] = None) -> Range[tuple[int, Value, ObjId]]: ...
+    def lookup(self, path: str | Sequence[str | int]) -> Optional[tuple[Value, ObjId]]: ...
+    def query(self, expr: str) -> list[tuple[list[str | int], Value, ObjId]]: ...
+    def parents(self, obj_id: ObjIdLike) -> list[tuple[ObjId, str | int]]: ...
+    def path(self, obj_id: ObjIdLike) -> list[str | int]: ...
+    def length(self, obj_id: ObjIdLike = ROOT) -> int: ...
+    def text(self, obj_id: ObjIdLike) -> str: ...
+    def text_range(self, obj_id: ObjIdLike, start: int, end: int) -> str: ...
+    def marks(self, obj_id: ObjIdLike) -> list[Mark]: ...
+    def marks_at(self, obj_id: ObjIdLike, index: int) -> dict[str, tuple[ScalarType, ScalarValue]]: ...
+    def spans(self, obj_id: ObjIdLike) -> list[Span]: ...
+    @property
+    def root(self) -> MapProxy: ...
+    def __getitem__(self, key: str) -> Item: ...
+    def __contains__(self, key: object) -> bool: ...
+    def __iter__(self) -> Range[str]: ...
+    def to_py(self, obj_id: ObjIdLike = ROOT) -> Thing: ...
+
 class Transaction:
     def __enter__(self) -> Transaction: ...
     def __exit__(self, exc_type: Optional[Type[BaseException]], exc: Optional[BaseException], traceback: Optional[TracebackType]) -> None: ...
+    def commit(self, message: Optional[str] = None, timestamp: Optional[datetime] = None) -> Optional[bytes]: ...
+    def rollback(self) -> None: ...
 
     def get_heads(self) -> list[bytes]: ...
-    def object_type(self, obj_id: bytes) -> ObjType: ...
+    def object_type(self, obj_id: ObjIdLike) -> ObjType: ...
     def get_changes(self, have_deps: list[bytes]) -> list[Change]: ...
-    def get(self, obj_id: bytes, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[tuple[ObjType | tuple[ScalarType, ScalarValue], bytes]]: ...
-    def keys(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> list[str]: ...
-    def values(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> list[tuple[Value, bytes]]: ...
-    def length(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> int: ...
-    def text(self, obj_id: bytes, heads: Optional[list[bytes]] = None) -> str: ...
+    @overload
+    def get(self, obj_id: ObjIdLike, prop: PropPath, heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, ObjId]]: ...
+    @overload
+    def get(self, prop: PropPath, heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, ObjId]]: ...
+    @overload
+    def get_all(self, obj_id: ObjIdLike, prop: PropPath, heads: Optional[list[bytes]] = None) -> list[tuple[Value, ObjId]]: ...
+    @overload
+    def get_all(self, prop: PropPath, heads: Optional[list[bytes]] = None) -> list[tuple[Value, ObjId]]: ...
+    @overload
+    def get_str(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[str]: ...
+    @overload
+    def get_str(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[str]: ...
+    @overload
+    def get_int(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[int]: ...
+    @overload
+    def get_int(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[int]: ...
+    @overload
+    def get_bool(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[bool]: ...
+    @overload
+    def get_bool(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[bool]: ...
+    @overload
+    def get_bytes(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None, zero_copy: Literal[False] = False) -> Optional[bytes]: ...
+    @overload
+    def get_bytes(self, prop: str | int, heads: Optional[list[bytes]] = None, zero_copy: Literal[False] = False) -> Optional[bytes]: ...
+    @overload
+    def get_bytes(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None, *, zero_copy: Literal[True]) -> Optional[memoryview]: ...
+    @overload
+    def get_bytes(self, prop: str | int, heads: Optional[list[bytes]] = None, *, zero_copy: Literal[True]) -> Optional[memoryview]: ...
+    @overload
+    def get_map(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_map(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_list(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_list(self, prop: str | int, heads: Optional[list[bytes]] = None) -> Optional[ObjId]: ...
+    @overload
+    def get_or(self, obj_id: ObjIdLike, prop: str | int, default: _T, heads: Optional[list[bytes]] = None) -> ScalarValue | ObjId | _T: ...
+    @overload
+    def get_or(self, prop: str | int, default: _T, heads: Optional[list[bytes]] = None) -> ScalarValue | ObjId | _T: ...
+    @overload
+    def has(self, obj_id: ObjIdLike, prop: str | int, heads: Optional[list[bytes]] = None) -> bool: ...
+    @overload
+    def has(self, prop: str | int, heads: Optional[list[bytes]] = None) -> bool: ...
+    def keys(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> Range[str]: ...
+    def parents(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[tuple[ObjId, str | int]]: ...
+    def map_range(self, obj_id: ObjIdLike = ROOT, start_key: Optional[str] = None, end_key: Optional[str] = None, heads: Optional[list[bytes]] = None) -> Range[tuple[str, Value, ObjId]]: ...
+    def list_range(self, obj_id: ObjIdLike, start: Optional[int] = None, end: Optional[int] = None, heads: Optional[list[bytes]] = None) -> Range[tuple[int, Value, ObjId]]: ...
+    def lookup(self, path: str | Sequence[str | int], heads: Optional[list[bytes]] = None) -> Optional[tuple[Value, ObjId]]: ...
+    def query(self, expr: str, heads: Optional[list[bytes]] = None) -> list[tuple[list[str | int], Value, ObjId]]: ...
+    def path(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[str | int]: ...
+    def values(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> Range[tuple[Value, ObjId]]: ...
+    def values_range(self, obj_id: ObjIdLike, start: int, end: int, heads: Optional[list[bytes]] = None) -> Range[tuple[Value, ObjId]]: ...
+    def length(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> int: ...
+    def text(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> str: ...
+    def text_range(self, obj_id: ObjIdLike, start: int, end: int, heads: Optional[list[bytes]] = None) -> str: ...
     # def marks
+    def marks_at(self, obj_id: ObjIdLike, index: int, heads: Optional[list[bytes]] = None) -> dict[str, tuple[ScalarType, ScalarValue]]: ...
+    def get_cursor(self, obj_id: ObjIdLike, position: int, heads: Optional[list[bytes]] = None) -> bytes: ...
+    @overload
+    def find_text(self, obj_id: ObjIdLike, needle: str, heads: Optional[list[bytes]] = None, cursors: Literal[False] = False) -> list[int]: ...
+    @overload
+    def find_text(self, obj_id: ObjIdLike, needle: str, heads: Optional[list[bytes]] = None, *, cursors: Literal[True]) -> list[tuple[int, bytes]]: ...
+    def get_cursor_position(self, obj_id: ObjIdLike, cursor: bytes, heads: Optional[list[bytes]] = None) -> int: ...
+    def spans(self, obj_id: ObjIdLike, heads: Optional[list[bytes]] = None) -> list[Span]: ...
+    def to_py(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None) -> Thing: ...
+    @property
+    def root(self) -> MapProxy: ...
+    def __getitem__(self, key: str) -> Item: ...
+    def __contains__(self, key: object) -> bool: ...
+    def __iter__(self) -> Range[str]: ...
+    def __setitem__(self, key: str, value: Thing) -> None: ...
+    def __delitem__(self, key: str) -> None: ...
+    def to_json(self, obj_id: ObjIdLike = ROOT, heads: Optional[list[bytes]] = None, indent: Optional[int] = None,
+                counters: Literal["number", "object"] = "number",
+                timestamps: Literal["millis", "iso"] = "millis",
+                bytes: Literal["hex", "array"] = "hex") -> str: ...
     
-    def put(self, obj_id: bytes, prop: str | int, scalar_type: ScalarType, value: ScalarValue) -> None: ...
-    def put_object(self, obj_id: bytes, prop: str | int, obj_type: ObjType) -> bytes: ...
-    def insert(self, obj_id: bytes, idx: int, scalar_type: ScalarType, value: ScalarValue) -> None: ...
-    def insert_object(self, obj_id: bytes, idx: int, obj_type: ObjType) -> bytes: ...
-    def increment(self, obj_id: bytes, prop: str | int, amount: int) -> None: ...
-    def delete(self, obj_id: bytes, prop: str | int) -> None: ...
-    def mark(self, obj_id: bytes, start: int, end: int, name: str, scalar_type: ScalarType, value: ScalarValue, expand: ExpandMark) -> None: ...
-    def unmark(self, obj_id: bytes, start: int, end: int, name: str) -> None: ...
+    @overload
+    def put(self, obj_id: ObjIdLike, prop: PropPath, value_type: ScalarType, value: ScalarValue) -> None: ...
+    @overload
+    def put(self, prop: PropPath, value_type: ScalarType, value: ScalarValue) -> None: ...
+    @overload
+    def put(self, obj_id: ObjIdLike, prop: PropPath, value: ScalarValue, *, strict: bool = False) -> None: ...
+    @overload
+    def put(self, prop: PropPath, value: ScalarValue, *, strict: bool = False) -> None: ...
+    @overload
+    def put_object(self, obj_id: ObjIdLike, prop: PropPath, value: ObjType | NestedValue) -> ObjId: ...
+    @overload
+    def put_object(self, prop: PropPath, value: ObjType | NestedValue) -> ObjId: ...
+    def insert(self, obj_id: ObjIdLike, idx: int, scalar_type: ScalarType, value: ScalarValue) -> None: ...
+    def insert_object(self, obj_id: ObjIdLike, idx: int, value: ObjType | NestedValue) -> ObjId: ...
+    @overload
+    def increment(self, obj_id: ObjIdLike, prop: PropPath, value: int) -> None: ...
+    @overload
+    def increment(self, prop: PropPath, value: int) -> None: ...
+    def put_many(self, obj_id: ObjIdLike, items: list[tuple[str | int, ScalarType, ScalarValue]]) -> None: ...
+    def insert_many(self, obj_id: ObjIdLike, index: int, values: list[tuple[ScalarType, ScalarValue]]) -> None: ...
+    def splice(self, obj_id: ObjIdLike, pos: int, delete_count: int, values: list[tuple[ScalarType, ScalarValue]]) -> None: ...
+    def splice_text(self, obj_id: ObjIdLike, pos: int, delete_count: int, text: str) -> None: ...
+    @overload
+    def delete(self, obj_id: ObjIdLike, prop: PropPath) -> None: ...
+    @overload
+    def delete(self, prop: PropPath) -> None: ...
+    def mark(self, obj_id: ObjIdLike, start: int, end: int, name: str, scalar_type: ScalarType, value: ScalarValue, expand: Expand = "after") -> None: ...
+    def unmark(self, obj_id: ObjIdLike, start: int, end: int, name: str, expand: Expand = "after") -> None: ...
+    def update_text(self, obj_id: ObjIdLike, new_value: str, base_heads: Optional[list[bytes]] = None) -> None: ...
+    def split_block(self, obj_id: ObjIdLike, index: int) -> ObjId: ...
+    def join_block(self, obj_id: ObjIdLike, index: int) -> None: ...
+    def replace_block(self, obj_id: ObjIdLike, index: int) -> ObjId: ...
+    def update_spans(self, obj_id: ObjIdLike, spans: list[Span], default_expand: Optional[Expand] = None, per_mark_expands: Optional[dict[str, Expand]] = None) -> None: ...
+
+# Values accepted by put_object and insert_object. Dicts and lists are imported recursively, a
+# `(ScalarType, value)` tuple fixes the type of a scalar and `(ObjType.Text, "...")` creates a text
+# object. Other scalars have their type inferred.
+NestedValue = ObjType | tuple[ObjType, NestedValue] | tuple[ScalarType, ScalarValue] | dict[str, NestedValue] | list[NestedValue] | ScalarValue
 
 class Mark:
     start: int
@@ -60,13 +332,104 @@ class Mark:
     name: str
     value: tuple[ScalarType, ScalarValue]
 
+class TextSpan:
+    text: str
+    marks: dict[str, tuple[ScalarType, ScalarValue]]
+    def __init__(self, text: str, marks: Optional[dict[str, tuple[ScalarType, ScalarValue]]] = None) -> None: ...
+
+class BlockSpan:
+    value: dict[str, Thing]
+    def __init__(self, value: dict[str, Thing]) -> None: ...
+
+Span = TextSpan | BlockSpan
+
+_T = TypeVar("_T")
+
+class Range(Generic[_T]):
+    def __iter__(self) -> Range[_T]: ...
+    def __next__(self) -> _T: ...
+
+# What indexing a document or proxy returns: nested objects are proxies, scalars plain values.
+Item = MapProxy | ListProxy | TextProxy | ScalarValue
+
+class MapProxy:
+    obj_id: ObjId
+    def __getitem__(self, key: str) -> Item: ...
+    def __setitem__(self, key: str, value: Thing | Item) -> None: ...
+    def __delitem__(self, key: str) -> None: ...
+    def __len__(self) -> int: ...
+    def __contains__(self, key: object) -> bool: ...
+    def __iter__(self) -> Range[str]: ...
+    def keys(self) -> Range[str]: ...
+    def values(self) -> list[Item]: ...
+    def items(self) -> list[tuple[str, Item]]: ...
+    def get(self, key: str, default: Optional[Item] = None) -> Optional[Item]: ...
+    def pop(self, key: str, default: Thing = ...) -> Thing: ...
+    def popitem(self) -> tuple[str, Thing]: ...
+    def clear(self) -> None: ...
+    def update(self, other: Mapping[str, Thing | Item] | Iterable[tuple[str, Thing | Item]] = ..., **kwargs: Thing | Item) -> None: ...
+    def setdefault(self, key: str, default: Thing = None) -> Item: ...
+    def to_py(self) -> Thing: ...
+
+class ListProxy:
+    obj_id: ObjId
+    @overload
+    def __getitem__(self, index: int) -> Item: ...
+    @overload
+    def __getitem__(self, index: slice) -> list[Item]: ...
+    @overload
+    def __setitem__(self, index: int, value: Thing | Item) -> None: ...
+    @overload
+    def __setitem__(self, index: slice, value: Iterable[Thing | Item]) -> None: ...
+    def __delitem__(self, index: int | slice) -> None: ...
+    def __len__(self) -> int: ...
+    def __contains__(self, value: object) -> bool: ...
+    def __reversed__(self) -> Iterator[Item]: ...
+    def __iadd__(self, values: Iterable[Thing | Item]) -> ListProxy: ...
+    def index(self, value: object, start: int = 0, stop: Optional[int] = None) -> int: ...
+    def count(self, value: object) -> int: ...
+    def insert(self, index: int, value: Thing | Item) -> None: ...
+    def append(self, value: Thing | Item) -> None: ...
+    def extend(self, values: Iterable[Thing | Item]) -> None: ...
+    def pop(self, index: int = -1) -> Thing: ...
+    def remove(self, value: object) -> None: ...
+    def reverse(self) -> None: ...
+    def clear(self) -> None: ...
+    def to_py(self) -> Thing: ...
+
+class TextProxy:
+    obj_id: ObjId
+    def __getitem__(self, index: int | slice) -> str: ...
+    def __setitem__(self, index: int | slice, value: str) -> None: ...
+    def __delitem__(self, index: int | slice) -> None: ...
+    def __len__(self) -> int: ...
+    def __contains__(self, value: str) -> bool: ...
+    def __reversed__(self) -> Iterator[str]: ...
+    def index(self, value: str, start: int = 0, stop: Optional[int] = None) -> int: ...
+    def count(self, value: str) -> int: ...
+    def to_py(self) -> str: ...
+
 class SyncState:
     def __init__(self) -> None: ...
+    shared_heads: list[bytes]
+    their_heads: Optional[list[bytes]]
+    sent_hashes: list[bytes]
+    def in_sync(self, doc: Document) -> bool: ...
+    def encode(self) -> bytes: ...
+    @staticmethod
+    def decode(data: bytes) -> SyncState: ...
 
 class Message:
     @staticmethod
     def decode(data: bytes) -> Message: ...
     def encode(self) -> bytes: ...
+    heads: list[bytes]
+    need: list[bytes]
+    have: list[tuple[list[bytes], bytes]]
+    num_changes: int
+    num_chunks: int
+    changes_size: int
+    is_compressed: bool
 
 class Change:
     actor_id: bytes
@@ -82,8 +445,78 @@ class Change:
     raw_bytes: bytes
     extra_bytes: bytes
     bytes: bytes
+    def ops(self) -> list[ChangeOp]: ...
+    @staticmethod
+    def from_bytes(data: bytes) -> Change: ...
+    def verify(self, hash: Optional[bytes] = None) -> bool: ...
+
+class ChangeOp:
+    id: str
+    action: Literal["make_map", "make_list", "make_text", "make_table", "put", "delete", "increment", "mark_begin", "mark_end"]
+    obj: ObjId
+    key: Optional[str]
+    elem: Optional[str]
+    insert: bool
+    value: Optional[tuple[ScalarType, ScalarValue]]
+    mark_name: Optional[str]
+    expand: bool
+    pred: list[str]
+
+class Patch:
+    obj: ObjId
+    path: list[tuple[ObjId, str | int]]
+    action: PatchAction
+
+class PutMap:
+    key: str
+    value: tuple[Value, ObjId]
+    conflict: bool
+
+class PutSeq:
+    index: int
+    value: tuple[Value, ObjId]
+    conflict: bool
+
+class Insert:
+    index: int
+    values: list[tuple[Value, ObjId]]
+    # Deprecated: always None, and warns when read.
+    marks: None
+
+class SpliceText:
+    index: int
+    value: str
+    marks: Optional[dict[str, tuple[ScalarType, ScalarValue]]]
+
+class Increment:
+    prop: str | int
+    value: int
 
-class Patch: ...
+class Conflict:
+    prop: str | int
+
+class DeleteMap:
+    key: str
+
+class DeleteSeq:
+    index: int
+    length: int
+
+class Marks:
+    marks: list[Mark]
+
+class Stats:
+    num_changes: int
+    num_ops: int
+    num_objects: int
+    num_actors: int
+    save_size: Optional[int]
+
+class Attribution:
+    added: list[tuple[int, int]]
+    deleted: list[tuple[int, str]]
+
+PatchAction = PutMap | PutSeq | Insert | SpliceText | Increment | Conflict | DeleteMap | DeleteSeq | Marks
 
 class ObjType(Enum):
     Map: ObjType
@@ -108,6 +541,21 @@ class ExpandMark(Enum):
     Both: ExpandMark
     Neither: ExpandMark
 
-ROOT: bytes
+Expand = ExpandMark | Literal["before", "after", "both", "none"]
+
+class TextEncoding(Enum):
+    UnicodeCodePoint: TextEncoding
+    Utf8CodeUnit: TextEncoding
+    Utf16CodeUnit: TextEncoding
+    GraphemeCluster: TextEncoding
+
+class TimestampMode(Enum):
+    Naive: TimestampMode
+    Utc: TimestampMode
+    Millis: TimestampMode
+
+class TypeMismatchError(Exception): ...
+
+ROOT: ObjId
 
 def random_actor_id() -> bytes: ...
@@ -1,5 +1,5 @@
 import automerge.core as core
-from datetime import datetime
+from datetime import date, datetime
 from typing import Iterator, cast, Mapping, Sequence, MutableMapping, MutableSequence, overload, Iterable, List, Union, Tuple, Optional
 from contextlib import contextmanager
 
@@ -23,10 +23,10 @@ ProxyThing = Union[core.ScalarValue, Mapping[str, 'ProxyThing'], Sequence['Proxy
 
 class ReadProxy:
     _doc: core.Document
-    _obj_id: bytes
+    _obj_id: core.ObjId
     _heads: Union[List[bytes], None]
 
-    def __init__(self, doc: core.Document, obj_id: bytes, heads: Optional[List[bytes]]):
+    def __init__(self, doc: core.Document, obj_id: core.ObjId, heads: Optional[List[bytes]]):
         self._doc = doc
         self._obj_id = obj_id
         self._heads = heads
@@ -35,9 +35,9 @@ class ReadProxy:
         return self._doc.length(self._obj_id, self._heads)
 
     def to_py(self) -> core.Thing:
-        return core.extract(self._doc, self._obj_id)
+        return self._doc.to_py(self._obj_id, self._heads)
 
-    def _maybe_wrap(self, x: Tuple[core.Value, bytes]) -> 'MapReadProxy | ListReadProxy | core.ScalarValue':
+    def _maybe_wrap(self, x: Tuple[core.Value, core.ObjId]) -> 'MapReadProxy | ListReadProxy | core.ScalarValue':
         value, obj_id = x
         if isinstance(value, core.ObjType):
             if value == core.ObjType.List:
@@ -70,10 +70,10 @@ class ListReadProxy(ReadProxy, Sequence[ProxyThing]):
     
 class WriteProxy:
     _tx: core.Transaction
-    _obj_id: bytes
+    _obj_id: core.ObjId
     _heads: Optional[List[bytes]]
     
-    def __init__(self, tx: core.Transaction, obj_id: bytes, heads: Optional[List[bytes]]) -> None:
+    def __init__(self, tx: core.Transaction, obj_id: core.ObjId, heads: Optional[List[bytes]]) -> None:
         self._tx = tx
         self._obj_id = obj_id
         self._heads = heads
@@ -227,15 +227,17 @@ class ListWriteProxy(WriteProxy, MutableSequence[MutableProxyThing]):
 def _infer_scalar_type(value: core.ScalarValue) -> core.ScalarType:
     if isinstance(value, str):
         return core.ScalarType.Str
-    elif isinstance(value, bytes):
+    elif isinstance(value, (bytes, bytearray, memoryview)):
         return core.ScalarType.Bytes
+    elif isinstance(value, core.Counter):
+        return core.ScalarType.Counter
     elif isinstance(value, int):
         return core.ScalarType.Int
     elif isinstance(value, float):
         return core.ScalarType.F64
     elif isinstance(value, bool):
         return core.ScalarType.Boolean
-    elif isinstance(value, datetime):
+    elif isinstance(value, (datetime, date)):
         return core.ScalarType.Timestamp
     elif value is None:
         return core.ScalarType.Null
@@ -1,5 +1,8 @@
+import array
+import random
+from datetime import date, datetime, timezone
 import pytest
-from automerge.core import Document, ROOT, ObjType, ScalarType, extract
+from automerge.core import Counter, Document, ObjId, ROOT, ObjType, ScalarType, TextEncoding, TimestampMode, TypeMismatchError, extract
 
 def test_basic() -> None:
     doc = Document()
@@ -40,9 +43,9 @@ def test_keys() -> None:
         tx.insert(list_id, 0, ScalarType.Str, "one")
         tx.insert(list_id, 1, ScalarType.Boolean, True)
 
-    assert doc.keys(ROOT) == ['list', 'map']
-    assert doc.keys(map_id) == ['foo', 'hello']
-    list_keys = doc.keys(list_id)
+    assert list(doc.keys(ROOT)) == ['list', 'map']
+    assert list(doc.keys(map_id)) == ['foo', 'hello']
+    list_keys = list(doc.keys(list_id))
     assert len(list_keys) == 2
 
 def test_values() -> None:
@@ -101,3 +104,1018 @@ def test_text_heads() -> None:
     assert doc1.text(text_id, [a_change.hash]) == 'hi'
     assert doc1.text(text_id, [b_change.hash]) == 'ho'
     assert doc1.text(text_id, [a_change.hash, b_change.hash]) == 'hoi'
+
+def test_counter() -> None:
+    doc = Document()
+
+    with doc.transaction() as tx:
+        tx.put(ROOT, "count", ScalarType.Counter, 1)
+        list_id = tx.put_object(ROOT, "list", ObjType.List)
+        tx.insert(list_id, 0, ScalarType.Counter, 10)
+
+    with doc.transaction() as tx:
+        tx.increment(ROOT, "count", 2)
+        tx.increment(list_id, 0, -3)
+
+    x = doc.get(ROOT, "count")
+    assert x is not None
+    value, _ = x
+    assert value == (ScalarType.Counter, 3)
+    assert next(doc.values(list_id))[0] == (ScalarType.Counter, 7)
+    assert extract(doc) == {'count': 3, 'list': [7]}
+
+def test_counter_class() -> None:
+    doc = Document()
+
+    with doc.transaction() as tx:
+        tx.put("count", ScalarType.Counter, 1)
+        tx.increment("count", 4)
+
+    x = doc.get("count")
+    assert x is not None
+    (scalar_type, counter), _ = x
+    assert scalar_type == ScalarType.Counter
+    assert isinstance(counter, Counter)
+    assert counter.value == 5
+    assert int(counter) == 5
+    assert counter == Counter(5)
+    assert repr(counter) == "Counter(5)"
+    assert isinstance(doc.to_py()["count"], Counter)
+
+    with doc.transaction() as tx:
+        tx.put("copy", counter)
+        tx.put("typed", ScalarType.Counter, counter)
+        tx.put_object("map", {"nested": Counter(2)})
+        tx.increment("copy", 1)
+        tx.increment(("map", "nested"), 1)
+
+    assert extract(doc) == {'count': 5, 'copy': 6, 'typed': 5, 'map': {'nested': 3}}
+
+def test_put_inferred() -> None:
+    doc = Document()
+
+    with doc.transaction() as tx:
+        tx.put("flag", True)
+        tx.put("int", 5)
+        tx.put("uint", 2**64 - 1)
+        tx.put("float", 1.0)
+        tx.put("str", "hi")
+        tx.put("bytes", b"\x01")
+        tx.put("none", None)
+        tx.put("strict", 7, strict=True)
+
+    def scalar_type(key: str) -> ScalarType:
+        x = doc.get(key)
+        assert x is not None
+        (t, _), _ = x
+        return t  # type: ignore
+
+    assert scalar_type("flag") == ScalarType.Boolean
+    assert scalar_type("int") == ScalarType.Int
+    assert scalar_type("uint") == ScalarType.Uint
+    assert scalar_type("float") == ScalarType.F64
+    assert scalar_type("str") == ScalarType.Str
+    assert scalar_type("bytes") == ScalarType.Bytes
+    assert scalar_type("none") == ScalarType.Null
+    assert scalar_type("strict") == ScalarType.Int
+
+    class Flag(int):
+        pass
+
+    with doc.transaction() as tx:
+        tx.put("sub", Flag(3))
+        with pytest.raises(TypeError):
+            tx.put("sub", Flag(3), strict=True)
+        with pytest.raises(TypeError):
+            tx.put("uint", 2**64 - 1, strict=True)
+        with pytest.raises(Exception):
+            tx.put("bad", object())
+    assert scalar_type("sub") == ScalarType.Int
+
+def test_timestamps() -> None:
+    doc = Document(timestamps=TimestampMode.Utc)
+    aware = datetime(2024, 5, 6, 7, 8, 9, tzinfo=timezone.utc)
+
+    with doc.transaction() as tx:
+        tx.put("aware", ScalarType.Timestamp, aware)
+        tx.put("millis", ScalarType.Timestamp, 1_000)
+        tx.put("day", ScalarType.Timestamp, date(2024, 5, 6))
+        tx.put_object("nested", {"day": date(1970, 1, 2)})
+
+    assert doc.timestamps == TimestampMode.Utc
+    assert doc.to_py() == {
+        "aware": aware,
+        "millis": datetime(1970, 1, 1, 0, 0, 1, tzinfo=timezone.utc),
+        "day": datetime(2024, 5, 6, tzinfo=timezone.utc),
+        "nested": {"day": datetime(1970, 1, 2, tzinfo=timezone.utc)},
+    }
+
+    doc.timestamps = TimestampMode.Millis
+    assert doc.get("aware")[0] == (ScalarType.Timestamp, int(aware.timestamp() * 1000))  # type: ignore
+    assert doc.get(("nested", "day"))[0] == (ScalarType.Timestamp, 86_400_000)  # type: ignore
+    assert Document.load(doc.save(), timestamps=TimestampMode.Millis).to_py()["millis"] == 1_000
+    assert doc.fork().timestamps == TimestampMode.Millis
+
+    doc.timestamps = TimestampMode.Naive
+    value = doc.to_py()["aware"]
+    assert value.tzinfo is None
+    assert value == datetime.fromtimestamp(aware.timestamp())
+
+    with pytest.raises(TypeError):
+        with doc.transaction() as tx:
+            tx.put("bad", ScalarType.Timestamp, "2024-05-06")
+
+    # Timestamps past the range of datetime raise when read as one, except in a tagged pair.
+    with doc.transaction() as tx:
+        tx.put("far", ScalarType.Timestamp, 10**16)
+    assert doc.get("far")[0] == (ScalarType.Timestamp, 10**16)  # type: ignore
+    with pytest.raises((OverflowError, ValueError)):
+        doc.to_py()
+    doc.timestamps = TimestampMode.Millis
+    assert doc.to_py()["far"] == 10**16
+
+def test_buffer_bytes() -> None:
+    doc = Document()
+    data = array.array("H", [1, 2, 3])
+
+    with doc.transaction() as tx:
+        tx.put("bytearray", ScalarType.Bytes, bytearray(b"abc"))
+        tx.put("memoryview", ScalarType.Bytes, memoryview(b"abcdef")[::2])
+        tx.put("array", ScalarType.Bytes, data)
+        tx.put("inferred", bytearray(b"xyz"))
+        list_id = tx.put_object("list", [memoryview(b"q")])
+        tx.insert(list_id, 1, ScalarType.Bytes, bytearray(b"r"))
+        with pytest.raises(TypeError):
+            tx.put("bad", ScalarType.Bytes, "abc")
+
+    assert doc.get_bytes(ROOT, "bytearray") == b"abc"
+    assert doc.get_bytes(ROOT, "memoryview") == b"ace"
+    assert doc.get_bytes(ROOT, "array") == data.tobytes()
+    assert doc.get_bytes(ROOT, "inferred") == b"xyz"
+    assert doc.get_bytes(list_id, 0) == b"q"
+    assert doc.get_bytes(list_id, 1) == b"r"
+
+def test_get_bytes_zero_copy() -> None:
+    doc = Document()
+    data = bytes(range(256)) * 16
+
+    with doc.transaction() as tx:
+        tx.put("blob", ScalarType.Bytes, data)
+        tx.put("text", ScalarType.Str, "x")
+        view = tx.get_bytes(ROOT, "blob", zero_copy=True)
+        assert view is not None and view.tobytes() == data
+
+    first = doc.get_bytes(ROOT, "blob", zero_copy=True)
+    second = doc.get_bytes(ROOT, "blob", zero_copy=True)
+    assert first is not None and second is not None
+    assert first.readonly
+    assert bytes(first) == data
+    assert second == first
+    assert doc.get_bytes(ROOT, "missing", zero_copy=True) is None
+    with pytest.raises(TypeMismatchError):
+        doc.get_bytes(ROOT, "text", zero_copy=True)
+    with pytest.raises(TypeError):
+        first[0] = 1  # type: ignore
+
+    with doc.transaction() as tx:
+        tx.put("blob", ScalarType.Bytes, b"new")
+    assert bytes(first) == data
+    assert doc.get_bytes(ROOT, "blob", zero_copy=True) == b"new"
+
+def test_unknown_scalar() -> None:
+    doc = Document()
+
+    with doc.transaction() as tx:
+        tx.put(ROOT, "unknown", ScalarType.Unknown, (10, b'\x01\x02'))
+
+    loaded = Document.load(doc.save())
+    x = loaded.get(ROOT, "unknown")
+    assert x is not None
+    value, _ = x
+    assert value == (ScalarType.Unknown, (10, b'\x01\x02'))
+
+    with pytest.raises(Exception):
+        with doc.transaction() as tx:
+            tx.put(ROOT, "unknown", ScalarType.Unknown, (6, b'\x01\x02'))
+
+def test_get_all() -> None:
+    doc1 = Document(actor_id=b'A')
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "key", ScalarType.Str, "base")
+    heads = doc1.get_heads()
+    doc2 = doc1.fork()
+    doc2.set_actor(b'B')
+
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "key", ScalarType.Str, "one")
+    with doc2.transaction() as tx:
+        tx.put(ROOT, "key", ScalarType.Int, 2)
+    doc1.merge(doc2)
+
+    values = [v for v, _ in doc1.get_all(ROOT, "key")]
+    assert sorted(values, key=repr) == sorted([(ScalarType.Str, "one"), (ScalarType.Int, 2)], key=repr)
+    x = doc1.get(ROOT, "key")
+    assert x is not None
+    assert x[0] in values
+    assert [v for v, _ in doc1.get_all(ROOT, "key", heads)] == [(ScalarType.Str, "base")]
+    assert doc1.get_all(ROOT, "missing") == []
+
+def test_fork_actor_id() -> None:
+    doc = Document(actor_id=b'A')
+    with doc.transaction() as tx:
+        tx.put(ROOT, "key", ScalarType.Int, 1)
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "key", ScalarType.Int, 2)
+
+    assert doc.fork().get_actor() != doc.get_actor()
+    assert doc.fork(actor_id=b'B').get_actor() == b'B'
+
+    forked = doc.fork_at(heads, actor_id=b'C')
+    assert forked.get_actor() == b'C'
+    assert forked.get_heads() == heads
+    assert doc.fork_at(heads).get_actor() not in (b'A', b'C')
+
+def test_text_encoding() -> None:
+    assert Document().text_encoding == TextEncoding.UnicodeCodePoint
+
+    doc = Document(text_encoding=TextEncoding.Utf16CodeUnit)
+    assert doc.text_encoding == TextEncoding.Utf16CodeUnit
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "\U0001F600!")
+        # The emoji is two UTF-16 code units, so the "!" is at index 2.
+        tx.splice_text(text, 2, 1, "?")
+        tx.mark(text, 0, 2, "bold", ScalarType.Boolean, True)
+    assert doc.text(text) == "\U0001F600?"
+    assert doc.length(text) == 3
+    assert [(m.start, m.end) for m in doc.marks(text)] == [(0, 2)]
+
+    utf8 = Document.load(doc.save(), text_encoding=TextEncoding.Utf8CodeUnit)
+    assert utf8.length(text) == 5
+    assert utf8.fork().text_encoding == TextEncoding.Utf8CodeUnit
+    assert Document.load(doc.save()).length(text) == 2
+
+def test_update_text() -> None:
+    doc1 = Document(actor_id=b'A')
+    with doc1.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.update_text(text, "hello world")
+    assert doc1.text(text) == "hello world"
+
+    doc2 = doc1.fork()
+    doc2.set_actor(b'B')
+    with doc1.transaction() as tx:
+        tx.update_text(text, "hello there world")
+    with doc2.transaction() as tx:
+        tx.update_text(text, "hello world!")
+
+    # Only the differing parts were spliced, so concurrent edits merge cleanly.
+    doc1.merge(doc2)
+    assert doc1.text(text) == "hello there world!"
+
+    # Texts too different for a minimal diff are replaced between the common prefix and suffix.
+    rng = random.Random(0)
+    old = "<" + "".join(rng.choice("ab") for _ in range(510)) + ">"
+    new = "<" + "".join(rng.choice("cd") for _ in range(510)) + ">"
+    with doc1.transaction() as tx:
+        tx.update_text(text, old)
+    with doc1.transaction() as tx:
+        tx.update_text(text, new)
+    assert doc1.text(text) == new
+
+def test_update_text_from_base() -> None:
+    doc = Document(actor_id=b'A')
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "the quick brown fox")
+    base = doc.get_heads()
+    # Someone edits the document while a copy of the text is edited elsewhere.
+    with doc.transaction() as tx:
+        tx.splice_text(text, 4, 5, "slow")
+        tx.splice_text(text, 18, 0, "!")
+    assert doc.text(text) == "the slow brown fox!"
+
+    with doc.transaction() as tx:
+        tx.update_text(text, "a quick brown dog", base_heads=base)
+    assert doc.text(text) == "a slow brown dog!"
+
+    other = Document()
+    with other.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    with doc.transaction() as tx:
+        with pytest.raises(Exception, match="unknown heads"):
+            tx.update_text(text, "help", other.get_heads())
+    assert doc.text(text) == "a slow brown dog!"
+
+    # With no changes since the base it is the same as a plain update.
+    rng = random.Random(0)
+    for _ in range(50):
+        old = "".join(rng.choice("abc ") for _ in range(rng.randrange(12)))
+        new = "".join(rng.choice("abc ") for _ in range(rng.randrange(12)))
+        doc = Document()
+        with doc.transaction() as tx:
+            text = tx.put_object(ROOT, "text", ObjType.Text)
+            tx.splice_text(text, 0, 0, old)
+        base = doc.get_heads()
+        with doc.transaction() as tx:
+            tx.update_text(text, new, base)
+        assert doc.text(text) == new
+
+def test_nested_put_object() -> None:
+    doc = Document()
+
+    with doc.transaction() as tx:
+        obj = tx.put_object(ROOT, "obj", {
+            "a": [1, {"b": True}],
+            "c": None,
+            "count": (ScalarType.Counter, 5),
+            "text": (ObjType.Text, "hello"),
+            "empty": ObjType.List,
+        })
+        list_id = tx.put_object(ROOT, "list", [])
+        tx.insert_object(list_id, 0, {"x": 1.5})
+
+    assert extract(doc) == {
+        'obj': {'a': [1, {'b': True}], 'c': None, 'count': 5, 'text': 'hello', 'empty': []},
+        'list': [{'x': 1.5}],
+    }
+    x = doc.get(obj, "count")
+    assert x is not None
+    assert x[0] == (ScalarType.Counter, 5)
+    x = doc.get(obj, "text")
+    assert x is not None
+    assert x[0] == ObjType.Text
+
+    with pytest.raises(Exception):
+        with doc.transaction() as tx:
+            tx.put_object(ROOT, "scalar", "not an object")
+
+def test_to_py() -> None:
+    doc = Document()
+
+    with doc.transaction() as tx:
+        tx.put_object(ROOT, "obj", {"a": [1, {"b": True}], "text": (ObjType.Text, "hi")})
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        obj = tx.put_object(ROOT, "obj", {"a": []})
+        assert tx.to_py(obj) == {"a": []}
+
+    assert doc.to_py() == {"obj": {"a": []}}
+    assert doc.to_py(obj) == {"a": []}
+    assert doc.to_py(heads=heads) == {"obj": {"a": [1, {"b": True}], "text": "hi"}}
+
+def test_save_incremental() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    saved = doc.save()
+    assert doc.save_incremental() == b""
+
+    with doc.transaction() as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+    incremental = doc.save_incremental()
+    assert incremental != b""
+    assert doc.save_incremental() == b""
+
+    loaded = Document.load(saved)
+    loaded.load_incremental(incremental)
+    assert loaded.get_heads() == doc.get_heads()
+    assert Document.load(saved + incremental).to_py() == {"a": 1, "b": 2}
+
+def test_save_incremental_from_fork() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    doc.save()
+
+    # A fork, like a document built by from_py, has never been saved, so its first incremental
+    # save holds its whole history.
+    fork = doc.fork()
+    with fork.transaction() as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+    fresh = Document()
+    fresh.load_incremental(fork.save_incremental())
+    assert fresh.to_py() == {"a": 1, "b": 2}
+
+    fresh = Document()
+    fresh.load_incremental(Document.from_py({"c": 3}).save_incremental())
+    assert fresh.to_py() == {"c": 3}
+
+def test_save_and_merge_from_threads() -> None:
+    from concurrent.futures import ThreadPoolExecutor
+
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "x" * 10_000)
+
+    def work(i: int) -> bytes:
+        other = doc.fork()
+        with other.transaction() as tx:
+            tx.put(ROOT, f"key{i}", ScalarType.Int, i)
+        doc.merge(other)
+        return doc.save()
+
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        saves = list(pool.map(work, range(8)))
+
+    assert all(Document.load(saved).get(ROOT, "text") is not None for saved in saves)
+    assert all(doc.get(ROOT, f"key{i}") is not None for i in range(8))
+
+def test_merge_both_ways_from_threads() -> None:
+    from concurrent.futures import ThreadPoolExecutor
+
+    a, b = Document(), Document()
+    with pytest.raises(Exception, match="itself"):
+        a.merge(a)
+    for doc, name in ((a, "a"), (b, "b")):
+        with doc.transaction() as tx:
+            tx.put(ROOT, name, ScalarType.Int, 1)
+
+    def work(i: int) -> None:
+        (src, dst) = (a, b) if i % 2 else (b, a)
+        dst.merge(src)
+
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        list(pool.map(work, range(64)))
+
+    assert a.to_py() == b.to_py() == {"a": 1, "b": 1}
+
+def test_stats() -> None:
+    doc = Document()
+    stats = doc.stats()
+    assert (stats.num_changes, stats.num_ops, stats.num_objects, stats.num_actors) == (0, 0, 1, 0)
+
+    with doc.transaction() as tx:
+        todos = tx.put_object(ROOT, "todos", ObjType.List)
+        item = tx.insert_object(todos, 0, ObjType.Map)
+        tx.put(item, "title", ScalarType.Str, "write stats")
+        tx.put(ROOT, "count", ScalarType.Int, 1)
+    stats = doc.stats()
+    assert stats.num_changes == 1
+    assert stats.num_ops == 4
+    assert stats.num_objects == 3
+    assert stats.num_actors == 1
+    assert stats.save_size is None
+    assert doc.stats(save_size=True).save_size == len(doc.save())
+
+    with doc.transaction() as tx:
+        with pytest.raises(Exception):
+            doc.stats()
+
+def test_explicit_commit_and_rollback() -> None:
+    doc = Document()
+    tx = doc.transaction()
+    tx.put(ROOT, "a", ScalarType.Int, 1)
+    hash = tx.commit()
+    assert hash is not None
+    assert doc.get_heads() == [hash]
+    with pytest.raises(Exception):
+        tx.commit()
+
+    assert doc.transaction().commit() is None
+
+    tx = doc.transaction()
+    tx.put(ROOT, "b", ScalarType.Int, 2)
+    tx.rollback()
+    assert doc.get_heads() == [hash]
+    assert extract(doc) == {"a": 1}
+
+    # Leaving a with block after an explicit commit is fine.
+    with doc.transaction() as tx:
+        tx.put(ROOT, "c", ScalarType.Int, 3)
+        hash = tx.commit()
+    assert doc.get_heads() == [hash]
+
+def test_stale_transaction() -> None:
+    doc = Document()
+    tx1 = doc.transaction(message="one")
+    root1 = tx1.root
+    tx1.put(ROOT, "a", ScalarType.Int, 1)
+    tx1.commit()
+
+    tx2 = doc.transaction(message="two")
+    tx2.put(ROOT, "b", ScalarType.Int, 2)
+    with pytest.raises(Exception, match="no longer active"):
+        tx1.put(ROOT, "c", ScalarType.Int, 3)
+    with pytest.raises(Exception, match="no longer active"):
+        root1["c"] = 3
+    with pytest.raises(Exception, match="no longer active"):
+        tx1.commit()
+    with pytest.raises(Exception, match="no longer active"):
+        tx1.rollback()
+    hash = tx2.commit()
+    assert hash is not None
+    assert doc.get_change_by_hash(hash).message == "two"  # type: ignore
+
+    # Leaving a with block must not commit a transaction started inside it.
+    with pytest.raises(Exception, match="no longer active"):
+        with doc.transaction() as tx:
+            tx.commit()
+            tx3 = doc.transaction()
+            tx3.put(ROOT, "d", ScalarType.Int, 4)
+    tx3.rollback()
+    assert extract(doc) == {"a": 1, "b": 2}
+
+def test_parents_and_path() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        todos = tx.put_object(ROOT, "todos", [{"title": "a"}, {"title": (ObjType.Text, "b")}])
+        title = tx.get(todos, 1)
+        assert title is not None
+        todo = title[1]
+        text = tx.get(todo, "title")
+        assert text is not None
+        text_id = text[1]
+
+    assert doc.parents(text_id) == [(todo, "title"), (todos, 1), (ROOT, "todos")]
+    assert doc.path(text_id) == ["todos", 1, "title"]
+    assert doc.path(ROOT) == []
+
+def test_lookup() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put_object(ROOT, "todos", [{"title": "a"}, {"title": "b", "a/b": 1}])
+    todos = doc.get(ROOT, "todos")
+    assert todos is not None
+
+    assert doc.lookup(("todos", 1, "title")) == ((ScalarType.Str, "b"), doc.lookup("/todos/1/title")[1]) # type: ignore[index]
+    assert doc.lookup("/todos/1/title") == doc.lookup(["todos", 1, "title"])
+    assert doc.lookup("todos") == todos
+    assert doc.lookup("/todos/1/a~1b") is not None
+    assert doc.lookup("/todos/5/title") is None
+    assert doc.lookup("/todos/0/title/more") is None
+    assert doc.lookup("") == (ObjType.Map, ROOT)
+
+    # Negative indices count back from the end, as in Python.
+    assert doc.lookup(("todos", -1, "title")) == doc.lookup(("todos", 1, "title"))
+    assert doc.lookup(("todos", -3)) is None
+    with pytest.raises(TypeError):
+        doc.lookup(("todos", 1.5))
+    with pytest.raises(TypeError):
+        doc.get_all(ROOT, 1.5) # type: ignore[arg-type]
+
+def test_map_range() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put_object(ROOT, "m", {f"k{i:03}": i for i in range(200)})
+    m = doc.lookup("/m")
+    assert m is not None
+    m_id = m[1]
+    heads = doc.get_heads()
+
+    keys = [k for k, _, _ in doc.map_range(m_id)]
+    assert keys == [f"k{i:03}" for i in range(200)]
+
+    items = list(doc.map_range(m_id, "k010", "k013"))
+    assert [(k, v) for k, v, _ in items] == [("k010", (ScalarType.Int, 10)), ("k011", (ScalarType.Int, 11)), ("k012", (ScalarType.Int, 12))]
+
+    with doc.transaction() as tx:
+        tx.delete(m_id, "k000")
+    assert len(list(doc.map_range(m_id))) == 199
+    assert len(list(doc.map_range(m_id, heads=heads))) == 200
+
+def test_list_range() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        l = tx.put_object(ROOT, "l", list(range(100)))
+        tx.insert_object(l, 100, {"nested": True})
+
+    values = [(i, v) for i, v, _ in doc.list_range(l, 98)]
+    assert values[:2] == [(98, (ScalarType.Int, 98)), (99, (ScalarType.Int, 99))]
+    assert values[2] == (100, ObjType.Map)
+    assert [i for i, _, _ in doc.list_range(l, 10, 13)] == [10, 11, 12]
+    assert len(list(doc.list_range(l))) == 101
+
+def test_list_range_edited_while_iterating() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        l = tx.put_object(ROOT, "l", list(range(200)))
+
+    seen = []
+    for i, (value, _) in enumerate(doc.values(l)):
+        seen.append(value[1])
+        if i == 0:
+            # Shift everything after the first chunk, and delete the element it ended on.
+            with doc.transaction() as tx:
+                tx.delete(l, 63)
+                tx.delete(l, 0)
+                tx.insert(l, 0, ScalarType.Int, -1)
+                tx.insert(l, 0, ScalarType.Int, -2)
+    assert seen == list(range(200))
+
+    keys = []
+    for key in doc.keys(l):
+        keys.append(key)
+        if len(keys) == 64:
+            with doc.transaction() as tx:
+                tx.delete(l, 0)
+    assert len(keys) == len(set(keys)) == 200
+
+    # Deleting the end of the list, including where the first chunk ended, stops the iteration.
+    seen = []
+    for i, (value, _) in enumerate(doc.values(l)):
+        seen.append(value[1])
+        if i == 0:
+            with doc.transaction() as tx:
+                while tx.length(l) > 40:
+                    tx.delete(l, 40)
+    assert len(seen) == 64
+
+def test_text_range() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello world")
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.splice_text(text, 0, 5, "goodbye")
+        assert tx.text_range(text, 0, 4) == "good"
+
+    assert doc.text_range(text, 8, 100) == "world"
+    assert doc.text_range(text, 3, 3) == ""
+    assert doc.text_range(text, 0, 5, heads) == "hello"
+    assert doc.view_at(heads).text_range(text, 6, 11) == "world"
+
+    utf16 = Document(text_encoding=TextEncoding.Utf16CodeUnit)
+    with utf16.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "a\U0001F600b")
+    assert utf16.text_range(text, 1, 3) == "\U0001F600"
+    assert utf16.text_range(text, 3, 4) == "b"
+
+def test_find_text() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "abcabc aaaa")
+    heads = doc.get_heads()
+    assert doc.find_text(text, "bc") == [1, 4]
+    assert doc.find_text(text, "aa") == [7, 9]
+    assert doc.find_text(text, "zz") == []
+    with pytest.raises(Exception):
+        doc.find_text(text, "")
+
+    matches = doc.find_text(text, "bc", cursors=True)
+    with doc.transaction() as tx:
+        tx.splice_text(text, 0, 0, ">> ")
+        assert tx.find_text(text, "bc") == [4, 7]
+    assert [doc.get_cursor_position(text, c) for _, c in matches] == [4, 7]
+    assert doc.find_text(text, "bc", heads) == [1, 4]
+
+    utf16 = Document(text_encoding=TextEncoding.Utf16CodeUnit)
+    with utf16.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "\U0001F600x\U0001F600x")
+    assert utf16.find_text(text, "x") == [2, 5]
+
+def test_values_range() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        l = tx.put_object(ROOT, "l", list(range(1000)))
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.delete(l, 0)
+        assert [v for v, _ in tx.values_range(l, 0, 2)] == [(ScalarType.Int, 1), (ScalarType.Int, 2)]
+
+    assert [v for v, _ in doc.values_range(l, 500, 503)] == [(ScalarType.Int, i) for i in range(501, 504)]
+    assert [v for v, _ in doc.values_range(l, 0, 1, heads)] == [(ScalarType.Int, 0)]
+    assert [v for v, _ in doc.view_at(heads).values_range(l, 998, 2000)] == [(ScalarType.Int, 998), (ScalarType.Int, 999)]
+    assert list(doc.values_range(l, 5, 5)) == []
+
+def test_negative_indices() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        l = tx.put_object(ROOT, "l", ["a", "b", "c"])
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello")
+    assert doc.get(l, -1) == ((ScalarType.Str, "c"), doc.get(l, 2)[1])  # type: ignore
+    assert doc.get(l, -3) is not None
+    assert doc.get(l, -4) is None
+    assert doc.get_all(l, -4) == []
+
+    with doc.transaction() as tx:
+        tx.insert(l, -1, ScalarType.Str, "x")
+        tx.delete(l, -1)
+        tx.splice_text(text, -1, 1, "!")
+        with pytest.raises(IndexError):
+            tx.delete(l, 3)
+        with pytest.raises(IndexError):
+            tx.insert(l, -5, ScalarType.Str, "y")
+        with pytest.raises(IndexError):
+            tx.splice_text(text, 6, 0, "?")
+    assert doc.to_py() == {"l": ["a", "b", "x"], "text": "hell!"}
+
+def test_typed_getters() -> None:
+    doc = Document.from_py({
+        "title": "hello",
+        "count": 3,
+        "done": False,
+        "data": b"\x00\x01",
+        "config": {"flags": ["a"]},
+    })
+    assert doc.get_str(ROOT, "title") == "hello"
+    assert doc.get_int(ROOT, "count") == 3
+    assert doc.get_bool(ROOT, "done") is False
+    assert doc.get_bytes(ROOT, "data") == b"\x00\x01"
+    config = doc.get_map(ROOT, "config")
+    assert config is not None
+    flags = doc.get_list(config, "flags")
+    assert flags is not None and doc.get_str(flags, -1) == "a"
+    assert doc.get_str(ROOT, "missing") is None
+
+    with pytest.raises(TypeMismatchError, match="expected str at count, found int"):
+        doc.get_str(ROOT, "count")
+    with pytest.raises(TypeMismatchError):
+        doc.get_list(ROOT, "config")
+
+    with doc.transaction() as tx:
+        tx.put(ROOT, "count", ScalarType.Str, "three")
+        assert tx.get_str(ROOT, "count") == "three"
+        with pytest.raises(TypeMismatchError):
+            tx.get_int(ROOT, "count")
+
+def test_get_or_and_has() -> None:
+    doc = Document.from_py({"title": "hello", "config": {}, "list": [1, 2]})
+    heads = doc.get_heads()
+    assert doc.get_or(ROOT, "title", "untitled") == "hello"
+    assert doc.get_or(ROOT, "missing", "untitled") == "untitled"
+    assert doc.get_or(ROOT, "config", None) == doc.get(ROOT, "config")[1]  # type: ignore
+    list_id = doc.get_list(ROOT, "list")
+    assert list_id is not None
+    assert doc.get_or(list_id, -1, 0) == 2
+    assert doc.get_or(list_id, 5, 0) == 0
+
+    assert doc.has(ROOT, "title")
+    assert not doc.has(ROOT, "missing")
+    assert not doc.has(list_id, -3)
+    with doc.transaction() as tx:
+        tx.delete(ROOT, "title")
+        assert not tx.has(ROOT, "title")
+        assert tx.get_or(ROOT, "title", "gone") == "gone"
+    assert doc.has(ROOT, "title", heads)
+
+def test_root_default() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put("title", ScalarType.Str, "hello")
+        tx.put("count", ScalarType.Counter, 1)
+        tx.increment("count", 2)
+        todos = tx.put_object("todos", ["a"])
+        tx.put_object("gone", {})
+        tx.delete("gone")
+        assert tx.get("title") == tx.get(ROOT, "title")
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.put("title", ScalarType.Str, "bye")
+
+    assert doc.get("title") == ((ScalarType.Str, "bye"), doc.get(ROOT, "title")[1])  # type: ignore
+    assert doc.get("title", heads)[0] == (ScalarType.Str, "hello")  # type: ignore
+    assert doc.get("title", heads=heads)[0] == (ScalarType.Str, "hello")  # type: ignore
+    assert doc.get(todos, 0) is not None
+    assert len(doc.get_all("count")) == 1
+    assert list(doc.keys()) == ["count", "title", "todos"]
+    assert len(list(doc.values())) == doc.length() == 3
+    assert [k for k, _, _ in doc.map_range(start_key="t")] == ["title", "todos"]
+    assert doc.view_at(heads).get("title")[0] == (ScalarType.Str, "hello")  # type: ignore
+    assert list(doc.view_at(heads).keys()) == ["count", "title", "todos"]
+
+    # The typed getters, get_or and has take the same optional obj_id.
+    with doc.transaction() as tx:
+        tx.put("flag", ScalarType.Boolean, True)
+        tx.put("n", ScalarType.Int, 7)
+        tx.put("data", ScalarType.Bytes, b"xy")
+        tx.put_object("config", {})
+        assert tx.get_str("title") == "bye"
+        assert tx.get_bool("flag", heads=None) is True
+        assert tx.has("config") and not tx.has("missing")
+        assert tx.get_or("missing", None) is None
+    assert doc.get_str("title") == "bye"
+    assert doc.get_str("title", heads) == "hello"
+    assert doc.get_str(prop="title", heads=heads) == "hello"
+    assert doc.get_int("n") == 7
+    assert doc.get_bool("flag") is True
+    assert doc.get_bytes("data") == b"xy"
+    assert bytes(doc.get_bytes("data", zero_copy=True)) == b"xy"  # type: ignore[arg-type]
+    assert doc.get_map("config") is not None
+    assert doc.get_list("todos") == todos
+    assert doc.get_or("missing", 5) == 5
+    assert doc.get_or("title", 5, heads) == "hello"
+    assert doc.get_or(ROOT, "title", default=5) == "bye"
+    assert doc.has("flag") and not doc.has("flag", heads)
+    assert doc.has(todos, -1)
+    with pytest.raises(TypeError, match="default"):
+        doc.get_or("title")  # type: ignore
+    with pytest.raises(TypeError):
+        doc.get_str()  # type: ignore
+
+    with pytest.raises(TypeError):
+        doc.get()  # type: ignore
+    with pytest.raises(TypeError):
+        doc.get("title", heads, heads=heads)  # type: ignore
+
+def test_path_props() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(("settings", "theme", "color"), ScalarType.Str, "red")
+        todos = tx.put_object(("settings", "todos"), [{"done": False}])
+        tx.put(todos, (0, "done"), ScalarType.Boolean, True)
+        tx.put(("settings", "count"), ScalarType.Counter, 1)
+        tx.increment(("settings", "count"), 2)
+        with pytest.raises(KeyError):
+            tx.delete(("missing", "key"))
+        with pytest.raises(Exception):
+            tx.put(("settings", "theme", "color", "x"), ScalarType.Str, "y")
+    assert doc.to_py() == {"settings": {"theme": {"color": "red"}, "todos": [{"done": True}], "count": 3}}
+
+    assert doc.get(("settings", "theme", "color"))[0] == (ScalarType.Str, "red")  # type: ignore
+    assert doc.get(("settings", "todos", -1, "done"))[0] == (ScalarType.Boolean, True)  # type: ignore
+    assert doc.get(("settings", "missing", "color")) is None
+    assert doc.get(("settings", "theme", "color", "x")) is None
+    assert len(doc.get_all(("settings", "count"))) == 1
+
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.delete(("settings", "theme"))
+    assert doc.get(("settings", "theme")) is None
+    assert doc.get(("settings", "theme", "color"), heads) is not None
+
+def test_query() -> None:
+    doc = Document.from_py({
+        "todos": [
+            {"title": "write", "done": True, "tags": ["a"]},
+            {"title": "test", "done": False, "priority": 2},
+            {"title": "ship", "done": False, "priority": 1.5},
+        ],
+        "owner": {"name": "sam"},
+    })
+    heads = doc.get_heads()
+
+    titles = doc.query("$.todos[*].title")
+    assert [(p, v) for p, v, _ in titles] == [
+        (["todos", 0, "title"], (ScalarType.Str, "write")),
+        (["todos", 1, "title"], (ScalarType.Str, "test")),
+        (["todos", 2, "title"], (ScalarType.Str, "ship")),
+    ]
+    assert [v for _, v, _ in doc.query("$.todos[?(@.done == false)].title")] == [(ScalarType.Str, "test"), (ScalarType.Str, "ship")]
+    assert [v for _, v, _ in doc.query("$['todos'][?(@.done != false)].title")] == [(ScalarType.Str, "write")]
+    assert [p for p, _, _ in doc.query("$.todos[?(@.priority == 2)]")] == [["todos", 1]]
+    assert [p for p, _, _ in doc.query("$.todos[?(@.tags[0] == 'a')]")] == [["todos", 0]]
+    assert [v for _, v, _ in doc.query("$.todos[-1].priority")] == [(ScalarType.F64, 1.5)]
+    assert [p for p, _, _ in doc.query("$.*.name")] == [["owner", "name"]]
+    owner = doc.query("$.owner")
+    assert owner[0][1] == ObjType.Map and owner[0][2] == doc.get("owner")[1]  # type: ignore
+    assert doc.query("$.missing[*]") == []
+
+    with doc.transaction() as tx:
+        tx.put(("todos", 1, "done"), ScalarType.Boolean, True)
+        assert len(tx.query("$.todos[?(@.done == true)]")) == 2
+    assert len(doc.query("$.todos[?(@.done == true)]", heads)) == 1
+    assert len(doc.view_at(heads).query("$.todos[?(@.done == true)]")) == 1
+
+    for bad in ["todos", "$.todos[", "$.todos[?(@.done = true)]", "$.todos[?(@[*] == 1)]"]:
+        with pytest.raises(Exception, match="invalid query"):
+            doc.query(bad)
+
+def test_keyword_args() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, prop="a", value_type=ScalarType.Int, value=1)
+        tx.put(obj_id=ROOT, prop="b", value=None)
+        tx.put("c", value=2, strict=True)
+        tx.put("d", ScalarType.Uint, value=3)
+        tx.put_object(obj_id=ROOT, prop="counts", value={"n": Counter(0)})
+        tx.increment(("counts", "n"), value=5)
+        tx.put_object(prop="gone", value=[])
+        tx.delete(obj_id=ROOT, prop="gone")
+        assert tx.get(obj_id=ROOT, prop="a")[0] == (ScalarType.Int, 1)  # type: ignore
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.put("a", 10)
+
+    assert doc.get(obj_id=ROOT, prop="a")[0] == (ScalarType.Int, 10)  # type: ignore
+    assert doc.get(ROOT, "a", heads=heads)[0] == (ScalarType.Int, 1)  # type: ignore
+    assert doc.get(prop="a", heads=heads)[0] == (ScalarType.Int, 1)  # type: ignore
+    assert doc.get_all(obj_id=ROOT, prop="b")[0][0] == (ScalarType.Null, None)
+    assert doc.view_at(heads).get(prop="a")[0] == (ScalarType.Int, 1)  # type: ignore
+    assert doc.to_py() == {"a": 10, "b": None, "c": 2, "d": 3, "counts": {"n": Counter(5)}}
+
+    with pytest.raises(TypeError, match="prop"):
+        doc.get(obj_id=ROOT)  # type: ignore
+    with pytest.raises(TypeError, match="value"):
+        with doc.transaction() as tx:
+            tx.put(ROOT, "e")  # type: ignore
+
+def test_lazy_keys_and_values() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        m = tx.put_object(ROOT, "m", {str(i): i for i in range(100)})
+        l = tx.put_object(ROOT, "l", list(range(100)))
+
+    assert len(set(doc.keys(m))) == 100
+    assert len(set(doc.keys(l))) == 100
+    assert [v for (_, v), _ in doc.values(l)] == list(range(100))
+
+    keys = doc.keys(m)
+    assert next(keys) == "0"
+    assert next(keys) == "1"
+
+def test_splice() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        l = tx.put_object(ROOT, "l", [1, 2, 3, 4])
+        tx.splice(l, 1, 2, [(ScalarType.Str, "a"), (ScalarType.Boolean, True), (ScalarType.Int, 5)])
+        text = tx.put_object(ROOT, "text", (ObjType.Text, "hello world"))
+        tx.splice_text(text, 6, 5, "there")
+    assert extract(doc) == {"l": [1, "a", True, 5, 4], "text": "hello there"}
+    assert len(doc.get_changes([])) == 1
+
+def test_put_and_insert_many() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        m = tx.put_object(ROOT, "m", ObjType.Map)
+        tx.put_many(m, [("a", ScalarType.Int, 1), ("b", ScalarType.Str, "two")])
+        l = tx.put_object(ROOT, "l", ["x", "y"])
+        tx.insert_many(l, 1, [(ScalarType.Int, 1), (ScalarType.Int, 2)])
+        tx.put_many(l, [(0, ScalarType.Str, "z"), (-1, ScalarType.Int, 5)])
+        with pytest.raises(IndexError):
+            tx.put_many(l, [(1, ScalarType.Int, 0), (-5, ScalarType.Int, 0)])
+        with pytest.raises(TypeError):
+            tx.put_many(l, [(1.5, ScalarType.Int, 0)]) # type: ignore[list-item]
+    assert extract(doc) == {"m": {"a": 1, "b": "two"}, "l": ["z", 1, 2, 5]}
+
+def test_obj_id() -> None:
+    doc = Document(actor_id=b"\x01\x02")
+    with doc.transaction() as tx:
+        m = tx.put_object(ROOT, "m", ObjType.Map)
+
+    assert isinstance(m, ObjId)
+    assert m.counter == 1
+    assert m.actor == b"\x01\x02"
+    assert not m.is_root
+    assert ROOT.is_root and ROOT.counter is None and ROOT.actor is None
+    assert str(m) == "1@0102"
+    assert repr(m) == "ObjId('1@0102')"
+    assert str(ROOT) == "_root"
+
+    assert ObjId.from_str(str(m)) == m
+    assert ObjId.from_str("_root") == ROOT
+    assert ObjId(bytes(m)) == m
+    assert m != bytes(m)
+    assert m != ROOT
+    assert len({m, ObjId.from_str("1@0102"), ROOT}) == 2
+    assert {m: 1}[ObjId(bytes(m))] == 1
+
+    # Both the class and its bytes form are accepted as arguments.
+    assert doc.object_type(m) == doc.object_type(bytes(m)) == ObjType.Map
+    assert doc.object_type(ObjId.from_str("1@0102")) == ObjType.Map
+    with pytest.raises(Exception):
+        ObjId.from_str("nonsense")
+
+    # An object made by an actor which isn't first in the document's actor table.
+    other = doc.fork(actor_id=b"\x03\x04")
+    with other.transaction() as tx:
+        n = tx.put_object(ROOT, "n", ObjType.List)
+        tx.insert(n, 0, ScalarType.Int, 1)
+    doc.merge(other)
+    n_id = doc.lookup("/n")
+    assert n_id is not None
+    parsed = ObjId.from_str(str(n_id[1]))
+    assert parsed == n_id[1] == n
+    assert hash(parsed) == hash(n_id[1])
+    assert doc.object_type(parsed) == ObjType.List
+    (change,) = [c for c in doc.get_changes([]) if c.actor_id == b"\x03\x04"]
+    assert change.ops()[1].obj == n_id[1]
+
+def test_view_at() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "title", ScalarType.Str, "draft")
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello")
+        tx.put_object(ROOT, "tags", ["a"])
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "title", ScalarType.Str, "final")
+        tx.splice_text(text, 5, 0, " world")
+        tx.put(ROOT, "extra", ScalarType.Int, 1)
+
+    view = doc.view_at(heads)
+    assert view.heads == heads
+    assert view.get(ROOT, "title") == doc.get(ROOT, "title", heads)
+    assert view.text(text) == "hello"
+    assert view.length(text) == 5
+    assert list(view.keys(ROOT)) == ["tags", "text", "title"]
+    assert view.lookup("/title") == doc.lookup("/title", heads)
+    assert view.to_py() == {"tags": ["a"], "text": "hello", "title": "draft"}
+    assert view["tags"] == ["a"]
+    assert str(view["text"]) == "hello"
+    assert "extra" not in view
+    assert dict(view.root.items())["title"] == "draft"
+
+    # The live document is unaffected.
+    assert doc.text(text) == "hello world"
+
+    with pytest.raises(Exception):
+        doc.view_at([b"\x00" * 32])
@@ -0,0 +1,53 @@
+from automerge.core import Document, ROOT, ObjType, ScalarType, ExpandMark, TextSpan, BlockSpan
+
+def make_text(doc: Document, s: str) -> bytes:
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        for i, c in enumerate(s):
+            tx.insert(text, i, ScalarType.Str, c)
+    return text
+
+def test_split_and_join_block() -> None:
+    doc = Document()
+    text = make_text(doc, "helloworld")
+    with doc.transaction() as tx:
+        block = tx.split_block(text, 5)
+        tx.put(block, "type", ScalarType.Str, "paragraph")
+
+    spans = doc.spans(text)
+    assert len(spans) == 3
+    first, block_span, last = spans
+    assert isinstance(first, TextSpan) and first.text == "hello"
+    assert isinstance(block_span, BlockSpan) and block_span.value == {"type": "paragraph"}
+    assert isinstance(last, TextSpan) and last.text == "world"
+
+    with doc.transaction() as tx:
+        new_block = tx.replace_block(text, 5)
+        tx.put(new_block, "type", ScalarType.Str, "heading")
+    assert [s.value for s in doc.spans(text) if isinstance(s, BlockSpan)] == [{"type": "heading"}]
+
+    with doc.transaction() as tx:
+        tx.join_block(text, 5)
+    spans = doc.spans(text)
+    assert len(spans) == 1
+    assert isinstance(spans[0], TextSpan) and spans[0].text == "helloworld"
+
+def test_update_spans() -> None:
+    doc = Document()
+    text = make_text(doc, "")
+    with doc.transaction() as tx:
+        tx.update_spans(text, [
+            BlockSpan({"type": "heading", "parents": [], "attrs": {"level": 1}}),
+            TextSpan("Title"),
+            BlockSpan({"type": "paragraph", "parents": [], "attrs": {}}),
+            TextSpan("some "),
+            TextSpan("bold", {"bold": (ScalarType.Boolean, True)}),
+        ], default_expand=ExpandMark.Both, per_mark_expands={"bold": ExpandMark.Neither})
+
+    spans = doc.spans(text)
+    assert [type(s) for s in spans] == [BlockSpan, TextSpan, BlockSpan, TextSpan, TextSpan]
+    assert isinstance(spans[0], BlockSpan)
+    assert spans[0].value == {"type": "heading", "parents": [], "attrs": {"level": 1}}
+    assert isinstance(spans[4], TextSpan)
+    assert spans[4].text == "bold"
+    assert spans[4].marks == {"bold": (ScalarType.Boolean, True)}
@@ -1,6 +1,7 @@
-from datetime import datetime
+from datetime import datetime, timezone
 from typing import List, Optional, Tuple
-from automerge.core import Document, ROOT, ScalarType, ObjType
+import pytest
+from automerge.core import Change, Document, ROOT, ScalarType, ObjType
 
 def test_get_changes() -> None:
     doc = Document()
@@ -95,3 +96,174 @@ def test_multi_author_changes() -> None:
     # actor, but let's say we waited a while and we want to persist now.
     snapshots.append((doc.text(text), last_actor))
     assert snapshots == [('', None), ('hi', b'A'), ('hi yo', b'B'), ('hi yo 😊', b'A'), ('hi yo 👋 😊', b'B')]
+
+def test_apply_changes() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+    with doc.transaction() as tx:
+        tx.put(ROOT, "foo", ScalarType.Str, "bar")
+    first, second = doc.get_changes([])
+
+    other = Document()
+    other.apply_changes([first])
+    assert other.get_heads() == [first.hash]
+    other.apply_changes([second.bytes])
+    assert other.get_heads() == doc.get_heads()
+    assert other.to_py() == {"hello": "world", "foo": "bar"}
+
+def test_get_missing_deps() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+    with doc.transaction() as tx:
+        tx.put(ROOT, "foo", ScalarType.Str, "bar")
+    first, second = doc.get_changes([])
+
+    other = Document()
+    assert other.get_missing_deps() == []
+    assert other.get_missing_deps(doc.get_heads()) == doc.get_heads()
+
+    # The second change is queued until the first one arrives.
+    other.apply_changes([second])
+    assert other.get_missing_deps() == [first.hash]
+    other.apply_changes([first])
+    assert other.get_missing_deps() == []
+    assert other.get_heads() == doc.get_heads()
+
+def test_get_change_by_hash() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+    (head,) = doc.get_heads()
+    change = doc.get_change_by_hash(head)
+    assert change is not None
+    assert change.hash == head
+    assert doc.get_change_by_hash(b"\x00" * 32) is None
+
+def test_get_changes_added() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    other = doc.fork()
+    with other.transaction() as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+
+    added = doc.get_changes_added(other)
+    assert [c.hash for c in added] == other.get_heads()
+    assert other.get_changes_added(doc) == []
+    assert doc.get_changes_added(doc) == []
+
+def test_commit_options() -> None:
+    doc = Document()
+    when = datetime(2024, 5, 6, 7, 8, 9, tzinfo=timezone.utc)
+    with doc.transaction(message="first", timestamp=when) as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    change = doc.get_last_local_change()
+    assert change is not None
+    assert change.message == "first"
+    assert change.timestamp.timestamp() == when.timestamp()
+
+    tx = doc.transaction(message="ignored")
+    tx.put(ROOT, "b", ScalarType.Int, 2)
+    tx.commit(message="second")
+    change = doc.get_last_local_change()
+    assert change is not None
+    assert change.message == "second"
+
+def test_history() -> None:
+    doc = Document()
+    with doc.transaction(message="first") as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    other = doc.fork()
+    with doc.transaction(message="local") as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+    with other.transaction(message="remote") as tx:
+        tx.put(ROOT, "c", ScalarType.Int, 3)
+    doc.merge(other)
+
+    history = doc.history()
+    assert [c.message for c, _ in history][0] == "first"
+    assert sorted(c.message or "" for c, _ in history[1:]) == ["local", "remote"]
+    assert history[0][1] == [history[0][0].hash]
+    assert history[-1][1] == doc.get_heads()
+    # The heads after each change are those of the document with the changes so far.
+    assert len(history[1][1]) == 1 and len(history[2][1]) == 2
+    assert len(doc.to_py(heads=history[1][1])) == 2  # type: ignore
+
+    with_patches = doc.history(patches=True)
+    assert [(c.hash, heads) for c, heads, _ in with_patches] == [(c.hash, heads) for c, heads in history]
+    assert [len(patches) for _, _, patches in with_patches] == [1, 1, 1]
+
+def test_attribute() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello world")
+    baseline = doc.get_heads()
+    alice = doc.fork()
+    bob = doc.fork()
+    with alice.transaction() as tx:
+        tx.splice_text(text, 0, 5, "howdy")
+    with bob.transaction() as tx:
+        tx.splice_text(text, 11, 0, "!")
+    doc.merge(alice)
+    doc.merge(bob)
+    assert doc.text(text) == "howdy world!"
+
+    by_alice, by_bob = doc.attribute(text, baseline, [alice.get_heads(), bob.get_heads()])
+    assert by_alice.added == [(0, 5)]
+    assert by_alice.deleted == [(5, "hello")]
+    assert by_bob.added == [(11, 12)]
+    assert by_bob.deleted == []
+    assert repr(by_bob) == "Attribution(added=[(11, 12)], deleted=[])"
+
+    other = Document()
+    with other.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    with pytest.raises(Exception, match="unknown heads"):
+        doc.attribute(text, baseline, [other.get_heads()])
+    with pytest.raises(Exception, match="unknown heads"):
+        doc.attribute(text, other.get_heads(), [])
+
+def test_change_ops() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "title", ScalarType.Str, "hello")
+        todos = tx.put_object(ROOT, "todos", ObjType.List)
+        tx.insert(todos, 0, ScalarType.Int, 1)
+    with doc.transaction() as tx:
+        tx.put(ROOT, "title", ScalarType.Str, "goodbye")
+    first, second = doc.get_changes([])
+
+    ops = first.ops()
+    assert [op.action for op in ops] == ["put", "make_list", "put"]
+    assert (ops[0].obj, ops[0].key, ops[0].value) == (ROOT, "title", (ScalarType.Str, "hello"))
+    assert ops[1].id == str(todos)
+    assert ops[2].obj == todos
+    assert (ops[2].elem, ops[2].insert, ops[2].value) == ("_head", True, (ScalarType.Int, 1))
+
+    (op,) = second.ops()
+    assert op.pred == [ops[0].id]
+    assert repr(op).startswith(f"ChangeOp(id='{op.id}', action='put', obj=ObjId('_root'), key='title', ")
+
+def test_change_from_bytes() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Str, "hello " * 100)
+    change = doc.get_last_local_change()
+    assert change is not None
+
+    for data in [change.bytes, change.raw_bytes]:
+        parsed = Change.from_bytes(data)
+        assert parsed.hash == change.hash
+        assert parsed.verify()
+        assert parsed.verify(change.hash)
+    assert not change.verify(bytes(32))
+
+    # Corrupt the checksum in the header.
+    data = bytearray(change.raw_bytes)
+    data[4] ^= 0xff
+    assert not Change.from_bytes(bytes(data)).verify()
+    with pytest.raises(Exception):
+        Change.from_bytes(b"not a change")
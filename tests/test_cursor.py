@@ -0,0 +1,27 @@
+from automerge.core import Document, ROOT, ObjType, ScalarType
+
+def test_cursor() -> None:
+    doc = Document(actor_id=b'A')
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        for i, c in enumerate("hello"):
+            tx.insert(text, i, ScalarType.Str, c)
+    heads = doc.get_heads()
+
+    # Cursor pointing at the "l" at index 3.
+    cursor = doc.get_cursor(text, 3)
+    assert doc.get_cursor_position(text, cursor) == 3
+
+    doc2 = doc.fork()
+    doc2.set_actor(b'B')
+    with doc2.transaction() as tx:
+        tx.insert(text, 0, ScalarType.Str, ">")
+        assert tx.get_cursor_position(text, cursor) == 4
+    with doc.transaction() as tx:
+        tx.delete(text, 0)
+    doc.merge(doc2)
+
+    assert doc.text(text) == ">ello"
+    assert doc.get_cursor_position(text, cursor) == 3
+    assert doc.get_cursor_position(text, cursor, heads) == 3
+    assert doc.get_cursor(text, 3, heads) == cursor
@@ -0,0 +1,61 @@
+import json
+from datetime import datetime, timezone
+
+import pytest
+from automerge.core import Document, ROOT, ObjType, ScalarType
+
+def make_doc() -> Document:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "count", ScalarType.Counter, 3)
+        tx.put(ROOT, "when", ScalarType.Timestamp, datetime(2024, 1, 2, 3, 4, 5, tzinfo=timezone.utc))
+        tx.put(ROOT, "data", ScalarType.Bytes, b"\x01\xff")
+        tx.put_object(ROOT, "list", [1, "two", None, True])
+        tx.put_object(ROOT, "text", (ObjType.Text, "hello"))
+    return doc
+
+def test_to_json() -> None:
+    doc = make_doc()
+    assert json.loads(doc.to_json()) == {
+        "count": 3,
+        "when": 1704164645000,
+        "data": "01ff",
+        "list": [1, "two", None, True],
+        "text": "hello",
+    }
+
+def test_to_json_options() -> None:
+    doc = make_doc()
+    value = json.loads(doc.to_json(counters="object", timestamps="iso", bytes="array"))
+    assert value["count"] == {"counter": 3}
+    assert value["when"] == "2024-01-02T03:04:05.000Z"
+    assert value["data"] == [1, 255]
+
+    with pytest.raises(Exception):
+        doc.to_json(bytes="base64") # type: ignore[arg-type]
+
+def test_to_json_indent_and_heads() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        m = tx.put_object(ROOT, "m", {"a": 1})
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.put(m, "a", ScalarType.Int, 2)
+
+    assert doc.to_json(m) == '{"a":2}'
+    assert doc.to_json(m, heads) == '{"a":1}'
+    assert doc.to_json(m, indent=2) == '{\n  "a": 2\n}'
+
+def test_from_json() -> None:
+    doc = Document.from_json('{"a": 1, "b": [1.5, "x", null, {"c": false}], "big": 18446744073709551615}')
+    assert doc.to_py() == {"a": 1, "b": [1.5, "x", None, {"c": False}], "big": 18446744073709551615}
+    assert len(doc.get_heads()) == 1
+
+    with pytest.raises(Exception):
+        Document.from_json("[1, 2]")
+
+def test_from_py() -> None:
+    doc = Document.from_py({"n": 1, "items": ["a", "b"], "text": (ObjType.Text, "hi")}, actor_id=b"abc")
+    assert doc.get_actor() == b"abc"
+    assert doc.to_py() == {"n": 1, "items": ["a", "b"], "text": "hi"}
+    assert len(doc.get_heads()) == 1
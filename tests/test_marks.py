@@ -1,4 +1,5 @@
-from automerge.core import Document, ROOT, ObjType, ScalarType, ExpandMark, extract
+import pytest
+from automerge.core import Document, ROOT, ObjType, ScalarType, ExpandMark, TextSpan, extract
 
 def test_marks() -> None:
     doc = Document()
@@ -14,3 +15,60 @@ def test_marks() -> None:
     assert mark.start == 0
     assert mark.end == 1
     assert mark.value == (ScalarType.Boolean, True)
+
+def test_spans() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        for i, c in enumerate("hello world"):
+            tx.insert(text, i, ScalarType.Str, c)
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.mark(text, 0, 5, "bold", ScalarType.Boolean, True, ExpandMark.After)
+        tx.mark(text, 3, 8, "link", ScalarType.Str, "https://automerge.org", ExpandMark.Neither)
+
+    spans = [(s.text, s.marks) for s in doc.spans(text) if isinstance(s, TextSpan)]
+    assert spans == [
+        ("hel", {"bold": (ScalarType.Boolean, True)}),
+        ("lo", {"bold": (ScalarType.Boolean, True), "link": (ScalarType.Str, "https://automerge.org")}),
+        (" wo", {"link": (ScalarType.Str, "https://automerge.org")}),
+        ("rld", {}),
+    ]
+
+    old_spans = doc.spans(text, heads)
+    assert len(old_spans) == 1
+    assert isinstance(old_spans[0], TextSpan)
+    assert (old_spans[0].text, old_spans[0].marks) == ("hello world", {})
+
+def test_marks_at() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", (ObjType.Text, "hello world"))
+        tx.mark(text, 0, 5, "bold", ScalarType.Boolean, True, ExpandMark.After)
+        tx.mark(text, 3, 8, "link", ScalarType.Str, "https://automerge.org", ExpandMark.Neither)
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.mark(text, 0, 11, "italic", ScalarType.Boolean, True, ExpandMark.Neither)
+
+    assert doc.marks_at(text, 4, heads) == {
+        "bold": (ScalarType.Boolean, True),
+        "link": (ScalarType.Str, "https://automerge.org"),
+    }
+    assert doc.marks_at(text, 9, heads) == {}
+    assert doc.marks_at(text, 9) == {"italic": (ScalarType.Boolean, True)}
+
+def test_mark_expand_strings() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", (ObjType.Text, "hello"))
+        tx.mark(text, 0, 5, "bold", ScalarType.Boolean, True)
+        tx.mark(text, 0, 5, "link", ScalarType.Str, "x", "none")
+        tx.splice_text(text, 5, 0, "!")
+    # The default expand is "after", so bold grows to cover the new character but link does not.
+    assert doc.marks_at(text, 5) == {"bold": (ScalarType.Boolean, True)}
+
+    with doc.transaction() as tx:
+        tx.unmark(text, 0, 2, "bold", "both")
+        with pytest.raises(Exception):
+            tx.mark(text, 0, 1, "bold", ScalarType.Boolean, True, "sideways") # type: ignore[arg-type]
+    assert doc.marks_at(text, 0) == {"link": (ScalarType.Str, "x")}
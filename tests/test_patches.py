@@ -0,0 +1,191 @@
+import pytest
+from automerge.core import Document, Transaction, Patch, SyncState, ROOT, ObjType, ScalarType, PutMap, Insert, Increment, DeleteMap, DeleteSeq, Marks, ExpandMark
+
+def test_patch_actions() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        map_id = tx.put_object(ROOT, "map", ObjType.Map)
+        tx.put(map_id, "hello", ScalarType.Str, "world")
+        list_id = tx.put_object(ROOT, "list", ObjType.List)
+        tx.insert(list_id, 0, ScalarType.Boolean, True)
+        tx.put(ROOT, "count", ScalarType.Counter, 0)
+    heads = doc.get_heads()
+
+    patches = doc.diff([], heads)
+    actions = [p.action for p in patches]
+    assert all(isinstance(a, (PutMap, Insert)) for a in actions)
+
+    hello = next(p for p in patches if p.obj == map_id)
+    assert hello.path == [(ROOT, "map")]
+    assert isinstance(hello.action, PutMap)
+    assert hello.action.key == "hello"
+    assert hello.action.value[0] == (ScalarType.Str, "world")
+    assert hello.action.conflict == False
+    assert repr(hello.action) == f"PutMap(key='hello', value=((ScalarType.Str, 'world'), {hello.action.value[1]!r}), conflict=False)"
+    assert repr(hello).startswith(f"Patch(obj={map_id!r}, path=[(ObjId('_root'), 'map')], action=PutMap(")
+
+    insert = next(p for p in patches if p.obj == list_id)
+    assert isinstance(insert.action, Insert)
+    assert insert.action.index == 0
+    assert [v for v, _ in insert.action.values] == [(ScalarType.Boolean, True)]
+    with pytest.deprecated_call():
+        assert insert.action.marks is None
+
+    with doc.transaction() as tx:
+        tx.increment(ROOT, "count", 5)
+        tx.delete(map_id, "hello")
+        tx.delete(list_id, 0)
+    patches = doc.diff(heads, doc.get_heads())
+    actions = [p.action for p in patches]
+    increment = next(a for a in actions if isinstance(a, Increment))
+    assert increment.prop == "count"
+    assert increment.value == 5
+    delete_map = next(a for a in actions if isinstance(a, DeleteMap))
+    assert delete_map.key == "hello"
+    delete_seq = next(a for a in actions if isinstance(a, DeleteSeq))
+    assert (delete_seq.index, delete_seq.length) == (0, 1)
+
+def test_mark_patch() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.insert(text, 0, ScalarType.Str, "h")
+        tx.insert(text, 1, ScalarType.Str, "i")
+    heads = doc.get_heads()
+    with doc.transaction() as tx:
+        tx.mark(text, 0, 2, "bold", ScalarType.Boolean, True, ExpandMark.After)
+
+    patches = doc.diff(heads, doc.get_heads())
+    assert len(patches) == 1
+    action = patches[0].action
+    assert isinstance(action, Marks)
+    assert [(m.name, m.start, m.end, m.value) for m in action.marks] == [("bold", 0, 2, (ScalarType.Boolean, True))]
+
+def test_change_callback() -> None:
+    doc = Document()
+
+    def add(tx: Transaction) -> None:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+
+    hash, patches = doc.change(add, message="add hello")
+    assert doc.get_heads() == [hash]
+    change = doc.get_last_local_change()
+    assert change is not None and change.message == "add hello"
+    assert len(patches) == 1
+    assert isinstance(patches[0].action, PutMap)
+    assert patches[0].action.key == "hello"
+
+    def fail(tx: Transaction) -> None:
+        tx.put(ROOT, "oops", ScalarType.Int, 1)
+        raise ValueError("nope")
+
+    with pytest.raises(ValueError):
+        doc.change(fail)
+    assert doc.get_heads() == [hash]
+    assert list(doc.keys(ROOT)) == ["hello"]
+
+    assert doc.change(lambda tx: None) == (None, [])
+
+    # A callback may finish the transaction itself.
+    def commit(tx: Transaction) -> None:
+        tx.put(ROOT, "committed", ScalarType.Int, 1)
+        tx.commit()
+
+    hash, patches = doc.change(commit)
+    assert hash is None
+    assert [p.action.key for p in patches if isinstance(p.action, PutMap)] == ["committed"]
+
+    def rollback(tx: Transaction) -> None:
+        tx.rollback()
+        raise ValueError("rolled back")
+
+    with pytest.raises(ValueError, match="rolled back"):
+        doc.change(rollback)
+
+def test_on_patch() -> None:
+    doc = Document()
+    received: list[list[Patch]] = []
+    doc.on_patch(received.append)
+
+    # Local changes are not reported.
+    with doc.transaction() as tx:
+        tx.put(ROOT, "local", ScalarType.Int, 1)
+    assert received == []
+
+    other = doc.fork()
+    with other.transaction() as tx:
+        tx.put(ROOT, "remote", ScalarType.Int, 2)
+    doc.merge(other)
+    assert len(received) == 1
+    assert isinstance(received[0][0].action, PutMap)
+    assert received[0][0].action.key == "remote"
+
+    with other.transaction() as tx:
+        tx.put(ROOT, "applied", ScalarType.Int, 3)
+    doc.apply_changes(other.get_changes(doc.get_heads()))
+    assert len(received) == 2
+
+    # Merging again changes nothing, so there is nothing to report.
+    doc.merge(other)
+    assert len(received) == 2
+
+    doc.on_patch(None)
+    with other.transaction() as tx:
+        tx.put(ROOT, "unobserved", ScalarType.Int, 4)
+    doc.merge(other)
+    assert len(received) == 2
+
+def test_diff_incremental() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    patches = doc.diff_incremental()
+    assert [p.action.key for p in patches if isinstance(p.action, PutMap)] == ["a"]
+    assert doc.diff_incremental() == []
+
+    with doc.transaction() as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+        tx.delete(ROOT, "a")
+    actions = [p.action for p in doc.diff_incremental()]
+    assert len(actions) == 2
+    assert any(isinstance(a, PutMap) and a.key == "b" for a in actions)
+    assert any(isinstance(a, DeleteMap) and a.key == "a" for a in actions)
+
+def test_merge_return_patches() -> None:
+    doc = Document()
+    received: list[list[Patch]] = []
+    doc.on_patch(received.append)
+    other = doc.fork()
+    with other.transaction() as tx:
+        tx.put(ROOT, "remote", ScalarType.Int, 1)
+
+    heads, patches = doc.merge(other, return_patches=True)
+    assert heads == other.get_heads()
+    assert len(patches) == 1
+    assert isinstance(patches[0].action, PutMap)
+    assert patches[0].action.key == "remote"
+    assert len(received) == 1
+
+    assert doc.merge(other, return_patches=True) == (heads, [])
+    assert len(received) == 1
+
+def test_receive_sync_message_return_patches() -> None:
+    doc = Document()
+    other = Document()
+    with other.transaction() as tx:
+        tx.put(ROOT, "remote", ScalarType.Int, 1)
+    state, other_state = SyncState(), SyncState()
+
+    received: list[Patch] = []
+    while True:
+        message = other.generate_sync_message(other_state)
+        if message is None:
+            break
+        patches = doc.receive_sync_message(state, message, return_patches=True)
+        received.extend(patches)
+        reply = doc.generate_sync_message(state)
+        if reply is not None:
+            assert other.receive_sync_message(other_state, reply) is None
+    assert len(received) == 1
+    assert isinstance(received[0].action, PutMap)
+    assert received[0].action.key == "remote"
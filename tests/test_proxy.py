@@ -0,0 +1,148 @@
+from collections.abc import MutableMapping, MutableSequence
+
+import pytest
+from automerge.core import Document, ListProxy, MapProxy, ROOT, TextProxy, ObjType
+
+def make_doc() -> Document:
+    return Document.from_py({
+        "config": {"flags": ["a", "b"], "name": "test"},
+        "count": 3,
+        "notes": ObjType.Text,
+    })
+
+def test_read() -> None:
+    doc = make_doc()
+    flags = doc["config"]["flags"]
+    assert isinstance(doc.root, MapProxy)
+    assert isinstance(flags, ListProxy)
+    assert isinstance(doc.root, MutableMapping)
+    assert isinstance(flags, MutableSequence)
+    assert flags[0] == "a"
+    assert flags[-1] == "b"
+    assert list(flags) == ["a", "b"]
+    assert len(flags) == 2
+    assert "b" in flags
+    assert doc["count"] == 3
+    assert doc["config"]["name"] == "test"
+    assert doc["config"] == {"flags": ["a", "b"], "name": "test"}
+    assert "config" in doc and "missing" not in doc
+    assert sorted(doc) == ["config", "count", "notes"]
+    assert len(doc.root) == 3
+    assert doc.root.get("missing", 1) == 1
+    assert dict(doc["config"].items())["name"] == "test"
+    assert doc["config"].obj_id == doc.get(ROOT, "config")[1]  # type: ignore
+
+    with pytest.raises(KeyError):
+        doc["missing"]
+    with pytest.raises(IndexError):
+        flags[2]
+
+def test_write() -> None:
+    doc = make_doc()
+    with doc.transaction() as tx:
+        tx["count"] = 4
+        tx["config"]["flags"][0] = "x"
+        tx["config"]["flags"].append({"nested": [1]})
+        tx["config"]["flags"].insert(0, "first")
+        del tx["config"]["name"]
+        assert isinstance(tx["notes"], TextProxy)
+    assert doc.to_py() == {
+        "config": {"flags": ["first", "x", "b", {"nested": [1]}]},
+        "count": 4,
+        "notes": "",
+    }
+
+    with doc.transaction() as tx:
+        del tx["config"]["flags"][-1]
+        with pytest.raises(KeyError):
+            del tx["missing"]
+    assert doc["config"]["flags"] == ["first", "x", "b"]
+
+    # Proxies read from the document are read-only.
+    with pytest.raises(Exception):
+        doc["config"]["flags"][0] = "y"  # type: ignore
+
+def test_text() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello")
+        proxy = tx["text"]
+        assert isinstance(proxy, TextProxy)
+        proxy[0] = "j"
+        del proxy[-1]
+    proxy = doc["text"]
+    assert isinstance(proxy, TextProxy)
+    assert str(proxy) == "jell"
+    assert proxy == "jell"
+    assert len(proxy) == 4
+    assert proxy[1] == "e"
+    assert "ell" in proxy
+    assert list(proxy) == ["j", "e", "l", "l"]
+
+def test_mutable_mapping_methods() -> None:
+    doc = make_doc()
+    with doc.transaction() as tx:
+        config = tx["config"]
+        assert config.pop("name") == "test"
+        assert config.pop("name", None) is None
+        with pytest.raises(KeyError):
+            config.pop("name")
+        assert config.pop("flags") == ["a", "b"]
+        config.update({"a": 1}, b=[2])
+        config.update([("c", 3)])
+        config.update(tx["config"], d=4)
+        assert config.setdefault("a", 10) == 1
+        assert config.setdefault("e", 5) == 5
+        assert config.popitem() == ("a", 1)
+    assert doc["config"] == {"b": [2], "c": 3, "d": 4, "e": 5}
+
+    with doc.transaction() as tx:
+        tx["copy"] = tx["config"]
+        tx["config"].clear()
+    assert doc["config"] == {}
+    assert doc["copy"] == {"b": [2], "c": 3, "d": 4, "e": 5}
+
+def test_mutable_sequence_methods() -> None:
+    doc = Document.from_py({"l": [0, 1, 2, 3, 4, 5]})
+    assert doc["l"][1:3] == [1, 2]
+    assert doc["l"][::-2] == [5, 3, 1]
+    assert list(reversed(doc["l"])) == [5, 4, 3, 2, 1, 0]
+    assert doc["l"].index(3) == 3
+    assert doc["l"].count(3) == 1
+    with pytest.raises(ValueError):
+        doc["l"].index(3, 4)
+
+    with doc.transaction() as tx:
+        l = tx["l"]
+        l[1:3] = ["a", "b", "c"]
+        assert l == [0, "a", "b", "c", 3, 4, 5]
+        del l[::2]
+        assert l == ["a", "c", 4]
+        l[::2] = [1, {"x": 1}]
+        with pytest.raises(ValueError):
+            l[::2] = [1]
+        l.extend([7, 8])
+        l += [9]
+        assert l.pop() == 9
+        assert l.pop(2) == {"x": 1}
+        l.remove("c")
+        l.reverse()
+        assert l == [8, 7, 1]
+        l.clear()
+        assert len(l) == 0
+    assert doc["l"] == []
+
+def test_text_sequence_methods() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put_object(ROOT, "text", ObjType.Text)
+        text = tx["text"]
+        text[0:0] = "hello world"
+        text[0:5] = "HELLO"
+        del text[5:]
+        assert text[1:3] == "EL"
+    assert doc["text"][::-1] == "OLLEH"
+    assert "".join(reversed(doc["text"])) == "OLLEH"
+    assert doc["text"].index("L") == 2
+    assert doc["text"].count("L") == 2
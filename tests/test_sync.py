@@ -1,3 +1,4 @@
+import os
 from automerge.core import Document, ROOT, SyncState, Message, ScalarType, extract
 
 def test_sync() -> None:
@@ -34,3 +35,177 @@ def test_sync() -> None:
         doc1.receive_sync_message(p1_state_p2, msg)
         
     assert extract(doc2) == extract(doc1)
+
+def sync(doc1: Document, state1: SyncState, doc2: Document, state2: SyncState) -> None:
+    while True:
+        msg1 = doc1.generate_sync_message(state1)
+        if msg1:
+            doc2.receive_sync_message(state2, msg1)
+        msg2 = doc2.generate_sync_message(state2)
+        if msg2:
+            doc1.receive_sync_message(state1, msg2)
+        if not msg1 and not msg2:
+            break
+
+def test_sync_state_encode() -> None:
+    doc1 = Document()
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+    doc2 = Document()
+    state1, state2 = SyncState(), SyncState()
+    sync(doc1, state1, doc2, state2)
+
+    # Restore both sides' state as if the peers had restarted.
+    state1 = SyncState.decode(state1.encode())
+    state2 = SyncState.decode(state2.encode())
+
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "foo", ScalarType.Str, "bar")
+    sync(doc1, state1, doc2, state2)
+    assert extract(doc2) == {"hello": "world", "foo": "bar"}
+
+def test_sync_state_introspection() -> None:
+    doc1 = Document()
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+    doc2 = Document()
+    state1, state2 = SyncState(), SyncState()
+    assert state1.their_heads is None
+    assert state1.shared_heads == []
+    assert not state1.in_sync(doc1)
+
+    sync(doc1, state1, doc2, state2)
+    assert state1.in_sync(doc1)
+    assert state2.in_sync(doc2)
+    assert state1.shared_heads == doc1.get_heads()
+    assert state1.their_heads == doc1.get_heads()
+    assert isinstance(state1.sent_hashes, list)
+
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "foo", ScalarType.Str, "bar")
+    assert not state1.in_sync(doc1)
+
+def test_sync_many_peers_from_threads() -> None:
+    from concurrent.futures import ThreadPoolExecutor
+
+    server = Document()
+    with server.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+
+    def peer(i: int) -> Document:
+        doc = Document()
+        with doc.transaction() as tx:
+            tx.put(ROOT, f"peer{i}", ScalarType.Int, i)
+        sync(server, SyncState(), doc, SyncState())
+        return doc
+
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        peers = list(pool.map(peer, range(8)))
+
+    expected = {"hello": "world", **{f"peer{i}": i for i in range(8)}}
+    assert extract(server) == expected
+    assert all(extract(doc)["hello"] == "world" for doc in peers)
+
+def test_message_introspection() -> None:
+    doc1 = Document()
+    for i in range(5):
+        with doc1.transaction() as tx:
+            tx.put(ROOT, "hello", ScalarType.Int, i)
+    doc2 = Document()
+    state1, state2 = SyncState(), SyncState()
+
+    first = doc1.generate_sync_message(state1)
+    assert first is not None
+    assert first.heads == doc1.get_heads()
+    assert first.need == []
+    assert len(first.have) == 1 and first.have[0][0] == []
+    assert first.num_changes == first.num_chunks == first.changes_size == 0
+    doc2.receive_sync_message(state2, first)
+
+    reply = doc2.generate_sync_message(state2)
+    assert reply is not None
+    assert reply.heads == []
+    doc1.receive_sync_message(state1, reply)
+
+    changes = doc1.generate_sync_message(state1)
+    assert changes is not None
+    assert changes.num_changes == 5 and changes.changes_size > 0
+    assert 0 < changes.num_chunks <= 5
+    decoded = Message.decode(changes.encode())
+    assert (decoded.heads, decoded.num_changes, decoded.is_compressed) == (changes.heads, changes.num_changes, changes.is_compressed)
+
+def test_sync_with() -> None:
+    doc1 = Document()
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+    doc2 = Document()
+    with doc2.transaction() as tx:
+        tx.put(ROOT, "foo", ScalarType.Str, "bar")
+
+    rounds, messages = doc1.sync_with(doc2)
+    assert rounds > 0 and messages >= rounds
+    assert extract(doc1) == extract(doc2) == {"hello": "world", "foo": "bar"}
+    assert doc1.get_heads() == doc2.get_heads()
+
+    # Peers which are already in sync only exchange their heads.
+    rounds, messages = doc1.sync_with(doc2)
+    assert rounds == 1 and messages == 2
+    assert doc1.sync_with(doc1) == (0, 0)
+
+def test_sync_with_both_ways_from_threads() -> None:
+    from concurrent.futures import ThreadPoolExecutor
+
+    doc1, doc2 = Document(), Document()
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "hello", ScalarType.Str, "world")
+
+    def work(i: int) -> None:
+        if i % 2:
+            doc1.sync_with(doc2)
+        else:
+            doc2.sync_with(doc1)
+
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        list(pool.map(work, range(64)))
+
+    assert doc1.get_heads() == doc2.get_heads()
+
+def test_max_message_size() -> None:
+    doc1 = Document()
+    for i in range(20):
+        with doc1.transaction() as tx:
+            tx.put(ROOT, f"key{i}", ScalarType.Bytes, os.urandom(1000))
+    doc2 = Document()
+    state1, state2 = SyncState(), SyncState()
+
+    sizes = []
+    while True:
+        msg1 = doc1.generate_sync_message(state1, max_message_size=4000)
+        if msg1:
+            sizes.append(len(msg1.encode()))
+            doc2.receive_sync_message(state2, msg1)
+        msg2 = doc2.generate_sync_message(state2, max_message_size=4000)
+        if msg2:
+            doc1.receive_sync_message(state1, msg2)
+        if not msg1 and not msg2:
+            break
+    assert max(sizes) <= 4000
+    assert len(sizes) > 5
+    assert doc2.get_heads() == doc1.get_heads()
+    assert extract(doc2) == extract(doc1)
+
+def test_fingerprint() -> None:
+    doc1 = Document()
+    doc2 = Document()
+    assert doc1.fingerprint() == doc2.fingerprint()
+    assert len(doc1.fingerprint()) == 32
+
+    with doc1.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    with doc2.transaction() as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+    assert doc1.fingerprint() != doc2.fingerprint()
+
+    doc1.sync_with(doc2)
+    assert len(doc1.get_heads()) == 2
+    assert doc1.fingerprint() == doc2.fingerprint()
@@ -0,0 +1,133 @@
+from automerge.core import Document, ROOT, ObjType, ScalarType
+
+def test_undo_redo() -> None:
+    doc = Document()
+    assert not doc.can_undo()
+    assert doc.undo() is None
+
+    with doc.transaction() as tx:
+        tx.put(ROOT, "title", ScalarType.Str, "one")
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello")
+    with doc.transaction() as tx:
+        tx.put(ROOT, "title", ScalarType.Str, "two")
+        tx.splice_text(text, 5, 0, " world")
+        tx.put_object(ROOT, "todos", [{"done": False}])
+
+    assert doc.can_undo()
+    assert doc.undo() is not None
+    assert doc.to_py() == {"title": "one", "text": "hello"}
+    assert doc.can_redo()
+
+    assert doc.redo() is not None
+    assert doc.to_py() == {"title": "two", "text": "hello world", "todos": [{"done": False}]}
+    assert not doc.can_redo()
+
+    doc.undo()
+    doc.undo()
+    assert doc.to_py() == {}
+    assert doc.undo() is None
+
+def test_undo_restores_deleted_objects() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put_object(ROOT, "config", {"flags": ["a", "b"], "nested": {"x": 1}})
+    with doc.transaction() as tx:
+        tx.delete(ROOT, "config")
+    assert doc.to_py() == {}
+
+    doc.undo()
+    assert doc.to_py() == {"config": {"flags": ["a", "b"], "nested": {"x": 1}}}
+
+def test_undo_keeps_remote_changes() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello")
+    other = doc.fork()
+    with doc.transaction() as tx:
+        tx.splice_text(text, 5, 0, " world")
+    with other.transaction() as tx:
+        tx.splice_text(text, 0, 0, ">> ")
+        tx.put(ROOT, "remote", ScalarType.Int, 1)
+    doc.merge(other)
+    assert doc.text(text) == ">> hello world"
+
+    # Only the local edit is reverted; the remote changes are never on the undo stack.
+    doc.undo()
+    assert doc.text(text) == ">> hello"
+    assert doc.get(ROOT, "remote") is not None
+
+def test_new_change_clears_redo() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "a", ScalarType.Int, 1)
+    doc.undo()
+    assert doc.can_redo()
+    with doc.transaction() as tx:
+        tx.put(ROOT, "b", ScalarType.Int, 2)
+    assert not doc.can_redo()
+
+def test_undo_depth() -> None:
+    doc = Document()
+    assert doc.undo_depth == 100
+    doc.undo_depth = 2
+    for i in range(4):
+        with doc.transaction() as tx:
+            tx.put(ROOT, "n", ScalarType.Int, i)
+    assert doc.undo() is not None
+    assert doc.undo() is not None
+    assert doc.undo() is None
+    assert doc.to_py() == {"n": 1}
+
+    # Lowering the depth trims what can be redone too.
+    doc.undo_depth = 1
+    assert doc.redo() is not None
+    assert doc.redo() is None
+    assert doc.to_py() == {"n": 2}
+
+def test_undo_through_redone_text() -> None:
+    doc = Document()
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+    with doc.transaction() as tx:
+        tx.splice_text(text, 0, 0, "hello")
+    with doc.transaction() as tx:
+        tx.splice_text(text, 4, 1, "")
+    with doc.transaction() as tx:
+        tx.splice_text(text, 0, 1, "j")
+    assert doc.text(text) == "jell"
+
+    doc.undo()
+    assert doc.text(text) == "hell"
+    doc.undo()
+    assert doc.text(text) == "hello"
+    doc.redo()
+    assert doc.text(text) == "hell"
+    doc.undo()
+    assert doc.text(text) == "hello"
+    # The "o" was reinserted by an undo, but undoing the insertion still removes it.
+    doc.undo()
+    assert doc.text(text) == ""
+    doc.redo()
+    assert doc.text(text) == "hello"
+
+def test_undo_depth_keeps_needed_replacements() -> None:
+    doc = Document()
+    doc.undo_depth = 1
+    with doc.transaction() as tx:
+        text = tx.put_object(ROOT, "text", ObjType.Text)
+        tx.splice_text(text, 0, 0, "hello")
+    for _ in range(3):
+        with doc.transaction() as tx:
+            tx.splice_text(text, 4, 1, "")
+        doc.undo()
+        assert doc.text(text) == "hello"
+        doc.redo()
+        assert doc.text(text) == "hell"
+        doc.undo()
+        assert doc.text(text) == "hello"
+        with doc.transaction() as tx:
+            tx.splice_text(text, 0, 0, "x")
+        doc.undo()
+        assert doc.text(text) == "hello"